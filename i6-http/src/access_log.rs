@@ -0,0 +1,493 @@
+//! Access logs in the Common or Combined Log Format, or as JSON lines,
+//! written to stdout or to a file that is rotated by size.
+//!
+//! A request is logged once its response body has been sent or the client
+//! went away, so the size and duration cover the whole transfer.
+//!
+//! Filters cannot see the HTTP version of a request, so the request line of
+//! the Common Log Format always says `HTTP/1.1`, which is what browsers use
+//! for plain http.
+
+use futures_util::{Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::convert::Infallible;
+use std::error::Error;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use time::OffsetDateTime;
+use warp::filters::BoxedFilter;
+use warp::http::{header, HeaderMap, Method, StatusCode};
+use warp::hyper::body::{Body, Bytes};
+use warp::path::FullPath;
+use warp::reply::Response;
+use warp::{Filter, Rejection};
+
+use crate::auth;
+use crate::config::ServerConfig;
+use crate::filters;
+
+/// Rotated files kept next to the log file, as `access.log.1` and so on.
+const KEEP_ROTATED: usize = 5;
+
+#[derive(
+  Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+  /// `host ident user [time] "request" status bytes`.
+  Common,
+  /// The common format followed by `"referer" "user-agent"`.
+  #[default]
+  Combined,
+  /// One JSON object per line, which also has the duration.
+  Json,
+}
+
+impl FromStr for LogFormat {
+  type Err = String;
+
+  fn from_str(format: &str) -> Result<Self, Self::Err> {
+    match format.to_ascii_lowercase().as_str() {
+      "common" => Ok(LogFormat::Common),
+      "combined" => Ok(LogFormat::Combined),
+      "json" => Ok(LogFormat::Json),
+      _ => Err(format!(
+        "Invalid log format {:?}, expected common, combined or json",
+        format
+      )),
+    }
+  }
+}
+
+/// What is known about a request before it is answered.
+#[derive(Debug, Clone)]
+struct Request {
+  time: OffsetDateTime,
+  start: Instant,
+  remote: Option<SocketAddr>,
+  user: Option<String>,
+  method: Method,
+  /// Path and query, with tokens redacted.
+  target: String,
+  referer: Option<String>,
+  user_agent: Option<String>,
+}
+
+/// Escapes a value for a quoted field of the Common Log Format.
+fn quoted(value: Option<&str>) -> String {
+  let Some(value) = value else {
+    return "-".to_owned();
+  };
+
+  let mut escaped = String::with_capacity(value.len());
+  for c in value.chars() {
+    match c {
+      '"' => escaped.push_str("\\\""),
+      '\\' => escaped.push_str("\\\\"),
+      c if c.is_control() => escaped.push_str(&format!("\\x{:02x}", c as u32)),
+      c => escaped.push(c),
+    }
+  }
+
+  escaped
+}
+
+/// Formats `time` like `10/Oct/2000:13:55:36 +0000`.
+fn clf_time(time: OffsetDateTime) -> String {
+  let time = time.to_offset(time::UtcOffset::UTC);
+  format!(
+    "{:02}/{}/{}:{:02}:{:02}:{:02} +0000",
+    time.day(),
+    &time.month().to_string()[..3],
+    time.year(),
+    time.hour(),
+    time.minute(),
+    time.second()
+  )
+}
+
+fn format_line(
+  format: LogFormat,
+  request: &Request,
+  status: StatusCode,
+  bytes: u64,
+  duration: Duration,
+) -> String {
+  let host =
+    request.remote.map_or("-".to_owned(), |addr| addr.ip().to_string());
+
+  if format == LogFormat::Json {
+    let time = request.time.to_offset(time::UtcOffset::UTC);
+    let line = serde_json::json!({
+      "time": format!(
+        "{}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        time.year(),
+        u8::from(time.month()),
+        time.day(),
+        time.hour(),
+        time.minute(),
+        time.second(),
+        time.millisecond()
+      ),
+      "remote_addr": request.remote.map(|addr| addr.ip().to_string()),
+      "user": request.user,
+      "method": request.method.as_str(),
+      "target": request.target,
+      "status": status.as_u16(),
+      "bytes": bytes,
+      "duration_ms": duration.as_secs_f64() * 1000.0,
+      "referer": request.referer,
+      "user_agent": request.user_agent,
+    });
+    return line.to_string();
+  }
+
+  let mut line = format!(
+    "{} - {} [{}] \"{} {} HTTP/1.1\" {} {}",
+    host,
+    request.user.as_deref().map_or("-".to_owned(), |user| quoted(Some(user))),
+    clf_time(request.time),
+    request.method,
+    quoted(Some(&request.target)),
+    status.as_u16(),
+    if bytes == 0 { "-".to_owned() } else { bytes.to_string() },
+  );
+
+  if format == LogFormat::Combined {
+    line.push_str(&format!(
+      " \"{}\" \"{}\"",
+      quoted(request.referer.as_deref()),
+      quoted(request.user_agent.as_deref())
+    ));
+  }
+
+  line
+}
+
+/// A log file that is renamed to `<path>.1` once it reaches `max_size`.
+struct RotatingFile {
+  path: PathBuf,
+  max_size: u64,
+  file: File,
+  len: u64,
+}
+
+impl RotatingFile {
+  fn open(path: &Path, max_size: u64) -> std::io::Result<Self> {
+    if let Some(parent) = path.parent() {
+      std::fs::create_dir_all(parent)?;
+    }
+
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    let len = file.metadata()?.len();
+
+    Ok(Self { path: path.to_owned(), max_size, file, len })
+  }
+
+  fn rotated(&self, n: usize) -> PathBuf {
+    let mut path = self.path.clone().into_os_string();
+    path.push(format!(".{}", n));
+    PathBuf::from(path)
+  }
+
+  fn rotate(&mut self) -> std::io::Result<()> {
+    for n in (1..KEEP_ROTATED).rev() {
+      let from = self.rotated(n);
+      if from.exists() {
+        std::fs::rename(from, self.rotated(n + 1))?;
+      }
+    }
+    std::fs::rename(&self.path, self.rotated(1))?;
+
+    *self = Self::open(&self.path, self.max_size)?;
+    Ok(())
+  }
+
+  fn write_line(&mut self, line: &str) -> std::io::Result<()> {
+    let len = line.len() as u64 + 1;
+    if self.len > 0 && self.len + len > self.max_size {
+      self.rotate()?;
+    }
+
+    writeln!(self.file, "{}", line)?;
+    self.len += len;
+    Ok(())
+  }
+}
+
+enum Output {
+  Stdout,
+  File(RotatingFile),
+}
+
+pub struct AccessLog {
+  format: LogFormat,
+  output: Mutex<Output>,
+}
+
+impl AccessLog {
+  pub fn stdout(format: LogFormat) -> Self {
+    Self { format, output: Mutex::new(Output::Stdout) }
+  }
+
+  /// Appends to `path`, keeping it and each rotated file below `max_size`
+  /// bytes.
+  pub fn file(
+    format: LogFormat,
+    path: &Path,
+    max_size: u64,
+  ) -> std::io::Result<Self> {
+    let file = RotatingFile::open(path, max_size)?;
+    Ok(Self { format, output: Mutex::new(Output::File(file)) })
+  }
+
+  /// Returns the log configured by `config`, `None` if it is quiet.
+  pub fn from_config(
+    config: &ServerConfig,
+  ) -> Result<Option<Self>, Box<dyn Error>> {
+    if config.quiet {
+      return Ok(None);
+    }
+
+    match &config.log_file {
+      Some(path) => {
+        let log = Self::file(config.log_format, path, config.log_max_size)
+          .map_err(|e| format!("Cannot open {}: {}", path.display(), e))?;
+        Ok(Some(log))
+      }
+      None => Ok(Some(Self::stdout(config.log_format))),
+    }
+  }
+
+  fn write(
+    &self,
+    request: &Request,
+    status: StatusCode,
+    bytes: u64,
+    duration: Duration,
+  ) {
+    let line = format_line(self.format, request, status, bytes, duration);
+
+    let result = match &mut *self.output.lock().unwrap() {
+      Output::Stdout => writeln!(std::io::stdout().lock(), "{}", line),
+      Output::File(file) => file.write_line(&line),
+    };
+    if let Err(e) = result {
+      eprintln!("Warning: failed to write the access log: {}", e);
+    }
+  }
+}
+
+/// Counts the bytes of a response body and logs the request when dropped.
+struct Counted {
+  body: Body,
+  bytes: u64,
+  log: Arc<AccessLog>,
+  request: Request,
+  status: StatusCode,
+}
+
+impl Stream for Counted {
+  type Item = Result<Bytes, warp::hyper::Error>;
+
+  fn poll_next(
+    mut self: Pin<&mut Self>,
+    cx: &mut Context<'_>,
+  ) -> Poll<Option<Self::Item>> {
+    let poll = self.body.poll_next_unpin(cx);
+    if let Poll::Ready(Some(Ok(chunk))) = &poll {
+      self.bytes += chunk.len() as u64;
+    }
+    poll
+  }
+}
+
+impl Drop for Counted {
+  fn drop(&mut self) {
+    let duration = self.request.start.elapsed();
+    self.log.write(&self.request, self.status, self.bytes, duration);
+  }
+}
+
+fn request() -> impl Filter<Extract = (Request,), Error = Infallible> + Clone {
+  let query = warp::query::raw().or(warp::any().map(String::new)).unify();
+
+  warp::any()
+    .map(|| (OffsetDateTime::now_utc(), Instant::now()))
+    .and(filters::remote())
+    .and(warp::method())
+    .and(warp::path::full())
+    .and(query)
+    .and(warp::header::headers_cloned())
+    .map(
+      |(time, start),
+       remote,
+       method,
+       path: FullPath,
+       query: String,
+       headers: HeaderMap| {
+        let header = |name| {
+          headers
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_owned)
+        };
+        let target = if query.is_empty() {
+          path.as_str().to_owned()
+        } else {
+          auth::redact(&format!("{}?{}", path.as_str(), query))
+        };
+
+        Request {
+          time,
+          start,
+          remote,
+          user: header(header::AUTHORIZATION)
+            .and_then(|value| auth::basic_user(&value)),
+          method,
+          target,
+          referer: header(header::REFERER)
+            .map(|referer| auth::redact(&referer)),
+          user_agent: header(header::USER_AGENT),
+        }
+      },
+    )
+}
+
+/// Logs every request to `routes` in `log`. Rejections are passed on for
+/// warp to answer.
+pub fn logged(
+  log: AccessLog,
+  routes: BoxedFilter<(Response,)>,
+) -> BoxedFilter<(Response,)> {
+  let log = Arc::new(log);
+
+  request()
+    .and(routes.map(Ok).or_else(|rejection| async move {
+      Ok::<_, Rejection>((Err(rejection),))
+    }))
+    .and_then(move |request: Request, result: Result<Response, Rejection>| {
+      let log = log.clone();
+      async move {
+        match result {
+          Ok(response) => {
+            let status = response.status();
+            let (parts, body) = response.into_parts();
+            let body = Counted { body, bytes: 0, log, request, status };
+            Ok(Response::from_parts(parts, Body::wrap_stream(body)))
+          }
+          Err(rejection) => {
+            let status = filters::rejection_status(&rejection);
+            log.write(&request, status, 0, request.start.elapsed());
+            Err(rejection)
+          }
+        }
+      }
+    })
+    .boxed()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::test_utils;
+
+  fn sample() -> Request {
+    Request {
+      time: OffsetDateTime::from_unix_timestamp(971_186_136).unwrap(),
+      start: Instant::now(),
+      remote: Some(([127, 0, 0, 1], 50000).into()),
+      user: Some("frank".into()),
+      method: Method::GET,
+      target: "/apache_pb.gif".into(),
+      referer: Some("http://www.example.com/start.html".into()),
+      user_agent: Some("Mozilla/4.08 \"quoted\"".into()),
+    }
+  }
+
+  #[test]
+  fn test_format_line() {
+    let request = sample();
+    let duration = Duration::from_millis(12);
+
+    assert_eq!(
+      format_line(LogFormat::Common, &request, StatusCode::OK, 2326, duration),
+      "127.0.0.1 - frank [10/Oct/2000:13:55:36 +0000] \
+       \"GET /apache_pb.gif HTTP/1.1\" 200 2326"
+    );
+    assert_eq!(
+      format_line(LogFormat::Combined, &request, StatusCode::OK, 0, duration),
+      "127.0.0.1 - frank [10/Oct/2000:13:55:36 +0000] \
+       \"GET /apache_pb.gif HTTP/1.1\" 200 - \
+       \"http://www.example.com/start.html\" \"Mozilla/4.08 \\\"quoted\\\"\""
+    );
+
+    let json: serde_json::Value = serde_json::from_str(&format_line(
+      LogFormat::Json,
+      &request,
+      StatusCode::NOT_FOUND,
+      9,
+      duration,
+    ))
+    .unwrap();
+    assert_eq!(json["time"], "2000-10-10T13:55:36.000Z");
+    assert_eq!(json["remote_addr"], "127.0.0.1");
+    assert_eq!(json["status"], 404);
+    assert_eq!(json["bytes"], 9);
+    assert_eq!(json["duration_ms"], 12.0);
+    assert_eq!(json["user_agent"], "Mozilla/4.08 \"quoted\"");
+  }
+
+  #[tokio::test]
+  async fn test_logged() {
+    let dir = test_utils::fixture_dir("access-log");
+    let path = dir.join("access.log");
+
+    let log = AccessLog::file(LogFormat::Json, &path, 400).unwrap();
+    let routes = warp::path("file").map(|| Response::new("contents".into()));
+    let routes = logged(log, routes.boxed());
+
+    for _ in 0..4 {
+      let res = warp::test::request()
+        .path("/file?token=secret")
+        .header("user-agent", "test")
+        .reply(&routes)
+        .await;
+      assert_eq!(res.body(), "contents");
+    }
+    let res = warp::test::request().path("/missing").reply(&routes).await;
+    assert_eq!(res.status(), 404);
+
+    // Oldest first.
+    let mut files: Vec<PathBuf> = (1..=KEEP_ROTATED)
+      .rev()
+      .map(|n| dir.join(format!("access.log.{}", n)))
+      .filter(|path| path.exists())
+      .collect();
+    assert!(!files.is_empty());
+    files.push(path);
+
+    let mut lines = Vec::new();
+    for file in files {
+      assert!(std::fs::metadata(&file).unwrap().len() <= 400);
+      for line in std::fs::read_to_string(file).unwrap().lines() {
+        lines.push(serde_json::from_str::<serde_json::Value>(line).unwrap());
+      }
+    }
+
+    assert_eq!(lines.len(), 5);
+    assert_eq!(lines[0]["target"], "/file?token=redacted");
+    assert_eq!(lines[0]["bytes"], 8);
+    assert_eq!(lines[0]["user_agent"], "test");
+    assert_eq!(lines[4]["status"], 404);
+
+    std::fs::remove_dir_all(dir).unwrap();
+  }
+}
@@ -0,0 +1,490 @@
+//! HTTP Basic and token authentication for every route.
+//!
+//! Passwords are only kept as argon2 hashes, either read from a credential
+//! file with `user:$argon2id$...` lines or hashed at startup from `--auth`.
+//! Clients that keep failing are locked out for a while.
+
+use argon2::password_hash::{PasswordHash, PasswordHasher, SaltString};
+use argon2::{Argon2, PasswordVerifier};
+use base64::Engine;
+use rand::Rng;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use warp::http::{header, StatusCode};
+use warp::reply::Response;
+use warp::{Filter, Rejection, Reply};
+
+use crate::config::ServerConfig;
+use crate::filters;
+
+/// Failed attempts allowed per client within `LOCKOUT`.
+const MAX_FAILURES: u32 = 5;
+const LOCKOUT: Duration = Duration::from_secs(60);
+/// Verified `Authorization` headers kept to skip hashing on every request.
+const MAX_CACHED: usize = 64;
+
+/// Hashes `password` into a PHC string for a credential file.
+pub fn hash_password(password: &str) -> Result<String, Box<dyn Error>> {
+  let salt = SaltString::generate(&mut rand::thread_rng());
+  let hash = Argon2::default()
+    .hash_password(password.as_bytes(), &salt)
+    .map_err(|e| format!("Failed to hash password: {}", e))?;
+
+  Ok(hash.to_string())
+}
+
+/// Generates a random token of 32 hex digits.
+pub fn generate_token() -> String {
+  let bytes: [u8; 16] = rand::thread_rng().gen();
+  bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+  a.len() == b.len()
+    && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Replaces credentials in a URL, such as the user info and a `token`
+/// query parameter, so that it can be logged.
+pub fn redact(url: &str) -> String {
+  let mut url = url.to_owned();
+
+  if let Some(scheme_end) = url.find("://") {
+    let authority_start = scheme_end + 3;
+    let authority_end = url[authority_start..]
+      .find(['/', '?', '#'])
+      .map_or(url.len(), |i| authority_start + i);
+    if let Some(at) = url[authority_start..authority_end].rfind('@') {
+      url.replace_range(authority_start..authority_start + at, "redacted");
+    }
+  }
+
+  let Some(query_start) = url.find('?') else {
+    return url;
+  };
+  let (base, query) = url.split_at(query_start + 1);
+  let (query, fragment) = match query.find('#') {
+    Some(i) => query.split_at(i),
+    None => (query, ""),
+  };
+
+  let query = query
+    .split('&')
+    .map(|pair| match pair.split_once('=') {
+      Some(("token", _)) => "token=redacted",
+      _ => pair,
+    })
+    .collect::<Vec<_>>()
+    .join("&");
+
+  format!("{}{}{}", base, query, fragment)
+}
+
+/// Returns the user of a `Basic` authorization header, without checking the
+/// password.
+pub fn basic_user(authorization: &str) -> Option<String> {
+  let (scheme, encoded) = authorization.split_once(' ')?;
+  if !scheme.eq_ignore_ascii_case("basic") {
+    return None;
+  }
+
+  let decoded =
+    base64::engine::general_purpose::STANDARD.decode(encoded.trim()).ok()?;
+  let decoded = String::from_utf8(decoded).ok()?;
+  decoded.split_once(':').map(|(user, _)| user.to_owned())
+}
+
+#[derive(Debug)]
+enum AuthRejection {
+  Unauthorized,
+  TooManyAttempts(Duration),
+}
+
+impl warp::reject::Reject for AuthRejection {}
+
+#[derive(Debug, Default)]
+struct Limiter {
+  failures: HashMap<Option<IpAddr>, (u32, Instant)>,
+}
+
+impl Limiter {
+  /// Returns how long the client still has to wait, if it is locked out.
+  fn locked_out(&mut self, ip: Option<IpAddr>) -> Option<Duration> {
+    let (count, since) = *self.failures.get(&ip)?;
+    let elapsed = since.elapsed();
+
+    if elapsed >= LOCKOUT {
+      self.failures.remove(&ip);
+      None
+    } else if count >= MAX_FAILURES {
+      Some(LOCKOUT - elapsed)
+    } else {
+      None
+    }
+  }
+
+  /// Counts an attempt as failed before it is verified, so that concurrent
+  /// requests can not try more than `MAX_FAILURES` passwords, see `succeed`
+  /// and `release`.
+  fn attempt(&mut self, ip: Option<IpAddr>) -> Result<(), Duration> {
+    if let Some(wait) = self.locked_out(ip) {
+      return Err(wait);
+    }
+
+    if self.failures.len() > 10_000 {
+      self.failures.retain(|_, (_, since)| since.elapsed() < LOCKOUT);
+    }
+
+    let entry = self.failures.entry(ip).or_insert((0, Instant::now()));
+    entry.0 += 1;
+    Ok(())
+  }
+
+  /// Forgets the failures of a client that authenticated.
+  fn succeed(&mut self, ip: Option<IpAddr>) {
+    self.failures.remove(&ip);
+  }
+
+  /// Takes back an attempt without credentials.
+  fn release(&mut self, ip: Option<IpAddr>) {
+    if let Some((count, _)) = self.failures.get_mut(&ip) {
+      *count = count.saturating_sub(1);
+    }
+  }
+}
+
+/// Credentials accepted by the server.
+#[derive(Default)]
+pub struct Auth {
+  /// User names and their PHC password hashes.
+  users: HashMap<String, String>,
+  token: Option<String>,
+  limiter: Mutex<Limiter>,
+  verified: Mutex<HashSet<String>>,
+}
+
+impl Auth {
+  /// Builds the credentials of `config`, or `None` if it allows anyone.
+  pub fn from_config(
+    config: &ServerConfig,
+  ) -> Result<Option<Self>, Box<dyn Error>> {
+    let mut auth = Self { token: config.token.clone(), ..Default::default() };
+
+    if let Some(path) = &config.auth_file {
+      auth.users.extend(read_credentials(path)?);
+    }
+
+    for credentials in &config.auth {
+      let (user, password) = credentials
+        .split_once(':')
+        .filter(|(user, password)| !user.is_empty() && !password.is_empty())
+        .ok_or("Expected credentials as user:password")?;
+      auth.users.insert(user.to_owned(), hash_password(password)?);
+    }
+
+    if auth.token.as_deref() == Some("") {
+      return Err("The token must not be empty".into());
+    }
+
+    Ok((!auth.users.is_empty() || auth.token.is_some()).then_some(auth))
+  }
+
+  fn check_basic(&self, value: &str) -> bool {
+    let Some(encoded) = value
+      .split_once(' ')
+      .filter(|(scheme, _)| scheme.eq_ignore_ascii_case("basic"))
+      .map(|(_, encoded)| encoded.trim())
+    else {
+      return false;
+    };
+
+    if self.verified.lock().unwrap().contains(encoded) {
+      return true;
+    }
+
+    let Some(decoded) = base64::engine::general_purpose::STANDARD
+      .decode(encoded)
+      .ok()
+      .and_then(|decoded| String::from_utf8(decoded).ok())
+    else {
+      return false;
+    };
+    let Some((user, password)) = decoded.split_once(':') else {
+      return false;
+    };
+    let Some(hash) = self.users.get(user) else {
+      return false;
+    };
+
+    let valid = PasswordHash::new(hash).is_ok_and(|hash| {
+      Argon2::default().verify_password(password.as_bytes(), &hash).is_ok()
+    });
+
+    if valid {
+      let mut verified = self.verified.lock().unwrap();
+      if verified.len() >= MAX_CACHED {
+        verified.clear();
+      }
+      verified.insert(encoded.to_owned());
+    }
+
+    valid
+  }
+
+  fn check_token(&self, candidate: &str) -> bool {
+    self.token.as_ref().is_some_and(|token| {
+      constant_time_eq(token.as_bytes(), candidate.as_bytes())
+    })
+  }
+
+  /// Checks the credentials of a request, `None` if it has none.
+  fn check(&self, authorization: Option<&str>, query: &str) -> Option<bool> {
+    let query_token = query
+      .split('&')
+      .filter_map(|pair| pair.split_once('='))
+      .find(|(key, _)| *key == "token")
+      .map(|(_, value)| {
+        percent_encoding::percent_decode_str(value)
+          .decode_utf8_lossy()
+          .into_owned()
+      });
+
+    if authorization.is_none() && query_token.is_none() {
+      return None;
+    }
+
+    let bearer = authorization.and_then(|value| {
+      value
+        .split_once(' ')
+        .filter(|(scheme, _)| scheme.eq_ignore_ascii_case("bearer"))
+        .map(|(_, token)| token.trim())
+    });
+
+    Some(
+      authorization.is_some_and(|value| self.check_basic(value))
+        || bearer.is_some_and(|token| self.check_token(token))
+        || query_token.is_some_and(|token| self.check_token(&token)),
+    )
+  }
+
+  async fn authorize(
+    self: Arc<Self>,
+    remote: Option<SocketAddr>,
+    authorization: Option<String>,
+    query: String,
+  ) -> Result<(), Rejection> {
+    let ip = remote.map(|addr| addr.ip());
+
+    if let Err(wait) = self.limiter.lock().unwrap().attempt(ip) {
+      return Err(warp::reject::custom(AuthRejection::TooManyAttempts(wait)));
+    }
+
+    // Verifying a password hash takes a while, keep it off the runtime.
+    let auth = self.clone();
+    let result = tokio::task::spawn_blocking(move || {
+      auth.check(authorization.as_deref(), &query)
+    })
+    .await
+    .unwrap_or(Some(false));
+
+    match result {
+      Some(true) => {
+        self.limiter.lock().unwrap().succeed(ip);
+        Ok(())
+      }
+      Some(false) => Err(warp::reject::custom(AuthRejection::Unauthorized)),
+      None => {
+        self.limiter.lock().unwrap().release(ip);
+        Err(warp::reject::custom(AuthRejection::Unauthorized))
+      }
+    }
+  }
+}
+
+/// Reads `user:hash` lines, skipping blank lines and `#` comments.
+fn read_credentials(
+  path: &Path,
+) -> Result<HashMap<String, String>, Box<dyn Error>> {
+  let contents = std::fs::read_to_string(path)
+    .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+
+  let mut users = HashMap::new();
+  for (number, line) in contents.lines().enumerate() {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+      continue;
+    }
+
+    let invalid =
+      || format!("Invalid credentials in {}:{}", path.display(), number + 1);
+    let (user, hash) = line.split_once(':').ok_or_else(invalid)?;
+    PasswordHash::new(hash).map_err(|_| invalid())?;
+    users.insert(user.to_owned(), hash.to_owned());
+  }
+
+  Ok(users)
+}
+
+/// Rejects requests without valid credentials for `auth`.
+pub fn authenticated(
+  auth: Arc<Auth>,
+) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+  warp::any()
+    .map(move || auth.clone())
+    .and(filters::remote())
+    .and(warp::header::optional::<String>(header::AUTHORIZATION.as_str()))
+    .and(warp::query::raw().or(warp::any().map(String::new)).unify())
+    .and_then(Auth::authorize)
+    .untuple_one()
+}
+
+/// Turns the rejections of `authenticated` into responses, passing on the
+/// others.
+pub async fn recover(rejection: Rejection) -> Result<Response, Rejection> {
+  match rejection.find::<AuthRejection>() {
+    Some(AuthRejection::Unauthorized) => {
+      let mut response =
+        warp::reply::with_status("Unauthorized\n", StatusCode::UNAUTHORIZED)
+          .into_response();
+      let headers = response.headers_mut();
+      headers.append(
+        header::WWW_AUTHENTICATE,
+        "Basic realm=\"i6\", charset=\"UTF-8\"".parse().unwrap(),
+      );
+      headers.append(header::WWW_AUTHENTICATE, "Bearer".parse().unwrap());
+      Ok(response)
+    }
+    Some(AuthRejection::TooManyAttempts(wait)) => Ok(
+      warp::reply::with_header(
+        warp::reply::with_status(
+          "Too many failed attempts\n",
+          StatusCode::TOO_MANY_REQUESTS,
+        ),
+        header::RETRY_AFTER,
+        wait.as_secs().max(1).to_string(),
+      )
+      .into_response(),
+    ),
+    None => Err(rejection),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::test_utils;
+
+  fn basic(credentials: &str) -> String {
+    format!(
+      "Basic {}",
+      base64::engine::general_purpose::STANDARD.encode(credentials)
+    )
+  }
+
+  #[test]
+  fn test_redact() {
+    assert_eq!(
+      redact("http://a:b@host:80/x?sort=size&token=secret#top"),
+      "http://redacted@host:80/x?sort=size&token=redacted#top"
+    );
+    assert_eq!(redact("/dir/?order=asc"), "/dir/?order=asc");
+  }
+
+  #[test]
+  fn test_read_credentials() {
+    let dir = test_utils::fixture_dir("credentials");
+    let path = dir.join("htpasswd");
+    let hash = hash_password("secret").unwrap();
+    std::fs::write(&path, format!("# users\n\nalice:{}\n", hash)).unwrap();
+    assert_eq!(read_credentials(&path).unwrap()["alice"], hash);
+
+    std::fs::write(&path, "bob:secret\n").unwrap();
+    assert!(read_credentials(&path).is_err());
+
+    std::fs::remove_dir_all(dir).unwrap();
+  }
+
+  #[tokio::test]
+  async fn test_authenticated() {
+    let config = ServerConfig {
+      auth: vec!["alice:secret".to_owned()],
+      token: Some("t0ken".to_owned()),
+      ..Default::default()
+    };
+    let auth = Arc::new(Auth::from_config(&config).unwrap().unwrap());
+    let filter =
+      authenticated(auth).map(|| "ok".into_response()).recover(recover).unify();
+
+    let res = warp::test::request().path("/").reply(&filter).await;
+    assert_eq!(res.status(), 401);
+    assert!(res.headers()["www-authenticate"]
+      .to_str()
+      .unwrap()
+      .contains("Basic"));
+
+    let res = warp::test::request()
+      .header("authorization", basic("alice:secret"))
+      .reply(&filter)
+      .await;
+    assert_eq!(res.status(), 200);
+
+    let res = warp::test::request()
+      .header("authorization", "Bearer t0ken")
+      .reply(&filter)
+      .await;
+    assert_eq!(res.status(), 200);
+
+    let res = warp::test::request().path("/?token=t0ken").reply(&filter).await;
+    assert_eq!(res.status(), 200);
+
+    for _ in 0..MAX_FAILURES {
+      let res = warp::test::request()
+        .header("authorization", basic("alice:wrong"))
+        .reply(&filter)
+        .await;
+      assert_eq!(res.status(), 401);
+    }
+
+    let res = warp::test::request()
+      .header("authorization", basic("alice:secret"))
+      .reply(&filter)
+      .await;
+    assert_eq!(res.status(), 429);
+    assert!(res.headers().contains_key("retry-after"));
+  }
+
+  #[tokio::test]
+  async fn test_concurrent_failures() {
+    let config = ServerConfig {
+      auth: vec!["alice:secret".to_owned()],
+      ..Default::default()
+    };
+    let auth = Arc::new(Auth::from_config(&config).unwrap().unwrap());
+    let filter =
+      authenticated(auth).map(|| "ok".into_response()).recover(recover).unify();
+
+    let attempts = (0..MAX_FAILURES * 4).map(|_| {
+      warp::test::request()
+        .header("authorization", basic("alice:wrong"))
+        .reply(&filter)
+    });
+    let statuses: Vec<_> = futures_util::future::join_all(attempts)
+      .await
+      .iter()
+      .map(|res| res.status())
+      .collect();
+
+    let unauthorized = statuses.iter().filter(|status| **status == 401);
+    assert_eq!(unauthorized.count(), MAX_FAILURES as usize);
+    let locked_out = statuses.iter().filter(|status| **status == 429);
+    assert_eq!(locked_out.count(), statuses.len() - MAX_FAILURES as usize);
+
+    // Requests without credentials are not counted.
+    let res = warp::test::request().path("/").reply(&filter).await;
+    assert_eq!(res.status(), 429);
+  }
+}
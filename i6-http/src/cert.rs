@@ -0,0 +1,285 @@
+//! Certificates for the https server, kept in a per-user config directory so
+//! that clients only have to trust them once.
+//!
+//! With a local certificate authority, every server certificate is signed by
+//! one root that a team installs once, instead of trusting each server.
+
+use rcgen::{
+  BasicConstraints, Certificate, CertificateParams, DistinguishedName, DnType,
+  ExtendedKeyUsagePurpose, IsCa, KeyPair, KeyUsagePurpose,
+};
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use time::OffsetDateTime;
+
+/// Names every generated certificate is valid for.
+const DEFAULT_SANS: [&str; 3] = ["localhost", "127.0.0.1", "::1"];
+
+const CA_VALIDITY_DAYS: i64 = 10 * 365;
+/// The longest validity that Apple platforms accept for server certificates,
+/// even from a locally installed root.
+const LEAF_VALIDITY_DAYS: i64 = 825;
+/// Age at which `server_cert` issues a new leaf.
+const LEAF_RENEW_DAYS: u64 = 800;
+
+/// Returns the directory for generated certificates, e.g.
+/// `~/.config/i6/http` on Linux.
+pub fn config_dir() -> PathBuf {
+  dirs::config_dir().unwrap_or_else(std::env::temp_dir).join("i6").join("http")
+}
+
+/// Returns the default subject alternative names followed by `extra`,
+/// without duplicates.
+pub fn sans_with_defaults(extra: &[String]) -> Vec<String> {
+  let mut sans: Vec<String> =
+    DEFAULT_SANS.iter().map(|san| san.to_string()).collect();
+
+  for san in extra {
+    let san = san.trim().to_ascii_lowercase();
+    if !san.is_empty() && !sans.contains(&san) {
+      sans.push(san);
+    }
+  }
+
+  sans
+}
+
+/// Writes a file only the current user can read.
+pub fn write_private(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+  if let Some(parent) = path.parent() {
+    std::fs::create_dir_all(parent)?;
+  }
+
+  let mut options = std::fs::OpenOptions::new();
+  options.write(true).create(true).truncate(true);
+  #[cfg(unix)]
+  std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+  std::io::Write::write_all(&mut options.open(path)?, contents)
+}
+
+/// Returns the paths of a self-signed certificate and key for `sans` in
+/// `dir`, generating them unless a previous run already did.
+pub fn self_signed_in(
+  dir: &Path,
+  sans: &[String],
+) -> Result<(PathBuf, PathBuf), Box<dyn Error>> {
+  let cert_path = dir.join("cert.pem");
+  let key_path = dir.join("key.pem");
+  let sans_path = dir.join("sans");
+  let wanted = sans.join("\n");
+
+  let current = std::fs::read_to_string(&sans_path).ok();
+  if current.as_deref() == Some(wanted.as_str())
+    && cert_path.is_file()
+    && key_path.is_file()
+  {
+    return Ok((cert_path, key_path));
+  }
+
+  let key_pair = KeyPair::generate()?;
+  let cert = CertificateParams::new(sans.to_vec())?.self_signed(&key_pair)?;
+
+  write_private(&key_path, key_pair.serialize_pem().as_bytes())?;
+  std::fs::write(&cert_path, cert.pem())?;
+  std::fs::write(&sans_path, wanted)?;
+
+  println!("Generated certificate {}", cert_path.display());
+
+  Ok((cert_path, key_path))
+}
+
+/// Like `self_signed_in`, in the `self-signed` directory of `config_dir`.
+pub fn self_signed(
+  sans: &[String],
+) -> Result<(PathBuf, PathBuf), Box<dyn Error>> {
+  self_signed_in(&config_dir().join("self-signed"), sans)
+}
+
+/// Returns the directory of the local certificate authority.
+pub fn ca_dir() -> PathBuf {
+  config_dir().join("ca")
+}
+
+fn ca_paths(dir: &Path) -> (PathBuf, PathBuf) {
+  (dir.join("ca.pem"), dir.join("ca-key.pem"))
+}
+
+/// Creates a root certificate authority in `dir` and returns the path of its
+/// certificate, which clients should trust. Refuses to replace an existing
+/// one unless `force` is set, since that invalidates every issued leaf.
+pub fn init_ca(dir: &Path, force: bool) -> Result<PathBuf, Box<dyn Error>> {
+  let (cert_path, key_path) = ca_paths(dir);
+  if !force && (cert_path.exists() || key_path.exists()) {
+    return Err(
+      format!("A CA already exists in {}, use --force", dir.display()).into(),
+    );
+  }
+
+  let mut name = DistinguishedName::new();
+  name.push(DnType::OrganizationName, "i6");
+  name.push(DnType::CommonName, "i6 Local CA");
+
+  let now = OffsetDateTime::now_utc();
+  let mut params = CertificateParams::default();
+  params.distinguished_name = name;
+  params.is_ca = IsCa::Ca(BasicConstraints::Constrained(0));
+  params.key_usages = vec![
+    KeyUsagePurpose::KeyCertSign,
+    KeyUsagePurpose::CrlSign,
+    KeyUsagePurpose::DigitalSignature,
+  ];
+  params.not_before = now - time::Duration::days(1);
+  params.not_after = now + time::Duration::days(CA_VALIDITY_DAYS);
+
+  let key_pair = KeyPair::generate()?;
+  let cert = params.self_signed(&key_pair)?;
+
+  write_private(&key_path, key_pair.serialize_pem().as_bytes())?;
+  std::fs::write(&cert_path, cert.pem())?;
+
+  Ok(cert_path)
+}
+
+struct Ca {
+  cert: Certificate,
+  key_pair: KeyPair,
+  /// The certificate as stored, since `cert` is signed again on loading.
+  pem: String,
+}
+
+/// Loads the certificate authority in `dir`, `None` if there is none.
+fn load_ca(dir: &Path) -> Result<Option<Ca>, Box<dyn Error>> {
+  let (cert_path, key_path) = ca_paths(dir);
+  if !cert_path.exists() {
+    return Ok(None);
+  }
+
+  let read = |path: &Path| {
+    std::fs::read_to_string(path)
+      .map_err(|e| format!("Failed to read {}: {}", path.display(), e))
+  };
+
+  let key_pair = KeyPair::from_pem(&read(&key_path)?)?;
+  let pem = read(&cert_path)?;
+  // Signing again with the same key and name yields an equivalent issuer.
+  let cert =
+    CertificateParams::from_ca_cert_pem(&pem)?.self_signed(&key_pair)?;
+
+  Ok(Some(Ca { cert, key_pair, pem }))
+}
+
+/// Signs a server certificate for `sans` with the certificate authority in
+/// `ca`, writing `cert.pem` and `key.pem` to `out`.
+pub fn issue(
+  ca: &Path,
+  sans: &[String],
+  out: &Path,
+) -> Result<(PathBuf, PathBuf), Box<dyn Error>> {
+  let authority = load_ca(ca)?.ok_or_else(|| {
+    format!("No CA in {}, create one with `i6 cert ca init`", ca.display())
+  })?;
+
+  let now = OffsetDateTime::now_utc();
+  let mut params = CertificateParams::new(sans.to_vec())?;
+  if let Some(san) = sans.first() {
+    params.distinguished_name.push(DnType::CommonName, san.as_str());
+  }
+  params.key_usages =
+    vec![KeyUsagePurpose::DigitalSignature, KeyUsagePurpose::KeyEncipherment];
+  params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ServerAuth];
+  params.use_authority_key_identifier_extension = true;
+  params.not_before = now - time::Duration::days(1);
+  params.not_after = now + time::Duration::days(LEAF_VALIDITY_DAYS);
+
+  let key_pair = KeyPair::generate()?;
+  let cert =
+    params.signed_by(&key_pair, &authority.cert, &authority.key_pair)?;
+
+  let cert_path = out.join("cert.pem");
+  let key_path = out.join("key.pem");
+  write_private(&key_path, key_pair.serialize_pem().as_bytes())?;
+  // Serve the chain, so clients can build the path to the root.
+  std::fs::write(&cert_path, format!("{}{}", cert.pem(), authority.pem))?;
+
+  Ok((cert_path, key_path))
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+  std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Returns a certificate and key for the https server: a leaf of the local
+/// certificate authority if there is one, otherwise a self-signed pair.
+pub fn server_cert(
+  sans: &[String],
+) -> Result<(PathBuf, PathBuf), Box<dyn Error>> {
+  let ca = ca_dir();
+  let (ca_cert_path, _) = ca_paths(&ca);
+  let Some(ca_modified) = modified(&ca_cert_path) else {
+    return self_signed(sans);
+  };
+
+  let dir = config_dir().join("leaf");
+  let sans_path = dir.join("sans");
+  let wanted = sans.join("\n");
+
+  let current = std::fs::read_to_string(&sans_path).ok();
+  let fresh = modified(&dir.join("cert.pem")).is_some_and(|issued| {
+    issued >= ca_modified
+      && issued.elapsed().unwrap_or_default()
+        < Duration::from_secs(LEAF_RENEW_DAYS * 24 * 60 * 60)
+  });
+  if fresh && current.as_deref() == Some(wanted.as_str()) {
+    return Ok((dir.join("cert.pem"), dir.join("key.pem")));
+  }
+
+  let paths = issue(&ca, sans, &dir)?;
+  std::fs::write(&sans_path, wanted)?;
+  println!("Issued certificate {} from the local CA", paths.0.display());
+
+  Ok(paths)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::test_utils;
+
+  #[test]
+  fn test_self_signed() {
+    let dir = test_utils::fixture_dir("cert");
+
+    let sans = sans_with_defaults(&["192.168.1.2".into(), "LOCALHOST".into()]);
+    assert_eq!(sans, ["localhost", "127.0.0.1", "::1", "192.168.1.2"]);
+
+    let (cert, _) = self_signed_in(&dir, &sans).unwrap();
+    let first = std::fs::read(&cert).unwrap();
+    self_signed_in(&dir, &sans).unwrap();
+    assert_eq!(std::fs::read(&cert).unwrap(), first);
+
+    self_signed_in(&dir, &sans_with_defaults(&[])).unwrap();
+    assert_ne!(std::fs::read(&cert).unwrap(), first);
+
+    std::fs::remove_dir_all(dir).unwrap();
+  }
+
+  #[test]
+  fn test_issue() {
+    let dir = test_utils::fixture_dir("ca");
+    let sans = sans_with_defaults(&["dev.lan".into()]);
+
+    assert!(issue(&dir, &sans, &dir).is_err());
+    let ca_path = init_ca(&dir, false).unwrap();
+    assert!(init_ca(&dir, false).is_err());
+
+    let (cert_path, _) = issue(&dir, &sans, &dir.join("leaf")).unwrap();
+    let chain = std::fs::read_to_string(cert_path).unwrap();
+    let ca = std::fs::read_to_string(ca_path).unwrap();
+    assert_eq!(chain.matches("BEGIN CERTIFICATE").count(), 2);
+    assert!(chain.ends_with(&ca));
+
+    std::fs::remove_dir_all(dir).unwrap();
+  }
+}
@@ -0,0 +1,332 @@
+use clap::parser::ValueSource;
+use clap::{value_parser, Arg, ArgAction, ArgMatches};
+use std::error::Error;
+use std::path::PathBuf;
+
+use crate::auth;
+use crate::config::{self, ServerConfig};
+use crate::filters;
+
+/// Arguments shared by the `http` and `https` commands.
+pub fn args() -> Vec<Arg> {
+  vec![
+    Arg::new("port")
+      .index(1)
+      .default_value("3030")
+      .value_parser(value_parser!(u16)),
+    Arg::new("config")
+      .help("Read settings from a TOML file, flags take precedence")
+      .long("config"),
+    Arg::new("bind")
+      .help(
+        "Address to listen on, repeat for several, e.g. 0.0.0.0, :: or \
+         [::1]:8080 [default: 127.0.0.1]",
+      )
+      .short('b')
+      .long("bind")
+      .action(ArgAction::Append),
+    Arg::new("root")
+      .help("Directory to serve [default: .]")
+      .short('r')
+      .long("root")
+      .value_parser(value_parser!(PathBuf)),
+    Arg::new("hidden")
+      .help("Serve files and directories whose name starts with a dot")
+      .long("hidden")
+      .action(ArgAction::SetTrue),
+    Arg::new("follow-symlinks")
+      .help("Serve symlinks that point outside of the root directory")
+      .long("follow-symlinks")
+      .action(ArgAction::SetTrue),
+    Arg::new("no-listing")
+      .help("Do not list directories that have no index.html")
+      .long("no-listing")
+      .action(ArgAction::SetTrue),
+    Arg::new("upload")
+      .help("Accept file uploads with PUT or from the directory listing")
+      .long("upload")
+      .action(ArgAction::SetTrue),
+    Arg::new("max-upload-size")
+      .help("Largest accepted upload, e.g. 500M [default: 1G]")
+      .long("max-upload-size")
+      .value_parser(config::parse_size),
+    Arg::new("overwrite")
+      .help("Let uploads replace existing files")
+      .long("overwrite")
+      .action(ArgAction::SetTrue),
+    Arg::new("webdav")
+      .help("Serve over WebDAV for file managers, writable with --upload")
+      .long("webdav")
+      .action(ArgAction::SetTrue),
+    Arg::new("auth")
+      .help("Require HTTP Basic credentials as user:password, repeatable")
+      .long("auth")
+      .action(ArgAction::Append),
+    Arg::new("auth-file")
+      .help("Require HTTP Basic credentials from a file of user:hash lines")
+      .long("auth-file")
+      .value_parser(value_parser!(PathBuf)),
+    Arg::new("token")
+      .help(
+        "Require a bearer token or ?token= query parameter, generated when \
+         no value is given",
+      )
+      .long("token")
+      .num_args(0..=1)
+      .require_equals(true)
+      .default_missing_value(""),
+    Arg::new("cache-control")
+      .help(
+        "Cache-Control header of served files, e.g. no-cache or max-age=600",
+      )
+      .long("cache-control"),
+    Arg::new("no-compression")
+      .help("Do not compress responses, precompressed files are still served")
+      .long("no-compression")
+      .action(ArgAction::SetTrue),
+    Arg::new("spa")
+      .help("Serve index.html for unknown paths without a file extension")
+      .long("spa")
+      .action(ArgAction::SetTrue),
+    Arg::new("not-found")
+      .help("Page to send with 404 responses")
+      .long("404")
+      .value_name("FILE")
+      .value_parser(value_parser!(PathBuf)),
+    Arg::new("watch")
+      .help(
+        "Reload pages in the browser when files change, serves uncompressed",
+      )
+      .long("watch")
+      .action(ArgAction::SetTrue),
+    Arg::new("watch-ignore")
+      .help("Glob pattern of changes that do not reload, repeatable")
+      .long("watch-ignore")
+      .value_name("PATTERN")
+      .action(ArgAction::Append),
+    Arg::new("proxy")
+      .help(
+        "Forward requests under a path to a backend, e.g. \
+         /api=http://127.0.0.1:8080, repeatable",
+      )
+      .long("proxy")
+      .value_name("PATH=URL")
+      .action(ArgAction::Append),
+    Arg::new("proxy-timeout")
+      .help("Seconds to wait for a proxy backend to respond")
+      .long("proxy-timeout")
+      .value_name("SECONDS")
+      .value_parser(value_parser!(u64)),
+    Arg::new("cors")
+      .help("Allow cross-origin requests from an origin, or any if omitted")
+      .long("cors")
+      .value_name("ORIGIN")
+      .num_args(0..=1)
+      .require_equals(true)
+      .default_missing_value("*")
+      .action(ArgAction::Append),
+    Arg::new("header")
+      .help("Add a response header, e.g. 'Cross-Origin-Opener-Policy: same-origin', repeatable")
+      .short('H')
+      .long("header")
+      .value_name("HEADER")
+      .action(ArgAction::Append),
+    Arg::new("quiet")
+      .help("Do not log requests")
+      .short('q')
+      .long("quiet")
+      .action(ArgAction::SetTrue),
+    Arg::new("log-format")
+      .help("Format of the access log [default: combined]")
+      .long("log-format")
+      .value_parser(["common", "combined", "json"]),
+    Arg::new("log-file")
+      .help("Append the access log to a file instead of stdout")
+      .long("log-file")
+      .value_parser(value_parser!(PathBuf)),
+    Arg::new("log-max-size")
+      .help("Rotate the log file at this size, e.g. 50M [default: 10M]")
+      .long("log-max-size")
+      .value_parser(config::parse_size),
+  ]
+}
+
+/// Arguments of the `https` command only.
+pub fn tls_args() -> Vec<Arg> {
+  vec![
+    Arg::new("cert")
+      .help("PEM certificate chain to serve instead of a generated one")
+      .long("cert")
+      .requires("key")
+      .value_parser(value_parser!(PathBuf)),
+    Arg::new("key")
+      .help("PEM private key of --cert")
+      .long("key")
+      .requires("cert")
+      .value_parser(value_parser!(PathBuf)),
+    Arg::new("san")
+      .help(
+        "Hostname or IP address to add to the generated certificate, \
+         repeatable",
+      )
+      .long("san")
+      .action(ArgAction::Append),
+    Arg::new("http3")
+      .help("Also serve HTTP/3 over QUIC on the same port, over UDP")
+      .long("http3")
+      .action(ArgAction::SetTrue),
+  ]
+}
+
+fn given(matches: &ArgMatches, id: &str) -> bool {
+  matches.value_source(id) == Some(ValueSource::CommandLine)
+}
+
+/// Builds the config from `--config` if given, then applies the flags that
+/// were passed on the command line.
+pub fn config_from_matches(
+  matches: &ArgMatches,
+) -> Result<ServerConfig, Box<dyn Error>> {
+  let mut config = match matches.get_one::<String>("config") {
+    Some(path) => ServerConfig::from_file(path)?,
+    None => ServerConfig::default(),
+  };
+
+  if given(matches, "port") {
+    config.port = *matches.get_one::<u16>("port").unwrap();
+  }
+
+  if let Some(bind) = matches.get_many::<String>("bind") {
+    config.bind = bind.cloned().collect();
+    for bind in &config.bind {
+      config::parse_bind(bind, config.port)?;
+    }
+  }
+
+  if let Some(root) = matches.get_one::<PathBuf>("root") {
+    config.root = root.clone();
+  }
+
+  if given(matches, "hidden") {
+    config.hidden = true;
+  }
+
+  if given(matches, "follow-symlinks") {
+    config.follow_symlinks = true;
+  }
+
+  if given(matches, "no-listing") {
+    config.listing = false;
+  }
+
+  if given(matches, "upload") {
+    config.upload = true;
+  }
+
+  if let Some(size) = matches.get_one::<u64>("max-upload-size") {
+    config.max_upload_size = *size;
+  }
+
+  if given(matches, "overwrite") {
+    config.overwrite = true;
+  }
+
+  if given(matches, "webdav") {
+    config.webdav = true;
+  }
+
+  if let Some(auth) = matches.get_many::<String>("auth") {
+    config.auth = auth.cloned().collect();
+  }
+
+  if let Some(path) = matches.get_one::<PathBuf>("auth-file") {
+    config.auth_file = Some(path.clone());
+  }
+
+  if let Some(token) = matches.get_one::<String>("token") {
+    if token.is_empty() {
+      let token = auth::generate_token();
+      println!("Access token: {}", token);
+      config.token = Some(token);
+    } else {
+      config.token = Some(token.clone());
+    }
+  }
+
+  if let Some(cache_control) = matches.get_one::<String>("cache-control") {
+    config.cache_control = Some(cache_control.clone());
+  }
+
+  if given(matches, "no-compression") {
+    config.compress = false;
+  }
+
+  if given(matches, "spa") {
+    config.spa = true;
+  }
+
+  if let Some(page) = matches.get_one::<PathBuf>("not-found") {
+    config.not_found = Some(page.clone());
+  }
+
+  if given(matches, "watch") {
+    config.watch = true;
+  }
+
+  if let Some(patterns) = matches.get_many::<String>("watch-ignore") {
+    config.watch_ignore = patterns.cloned().collect();
+  }
+
+  if let Some(rules) = matches.get_many::<String>("proxy") {
+    config.proxy = rules.cloned().collect();
+  }
+
+  if let Some(timeout) = matches.get_one::<u64>("proxy-timeout") {
+    config.proxy_timeout = *timeout;
+  }
+
+  if let Some(origins) = matches.get_many::<String>("cors") {
+    config.cors = origins.cloned().collect();
+  }
+
+  if let Some(headers) = matches.get_many::<String>("header") {
+    config.headers = headers.cloned().collect();
+    for header in &config.headers {
+      filters::parse_header(header)?;
+    }
+  }
+
+  if given(matches, "quiet") {
+    config.quiet = true;
+  }
+
+  if let Some(format) = matches.get_one::<String>("log-format") {
+    config.log_format = format.parse()?;
+  }
+
+  if let Some(path) = matches.get_one::<PathBuf>("log-file") {
+    config.log_file = Some(path.clone());
+  }
+
+  if let Some(size) = matches.get_one::<u64>("log-max-size") {
+    config.log_max_size = *size;
+  }
+
+  if let Ok(Some(cert)) = matches.try_get_one::<PathBuf>("cert") {
+    config.cert = Some(cert.clone());
+  }
+
+  if let Ok(Some(key)) = matches.try_get_one::<PathBuf>("key") {
+    config.key = Some(key.clone());
+  }
+
+  if let Ok(Some(san)) = matches.try_get_many::<String>("san") {
+    config.san = san.cloned().collect();
+  }
+
+  if matches!(matches.try_get_one::<bool>("http3"), Ok(Some(true))) {
+    config.http3 = true;
+  }
+
+  Ok(config)
+}
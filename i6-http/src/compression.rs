@@ -0,0 +1,162 @@
+//! Content negotiation on `Accept-Encoding`, for precompressed files next to
+//! the originals and for compressing text on the fly.
+
+use async_compression::tokio::bufread::{
+  BrotliEncoder, GzipEncoder, ZstdEncoder,
+};
+use async_compression::Level;
+use futures_util::stream::{BoxStream, StreamExt};
+use mime_guess::Mime;
+use tokio::io::AsyncBufRead;
+use tokio_util::io::ReaderStream;
+use warp::hyper::body::Bytes;
+
+/// Files smaller than this are not worth compressing on the fly.
+pub const MIN_COMPRESS_SIZE: u64 = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+  Brotli,
+  Zstd,
+  Gzip,
+}
+
+impl Encoding {
+  /// In order of preference when the client accepts several equally.
+  pub const ALL: [Encoding; 3] =
+    [Encoding::Brotli, Encoding::Zstd, Encoding::Gzip];
+
+  pub fn token(self) -> &'static str {
+    match self {
+      Encoding::Brotli => "br",
+      Encoding::Zstd => "zstd",
+      Encoding::Gzip => "gzip",
+    }
+  }
+
+  /// Extension of a precompressed file, e.g. `foo.js.br`.
+  pub fn extension(self) -> &'static str {
+    match self {
+      Encoding::Brotli => "br",
+      Encoding::Zstd => "zst",
+      Encoding::Gzip => "gz",
+    }
+  }
+
+  /// Compresses `reader` into a stream of chunks.
+  pub fn encode<R>(
+    self,
+    reader: R,
+  ) -> BoxStream<'static, std::io::Result<Bytes>>
+  where
+    R: AsyncBufRead + Send + Unpin + 'static,
+  {
+    match self {
+      // The default quality of 11 is too slow to compress on the fly.
+      Encoding::Brotli => ReaderStream::new(BrotliEncoder::with_quality(
+        reader,
+        Level::Precise(4),
+      ))
+      .boxed(),
+      Encoding::Zstd => ReaderStream::new(ZstdEncoder::new(reader)).boxed(),
+      Encoding::Gzip => ReaderStream::new(GzipEncoder::new(reader)).boxed(),
+    }
+  }
+}
+
+/// Returns the encodings accepted by an `Accept-Encoding` header, the most
+/// preferred first. Encodings with `q=0` are left out.
+pub fn accepted(accept_encoding: Option<&str>) -> Vec<Encoding> {
+  let Some(accept_encoding) = accept_encoding else {
+    return Vec::new();
+  };
+
+  let mut wildcard = None;
+  let mut weights: Vec<(Encoding, f32)> = Vec::new();
+
+  for item in accept_encoding.split(',') {
+    let mut params = item.split(';');
+    let token = params.next().unwrap_or("").trim().to_ascii_lowercase();
+    let q = params
+      .filter_map(|param| param.trim().strip_prefix("q="))
+      .find_map(|q| q.trim().parse::<f32>().ok())
+      .unwrap_or(1.0);
+
+    if token == "*" {
+      wildcard = Some(q);
+    } else if let Some(encoding) =
+      Encoding::ALL.into_iter().find(|e| e.token() == token)
+    {
+      weights.push((encoding, q));
+    }
+  }
+
+  if let Some(q) = wildcard {
+    for encoding in Encoding::ALL {
+      if !weights.iter().any(|(e, _)| *e == encoding) {
+        weights.push((encoding, q));
+      }
+    }
+  }
+
+  weights.retain(|(_, q)| *q > 0.0);
+  // Stable, so ties keep the order of `Encoding::ALL`.
+  weights.sort_by_key(|(encoding, _)| {
+    Encoding::ALL.iter().position(|e| e == encoding)
+  });
+  weights.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+
+  weights.into_iter().map(|(encoding, _)| encoding).collect()
+}
+
+/// Whether compressing `mime` on the fly saves enough to be worth it.
+pub fn is_compressible(mime: &Mime) -> bool {
+  // Covers `image/svg+xml`, `application/manifest+json` and the like.
+  if matches!(mime.suffix().map(|s| s.as_str()), Some("json" | "xml")) {
+    return true;
+  }
+
+  match (mime.type_().as_str(), mime.subtype().as_str()) {
+    ("text", _) => true,
+    ("application", subtype) => {
+      matches!(subtype, "javascript" | "json" | "xml" | "wasm")
+    }
+    _ => false,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_accepted() {
+    assert_eq!(accepted(None), []);
+    assert_eq!(
+      accepted(Some("gzip, deflate, br, zstd")),
+      [Encoding::Brotli, Encoding::Zstd, Encoding::Gzip]
+    );
+    assert_eq!(
+      accepted(Some("br;q=0.5, GZIP")),
+      [Encoding::Gzip, Encoding::Brotli]
+    );
+    assert_eq!(
+      accepted(Some("*;q=0.1, br;q=0")),
+      [Encoding::Zstd, Encoding::Gzip]
+    );
+    assert_eq!(accepted(Some("identity")), []);
+  }
+
+  #[test]
+  fn test_is_compressible() {
+    assert!(is_compressible(
+      &mime_guess::from_path("a.js").first_or_octet_stream()
+    ));
+    assert!(is_compressible(
+      &mime_guess::from_path("a.svg").first_or_octet_stream()
+    ));
+    assert!(!is_compressible(
+      &mime_guess::from_path("a.png").first_or_octet_stream()
+    ));
+  }
+}
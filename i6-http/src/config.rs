@@ -0,0 +1,244 @@
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+
+use crate::access_log::LogFormat;
+
+pub const DEFAULT_PORT: u16 = 3030;
+pub const DEFAULT_MAX_UPLOAD_SIZE: u64 = 1024 * 1024 * 1024;
+pub const DEFAULT_PROXY_TIMEOUT: u64 = 30;
+pub const DEFAULT_LOG_MAX_SIZE: u64 = 10 * 1024 * 1024;
+
+/// Settings of the static file server.
+///
+/// Defaults are safe for shared networks: the server only listens on the
+/// loopback interface, and hidden files and symlinks that leave the root are
+/// not served.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+  /// Addresses to listen on, either an IP address that uses `port`, or an
+  /// address with its own port like `[::1]:8080`.
+  pub bind: Vec<String>,
+  pub port: u16,
+  /// Directory to serve.
+  pub root: PathBuf,
+  /// Serve files and directories whose name starts with a dot.
+  pub hidden: bool,
+  /// Serve symlinks that point outside of `root`.
+  pub follow_symlinks: bool,
+  /// List directories that have no `index.html`.
+  pub listing: bool,
+  /// Accept `PUT` requests and multipart forms that write files.
+  pub upload: bool,
+  /// Largest accepted upload in bytes.
+  pub max_upload_size: u64,
+  /// Let uploads replace existing files.
+  pub overwrite: bool,
+  /// Serve WebDAV for file managers, which may change files when `upload`
+  /// is set as well.
+  pub webdav: bool,
+  /// Credentials as `user:password`, hashed when the server starts.
+  pub auth: Vec<String>,
+  /// File with `user:hash` lines, where the hash is an argon2 PHC string.
+  pub auth_file: Option<PathBuf>,
+  /// Token accepted as `Authorization: Bearer` or `?token=`.
+  pub token: Option<String>,
+  /// PEM certificate chain for https, generated when not set.
+  pub cert: Option<PathBuf>,
+  /// PEM private key of `cert`.
+  pub key: Option<PathBuf>,
+  /// Extra hostnames and IP addresses for the generated certificate.
+  pub san: Vec<String>,
+  /// Also serve https as HTTP/3 on the UDP port of each address, needs the
+  /// `http3` feature.
+  pub http3: bool,
+  /// `Cache-Control` header of served files, e.g. `no-cache`.
+  pub cache_control: Option<String>,
+  /// Compress text-like files for clients that accept gzip, brotli or zstd.
+  pub compress: bool,
+  /// Serve `index.html` for paths without a file extension that match no
+  /// file, for client side routing.
+  pub spa: bool,
+  /// Page to send with `404 Not Found` responses.
+  pub not_found: Option<PathBuf>,
+  /// Reload pages in the browser when files in `root` change.
+  pub watch: bool,
+  /// Glob patterns of changes that do not reload, in addition to
+  /// `livereload::DEFAULT_IGNORE`.
+  pub watch_ignore: Vec<String>,
+  /// Rules like `/api=http://127.0.0.1:8080` that forward requests under a
+  /// path to a backend.
+  pub proxy: Vec<String>,
+  /// Seconds to wait for a backend to accept and answer a request.
+  pub proxy_timeout: u64,
+  /// Origins allowed to fetch from other sites, `*` for any.
+  pub cors: Vec<String>,
+  /// Extra response headers as `Name: value`, e.g.
+  /// `Cross-Origin-Embedder-Policy: require-corp`.
+  pub headers: Vec<String>,
+  /// Do not log requests.
+  pub quiet: bool,
+  pub log_format: LogFormat,
+  /// File to append the access log to instead of stdout.
+  pub log_file: Option<PathBuf>,
+  /// Size in bytes at which `log_file` is rotated.
+  pub log_max_size: u64,
+}
+
+impl Default for ServerConfig {
+  fn default() -> Self {
+    Self {
+      bind: vec![Ipv4Addr::LOCALHOST.to_string()],
+      port: DEFAULT_PORT,
+      root: PathBuf::from("."),
+      hidden: false,
+      follow_symlinks: false,
+      listing: true,
+      upload: false,
+      max_upload_size: DEFAULT_MAX_UPLOAD_SIZE,
+      overwrite: false,
+      webdav: false,
+      auth: Vec::new(),
+      auth_file: None,
+      token: None,
+      cert: None,
+      key: None,
+      san: Vec::new(),
+      http3: false,
+      cache_control: None,
+      compress: true,
+      spa: false,
+      not_found: None,
+      watch: false,
+      watch_ignore: Vec::new(),
+      proxy: Vec::new(),
+      proxy_timeout: DEFAULT_PROXY_TIMEOUT,
+      cors: Vec::new(),
+      headers: Vec::new(),
+      quiet: false,
+      log_format: LogFormat::default(),
+      log_file: None,
+      log_max_size: DEFAULT_LOG_MAX_SIZE,
+    }
+  }
+}
+
+impl ServerConfig {
+  /// Reads a TOML config file, missing fields keep their defaults.
+  pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error>> {
+    let path = path.as_ref();
+    let contents = std::fs::read_to_string(path)
+      .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+
+    toml::from_str(&contents)
+      .map_err(|e| format!("Invalid config {}: {}", path.display(), e).into())
+  }
+
+  /// Resolves `bind` to socket addresses.
+  pub fn addresses(&self) -> Result<Vec<SocketAddr>, Box<dyn Error>> {
+    if self.bind.is_empty() {
+      return Err("No address to bind to".into());
+    }
+
+    self
+      .bind
+      .iter()
+      .map(|bind| parse_bind(bind, self.port))
+      .collect::<Result<Vec<_>, _>>()
+      .map_err(Into::into)
+  }
+}
+
+/// Parses `127.0.0.1`, `::`, `0.0.0.0:8080` or `[::1]:8080`.
+pub fn parse_bind(bind: &str, port: u16) -> Result<SocketAddr, String> {
+  let bind = bind.trim();
+
+  if let Ok(addr) = bind.parse::<SocketAddr>() {
+    return Ok(addr);
+  }
+
+  bind
+    .trim_start_matches('[')
+    .trim_end_matches(']')
+    .parse::<IpAddr>()
+    .map(|ip| SocketAddr::new(ip, port))
+    .map_err(|_| format!("Invalid bind address {:?}", bind))
+}
+
+/// Parses a size like `512`, `64K`, `10M` or `1G`, in powers of 1024.
+pub fn parse_size(size: &str) -> Result<u64, String> {
+  let size = size.trim();
+  let digits = size.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+  let unit = match size[digits.len()..].to_ascii_uppercase().as_str() {
+    "" | "B" => 1,
+    "K" | "KB" | "KIB" => 1 << 10,
+    "M" | "MB" | "MIB" => 1 << 20,
+    "G" | "GB" | "GIB" => 1 << 30,
+    "T" | "TB" | "TIB" => 1 << 40,
+    _ => return Err(format!("Invalid size {:?}", size)),
+  };
+
+  digits
+    .trim()
+    .parse::<u64>()
+    .ok()
+    .and_then(|n| n.checked_mul(unit))
+    .ok_or_else(|| format!("Invalid size {:?}", size))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::test_utils;
+
+  #[test]
+  fn test_parse_bind() {
+    assert_eq!(
+      parse_bind("127.0.0.1", 80).unwrap().to_string(),
+      "127.0.0.1:80"
+    );
+    assert_eq!(parse_bind("::", 80).unwrap().to_string(), "[::]:80");
+    assert_eq!(parse_bind("[::1]", 80).unwrap().to_string(), "[::1]:80");
+    assert_eq!(parse_bind("[::1]:8080", 80).unwrap().to_string(), "[::1]:8080");
+    assert!(parse_bind("localhost", 80).is_err());
+  }
+
+  #[test]
+  fn test_parse_size() {
+    assert_eq!(parse_size("512"), Ok(512));
+    assert_eq!(parse_size("64K"), Ok(64 * 1024));
+    assert_eq!(parse_size("10mb"), Ok(10 * 1024 * 1024));
+    assert_eq!(parse_size("1GiB"), Ok(1024 * 1024 * 1024));
+    assert!(parse_size("1X").is_err());
+    assert!(parse_size("M").is_err());
+  }
+
+  #[test]
+  fn test_from_file() {
+    let dir = test_utils::fixture_dir("config");
+    let path = dir.join("i6-http.toml");
+    std::fs::write(
+      &path,
+      "bind = [\"::\", \"0.0.0.0:8080\"]\nroot = \"dist\"\n",
+    )
+    .unwrap();
+
+    let config = ServerConfig::from_file(&path).unwrap();
+    assert_eq!(config.root, PathBuf::from("dist"));
+    assert_eq!(config.port, DEFAULT_PORT);
+    assert_eq!(
+      config.addresses().unwrap(),
+      vec![
+        SocketAddr::from(([0u16; 8], DEFAULT_PORT)),
+        SocketAddr::from(([0, 0, 0, 0], 8080)),
+      ]
+    );
+
+    std::fs::write(&path, "listen = 1\n").unwrap();
+    assert!(ServerConfig::from_file(&path).is_err());
+
+    std::fs::remove_dir_all(dir).unwrap();
+  }
+}
@@ -0,0 +1,236 @@
+//! Cross-origin resource sharing for `--cors`, so that a page served from
+//! another port can fetch files from this server.
+//!
+//! Any origin is answered with `Access-Control-Allow-Origin: *`, which
+//! browsers never combine with credentials. Listed origins are echoed back
+//! and may send credentials, for servers that also require `--auth`.
+
+use std::sync::Arc;
+use warp::filters::BoxedFilter;
+use warp::http::header::{self, HeaderValue};
+use warp::http::{Method, StatusCode};
+use warp::reply::Response;
+use warp::{Filter, Rejection, Reply};
+
+use crate::filters;
+
+/// How long browsers may cache a preflight response, in seconds.
+const MAX_AGE: &str = "600";
+
+/// Response headers that scripts may read besides the safelisted ones,
+/// for partial and conditional requests.
+const EXPOSE_HEADERS: &str = "Accept-Ranges, Content-Range, ETag";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cors {
+  /// Allowed origins like `http://localhost:5173`, `None` for any.
+  origins: Option<Vec<String>>,
+}
+
+impl Cors {
+  /// Allows `origins`, or any origin if they contain `*`.
+  pub fn new(origins: &[String]) -> Self {
+    let origins = origins
+      .iter()
+      .flat_map(|origins| origins.split(','))
+      .map(|origin| origin.trim().trim_end_matches('/').to_owned())
+      .filter(|origin| !origin.is_empty())
+      .collect::<Vec<_>>();
+
+    if origins.is_empty() || origins.iter().any(|origin| origin == "*") {
+      Self { origins: None }
+    } else {
+      Self { origins: Some(origins) }
+    }
+  }
+
+  /// Returns `Access-Control-Allow-Origin` for a request from `origin`, or
+  /// `None` if it is not allowed.
+  fn allow_origin(&self, origin: Option<&str>) -> Option<HeaderValue> {
+    match &self.origins {
+      None => Some(HeaderValue::from_static("*")),
+      Some(origins) => {
+        let origin = origin?;
+        origins
+          .iter()
+          .any(|allowed| allowed.eq_ignore_ascii_case(origin))
+          .then(|| HeaderValue::from_str(origin).ok())
+          .flatten()
+      }
+    }
+  }
+
+  /// Adds the headers shared by preflight and actual responses.
+  fn add_headers(&self, origin: Option<&str>, response: &mut Response) {
+    let headers = response.headers_mut();
+
+    // The answer depends on the origin, which caches must know about.
+    if self.origins.is_some() {
+      headers.append(header::VARY, HeaderValue::from_static("Origin"));
+    }
+
+    if let Some(allow_origin) = self.allow_origin(origin) {
+      headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, allow_origin);
+      if self.origins.is_some() {
+        headers.insert(
+          header::ACCESS_CONTROL_ALLOW_CREDENTIALS,
+          HeaderValue::from_static("true"),
+        );
+      }
+    }
+  }
+}
+
+/// Answers a preflight, which browsers send without credentials, so it must
+/// not require authentication. The requested method and headers are allowed
+/// as they are, since the actual request is checked like any other.
+fn preflight(
+  cors: Arc<Cors>,
+) -> impl Filter<Extract = (Response,), Error = Rejection> + Clone {
+  filters::method_is(Method::OPTIONS)
+    .and(warp::header::optional::<String>("origin"))
+    .and(warp::header::optional::<HeaderValue>("access-control-request-method"))
+    .and(warp::header::optional::<HeaderValue>(
+      "access-control-request-headers",
+    ))
+    .and_then(move |origin: Option<String>, method, request_headers| {
+      let cors = cors.clone();
+      async move {
+        // Plain `OPTIONS` requests are left to the other routes.
+        let (Some(origin), Some(method)) = (origin, method) else {
+          return Err(warp::reject::not_found());
+        };
+
+        if cors.allow_origin(Some(&origin)).is_none() {
+          return Ok(
+            warp::reply::with_status(
+              "Origin is not allowed\n",
+              StatusCode::FORBIDDEN,
+            )
+            .into_response(),
+          );
+        }
+
+        let mut response = StatusCode::NO_CONTENT.into_response();
+        cors.add_headers(Some(&origin), &mut response);
+        let headers = response.headers_mut();
+        headers.insert(header::ACCESS_CONTROL_ALLOW_METHODS, method);
+        if let Some(request_headers) = request_headers {
+          headers.insert(header::ACCESS_CONTROL_ALLOW_HEADERS, request_headers);
+        }
+        headers.insert(
+          header::ACCESS_CONTROL_MAX_AGE,
+          HeaderValue::from_static(MAX_AGE),
+        );
+
+        Ok(response)
+      }
+    })
+}
+
+/// Answers preflight requests and adds CORS headers to the responses of
+/// `routes`, including errors, so that scripts can read why a request
+/// failed.
+pub fn cors(
+  cors: Cors,
+  routes: BoxedFilter<(Response,)>,
+) -> impl Filter<Extract = (Response,), Error = Rejection> + Clone {
+  let cors = Arc::new(cors);
+
+  preflight(cors.clone())
+    .or(warp::header::optional::<String>("origin").and(routes).map(
+      move |origin: Option<String>, mut response: Response| {
+        cors.add_headers(origin.as_deref(), &mut response);
+        response.headers_mut().insert(
+          header::ACCESS_CONTROL_EXPOSE_HEADERS,
+          HeaderValue::from_static(EXPOSE_HEADERS),
+        );
+        response
+      },
+    ))
+    .unify()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn routes(origins: &[&str]) -> BoxedFilter<(Response,)> {
+    let origins: Vec<String> = origins.iter().map(|o| o.to_string()).collect();
+    let files = warp::path("file")
+      .map(|| "contents".into_response())
+      .or(warp::any().map(|| StatusCode::UNAUTHORIZED.into_response()))
+      .unify()
+      .boxed();
+
+    cors(Cors::new(&origins), files).boxed()
+  }
+
+  #[tokio::test]
+  async fn test_any_origin() {
+    let routes = routes(&["*"]);
+
+    let res = warp::test::request()
+      .path("/file")
+      .header("origin", "http://localhost:5173")
+      .reply(&routes)
+      .await;
+    assert_eq!(res.headers()["access-control-allow-origin"], "*");
+    assert!(!res.headers().contains_key("access-control-allow-credentials"));
+    assert_eq!(res.body(), "contents");
+
+    let res = warp::test::request()
+      .method("OPTIONS")
+      .path("/file")
+      .header("origin", "http://localhost:5173")
+      .header("access-control-request-method", "PUT")
+      .header("access-control-request-headers", "content-type")
+      .reply(&routes)
+      .await;
+    assert_eq!(res.status(), 204);
+    assert_eq!(res.headers()["access-control-allow-methods"], "PUT");
+    assert_eq!(res.headers()["access-control-allow-headers"], "content-type");
+
+    // Not a preflight.
+    let res = warp::test::request()
+      .method("OPTIONS")
+      .path("/file")
+      .reply(&routes)
+      .await;
+    assert_eq!(res.status(), 200);
+  }
+
+  #[tokio::test]
+  async fn test_listed_origins() {
+    let routes = routes(&["http://localhost:5173/, http://127.0.0.1:5173"]);
+
+    let res = warp::test::request()
+      .path("/missing")
+      .header("origin", "http://127.0.0.1:5173")
+      .reply(&routes)
+      .await;
+    assert_eq!(res.status(), 401);
+    assert_eq!(
+      res.headers()["access-control-allow-origin"],
+      "http://127.0.0.1:5173"
+    );
+    assert_eq!(res.headers()["access-control-allow-credentials"], "true");
+    assert_eq!(res.headers()["vary"], "Origin");
+
+    let res = warp::test::request()
+      .path("/file")
+      .header("origin", "http://evil.test")
+      .reply(&routes)
+      .await;
+    assert!(!res.headers().contains_key("access-control-allow-origin"));
+
+    let res = warp::test::request()
+      .method("OPTIONS")
+      .path("/file")
+      .header("origin", "http://evil.test")
+      .header("access-control-request-method", "GET")
+      .reply(&routes)
+      .await;
+    assert_eq!(res.status(), 403);
+  }
+}
@@ -0,0 +1,128 @@
+//! Fallbacks for requests that no file matches: `index.html` for the client
+//! side routes of single-page applications, and a custom 404 page.
+
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::Arc;
+use warp::http::{header, HeaderMap, HeaderValue, Method, StatusCode};
+use warp::path::FullPath;
+use warp::reply::Response;
+use warp::{Filter, Rejection, Reply};
+
+use crate::files::{self, FileOptions};
+
+/// Whether the last segment of `path` looks like a file name, in which case
+/// a missing file should stay a 404.
+fn has_extension(path: &str) -> bool {
+  path.rsplit('/').next().is_some_and(|segment| segment.contains('.'))
+}
+
+/// Serves `index.html` of `root`, which must be canonical, for `GET` and
+/// `HEAD` requests of paths without a file extension.
+pub fn spa(
+  root: PathBuf,
+  options: FileOptions,
+) -> impl Filter<Extract = (Response,), Error = Rejection> + Clone {
+  let root = Arc::new(root);
+  let options = Arc::new(options);
+
+  warp::get()
+    .or(warp::head())
+    .unify()
+    .and(warp::method())
+    .and(warp::path::full())
+    .and(warp::header::headers_cloned())
+    .and_then(move |method: Method, path: FullPath, headers: HeaderMap| {
+      let root = root.clone();
+      let options = options.clone();
+      async move {
+        if has_extension(path.as_str()) {
+          return Err(warp::reject::not_found());
+        }
+
+        let index = root.join("index.html");
+        let metadata = tokio::fs::metadata(&index)
+          .await
+          .ok()
+          .filter(|metadata| metadata.is_file())
+          .ok_or_else(warp::reject::not_found)?;
+
+        Ok(
+          files::serve_file(
+            &root, index, metadata, &method, &headers, &options,
+          )
+          .await,
+        )
+      }
+    })
+}
+
+async fn not_found_response(page: &Path) -> Option<Response> {
+  let contents = tokio::fs::read(page).await.ok()?;
+  let mime = mime_guess::from_path(page).first_or_octet_stream();
+
+  let mut response =
+    warp::reply::with_status(contents, StatusCode::NOT_FOUND).into_response();
+  if let Ok(value) = HeaderValue::from_str(mime.as_ref()) {
+    response.headers_mut().insert(header::CONTENT_TYPE, value);
+  }
+
+  Some(response)
+}
+
+pub type Recovered =
+  Pin<Box<dyn Future<Output = Result<Response, Rejection>> + Send>>;
+
+/// Returns a handler for `Filter::recover` that answers not found rejections
+/// with the contents of `page`, read on every request so edits show up.
+pub fn not_found_page(
+  page: PathBuf,
+) -> impl Fn(Rejection) -> Recovered + Clone {
+  let page = Arc::new(page);
+
+  move |rejection: Rejection| {
+    let page = page.clone();
+    Box::pin(async move {
+      if !rejection.is_not_found() {
+        return Err(rejection);
+      }
+
+      match not_found_response(&page).await {
+        Some(response) => Ok(response),
+        None => {
+          eprintln!("Warning: cannot read 404 page {}", page.display());
+          Err(rejection)
+        }
+      }
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::test_utils;
+
+  #[tokio::test]
+  async fn test_fallbacks() {
+    let root = test_utils::fixture_dir("fallback");
+    std::fs::write(root.join("index.html"), "<div id=app>").unwrap();
+    std::fs::write(root.join("404.html"), "gone").unwrap();
+
+    let filter = spa(root.clone(), FileOptions::default())
+      .recover(not_found_page(root.join("404.html")))
+      .unify();
+
+    let res = warp::test::request().path("/users/5").reply(&filter).await;
+    assert_eq!(res.status(), 200);
+    assert_eq!(res.body(), "<div id=app>");
+
+    let res = warp::test::request().path("/missing.js").reply(&filter).await;
+    assert_eq!(res.status(), 404);
+    assert_eq!(res.body(), "gone");
+    assert_eq!(res.headers()["content-type"], "text/html");
+
+    std::fs::remove_dir_all(root).unwrap();
+  }
+}
@@ -0,0 +1,531 @@
+//! Serves files with validators, conditional requests and byte ranges, so
+//! that browsers can seek in media and interrupted downloads can resume.
+
+use futures_util::stream::{self, StreamExt, TryStreamExt};
+use headers::{
+  AcceptRanges, ContentLength, ContentRange, ContentType, ETag, HeaderMapExt,
+  IfMatch, IfModifiedSince, IfNoneMatch, IfRange, IfUnmodifiedSince,
+  LastModified, Range,
+};
+use mime_guess::Mime;
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, BufReader};
+use tokio_util::io::ReaderStream;
+use warp::http::{header, HeaderMap, HeaderValue, Method, StatusCode};
+use warp::hyper::body::{Body, Bytes};
+use warp::path::FullPath;
+use warp::reply::Response;
+use warp::{Filter, Rejection, Reply};
+
+use crate::compression::{self, Encoding};
+use crate::filters;
+
+const BUF_SIZE: usize = 64 * 1024;
+/// Requests for more ranges than this get the whole file.
+const MAX_RANGES: usize = 16;
+
+/// Validators and metadata of a served file.
+#[derive(Debug, Clone)]
+pub struct FileInfo {
+  pub len: u64,
+  pub modified: SystemTime,
+  pub etag: ETag,
+}
+
+impl FileInfo {
+  pub fn new(metadata: &std::fs::Metadata) -> Self {
+    Self::with_suffix(metadata, "")
+  }
+
+  /// Appends `suffix` to the entity tag, for representations that differ
+  /// from the file on disk.
+  pub fn with_suffix(metadata: &std::fs::Metadata, suffix: &str) -> Self {
+    let modified = metadata.modified().unwrap_or(UNIX_EPOCH);
+    let nanos =
+      modified.duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos();
+    let etag = format!("\"{:x}-{:x}{}\"", metadata.len(), nanos, suffix)
+      .parse()
+      .expect("hex digits and encoding tokens in quotes are a valid tag");
+
+    Self { len: metadata.len(), modified, etag }
+  }
+}
+
+/// Outcome of the conditional headers of a request.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Condition {
+  /// Send the whole file.
+  Full,
+  /// Send these inclusive byte ranges.
+  Partial(Vec<(u64, u64)>),
+  NotModified,
+  PreconditionFailed,
+  RangeNotSatisfiable,
+}
+
+/// Resolves a `Range` header to inclusive byte ranges within `len`. Returns
+/// `None` if the header should be ignored and `Some` of an empty list if no
+/// range is satisfiable.
+fn satisfiable_ranges(range: &Range, len: u64) -> Option<Vec<(u64, u64)>> {
+  let mut ranges = Vec::new();
+
+  for bounds in range.iter() {
+    let (start, end) = match bounds {
+      (Bound::Included(start), Bound::Included(end)) if start <= end => {
+        (start, end.min(len.saturating_sub(1)))
+      }
+      (Bound::Included(start), Bound::Unbounded) => {
+        (start, len.saturating_sub(1))
+      }
+      (Bound::Unbounded, Bound::Included(suffix)) if suffix > 0 => {
+        (len.saturating_sub(suffix), len.saturating_sub(1))
+      }
+      (Bound::Unbounded, Bound::Included(_)) => continue,
+      // Invalid specs make the whole header invalid.
+      _ => return None,
+    };
+
+    if start < len {
+      ranges.push((start, end));
+    }
+  }
+
+  (ranges.len() <= MAX_RANGES).then_some(ranges)
+}
+
+/// Evaluates the preconditions and range of a request in the order of
+/// RFC 9110, section 13.2.2.
+pub fn evaluate(
+  method: &Method,
+  headers: &HeaderMap,
+  info: &FileInfo,
+) -> Condition {
+  let last_modified = LastModified::from(info.modified);
+
+  if let Some(if_match) = headers.typed_get::<IfMatch>() {
+    if !if_match.precondition_passes(&info.etag) {
+      return Condition::PreconditionFailed;
+    }
+  } else if let Some(since) = headers.typed_get::<IfUnmodifiedSince>() {
+    if !since.precondition_passes(info.modified) {
+      return Condition::PreconditionFailed;
+    }
+  }
+
+  let safe = method == Method::GET || method == Method::HEAD;
+  if let Some(if_none_match) = headers.typed_get::<IfNoneMatch>() {
+    if !if_none_match.precondition_passes(&info.etag) {
+      return if safe {
+        Condition::NotModified
+      } else {
+        Condition::PreconditionFailed
+      };
+    }
+  } else if let Some(since) = headers.typed_get::<IfModifiedSince>() {
+    if safe && !since.is_modified(info.modified) {
+      return Condition::NotModified;
+    }
+  }
+
+  if method != Method::GET {
+    return Condition::Full;
+  }
+
+  let Some(range) = headers.typed_get::<Range>() else {
+    return Condition::Full;
+  };
+
+  if let Some(if_range) = headers.typed_get::<IfRange>() {
+    if if_range.is_modified(Some(&info.etag), Some(&last_modified)) {
+      return Condition::Full;
+    }
+  }
+
+  match satisfiable_ranges(&range, info.len) {
+    None => Condition::Full,
+    Some(ranges) if ranges.is_empty() => Condition::RangeNotSatisfiable,
+    Some(ranges) => Condition::Partial(ranges),
+  }
+}
+
+/// Streams the inclusive byte range `start..=end` of `path`, opening the
+/// file when the body is first polled.
+fn range_stream(
+  path: Arc<PathBuf>,
+  start: u64,
+  end: u64,
+) -> impl futures_util::Stream<Item = std::io::Result<Bytes>> + Send {
+  stream::once(async move {
+    let mut file = tokio::fs::File::open(path.as_path()).await?;
+    file.seek(std::io::SeekFrom::Start(start)).await?;
+    Ok::<_, std::io::Error>(ReaderStream::with_capacity(
+      file.take(end + 1 - start),
+      BUF_SIZE,
+    ))
+  })
+  .try_flatten()
+}
+
+fn boundary(info: &FileInfo) -> String {
+  let nanos = SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .unwrap_or_default()
+    .subsec_nanos();
+  format!("i6-{:x}-{:x}", info.len, nanos)
+}
+
+/// What to send for a requested file.
+#[derive(Debug, Clone)]
+pub struct Representation {
+  /// The file to read, which may be a precompressed variant.
+  pub path: PathBuf,
+  /// Type of the requested file.
+  pub mime: Mime,
+  pub encoding: Option<Encoding>,
+  /// Compress `path` with `encoding` while sending it.
+  pub dynamic: bool,
+}
+
+/// Streams all of `path` compressed with `encoding`.
+fn encoded_stream(
+  path: Arc<PathBuf>,
+  encoding: Encoding,
+) -> impl futures_util::Stream<Item = std::io::Result<Bytes>> + Send {
+  stream::once(async move {
+    let file = tokio::fs::File::open(path.as_path()).await?;
+    Ok::<_, std::io::Error>(
+      encoding.encode(BufReader::with_capacity(BUF_SIZE, file)),
+    )
+  })
+  .try_flatten()
+}
+
+/// Builds the response for a representation once its preconditions are
+/// evaluated.
+pub fn file_response(
+  representation: Representation,
+  info: &FileInfo,
+  condition: Condition,
+  cache_control: Option<&HeaderValue>,
+) -> Response {
+  let mut status = StatusCode::OK;
+  let mut body = Body::empty();
+  let mut headers = HeaderMap::new();
+  headers.typed_insert(info.etag.clone());
+  headers.typed_insert(LastModified::from(info.modified));
+  headers.insert(header::VARY, HeaderValue::from_static("accept-encoding"));
+  if !representation.dynamic {
+    headers.typed_insert(AcceptRanges::bytes());
+  }
+  if let Some(cache_control) = cache_control {
+    headers.insert(header::CACHE_CONTROL, cache_control.clone());
+  }
+  if let Some(encoding) = representation.encoding {
+    headers.insert(
+      header::CONTENT_ENCODING,
+      HeaderValue::from_static(encoding.token()),
+    );
+  }
+
+  let mime = ContentType::from(representation.mime.clone());
+  let path = Arc::new(representation.path);
+
+  match condition {
+    Condition::NotModified => {
+      status = StatusCode::NOT_MODIFIED;
+    }
+    Condition::PreconditionFailed => {
+      status = StatusCode::PRECONDITION_FAILED;
+    }
+    Condition::RangeNotSatisfiable => {
+      status = StatusCode::RANGE_NOT_SATISFIABLE;
+      headers.typed_insert(ContentRange::unsatisfied_bytes(info.len));
+    }
+    Condition::Full if representation.dynamic => {
+      headers.typed_insert(mime);
+      let encoding =
+        representation.encoding.expect("dynamic representations are encoded");
+      body = Body::wrap_stream(encoded_stream(path, encoding));
+    }
+    Condition::Full => {
+      headers.typed_insert(mime);
+      headers.typed_insert(ContentLength(info.len));
+      if info.len > 0 {
+        body = Body::wrap_stream(range_stream(path, 0, info.len - 1));
+      }
+    }
+    Condition::Partial(ranges) if ranges.len() == 1 => {
+      let (start, end) = ranges[0];
+      status = StatusCode::PARTIAL_CONTENT;
+      headers.typed_insert(mime);
+      headers.typed_insert(ContentLength(end + 1 - start));
+      headers.typed_insert(
+        ContentRange::bytes(start..=end, info.len)
+          .expect("satisfiable ranges are valid"),
+      );
+      body = Body::wrap_stream(range_stream(path, start, end));
+    }
+    Condition::Partial(ranges) => {
+      let boundary = boundary(info);
+      let mime = &representation.mime;
+
+      let mut len = 0;
+      let mut parts = Vec::new();
+      for (i, (start, end)) in ranges.into_iter().enumerate() {
+        let head = format!(
+          "{}--{}\r\nContent-Type: {}\r\nContent-Range: bytes {}-{}/{}\r\n\r\n",
+          if i == 0 { "" } else { "\r\n" },
+          boundary,
+          mime,
+          start,
+          end,
+          info.len
+        );
+        len += head.len() as u64 + end + 1 - start;
+        parts.push(
+          stream::once(async move { Ok(Bytes::from(head)) })
+            .chain(range_stream(path.clone(), start, end))
+            .boxed(),
+        );
+      }
+      let tail = format!("\r\n--{}--\r\n", boundary);
+      len += tail.len() as u64;
+      parts.push(stream::once(async move { Ok(Bytes::from(tail)) }).boxed());
+
+      status = StatusCode::PARTIAL_CONTENT;
+      headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_str(&format!(
+          "multipart/byteranges; boundary={}",
+          boundary
+        ))
+        .expect("boundary is a valid header value"),
+      );
+      headers.typed_insert(ContentLength(len));
+      body = Body::wrap_stream(stream::iter(parts).flatten());
+    }
+  }
+
+  let mut response = Response::new(body);
+  *response.status_mut() = status;
+  *response.headers_mut() = headers;
+  response
+}
+
+enum Resolved {
+  File(PathBuf, std::fs::Metadata),
+  /// A directory with an index, requested without the trailing slash that
+  /// relative links in the index need.
+  Redirect(String),
+}
+
+/// Finds the file for a request path, `index.html` for directories.
+async fn resolve(root: &Path, path: &FullPath) -> Option<Resolved> {
+  let relative = filters::relative_path(path.as_str())?;
+  let mut file = root.join(relative);
+
+  let mut metadata = tokio::fs::metadata(&file).await.ok()?;
+  if metadata.is_dir() {
+    file.push("index.html");
+    metadata = tokio::fs::metadata(&file).await.ok()?;
+    if !path.as_str().ends_with('/') {
+      return Some(Resolved::Redirect(format!("{}/", path.as_str())));
+    }
+  }
+
+  metadata.is_file().then_some(Resolved::File(file, metadata))
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct FileOptions {
+  /// `Cache-Control` of every file.
+  pub cache_control: Option<HeaderValue>,
+  /// Compress text-like files on the fly for clients that accept it.
+  pub compress: bool,
+}
+
+/// Picks a precompressed variant of `file` or an encoding to compress it
+/// with, according to the `Accept-Encoding` of the request.
+async fn negotiate(
+  root: &Path,
+  file: PathBuf,
+  metadata: std::fs::Metadata,
+  headers: &HeaderMap,
+  compress: bool,
+) -> (Representation, FileInfo) {
+  let mime = mime_guess::from_path(&file).first_or_octet_stream();
+  let accepted = compression::accepted(
+    headers.get(header::ACCEPT_ENCODING).and_then(|v| v.to_str().ok()),
+  );
+
+  for &encoding in &accepted {
+    let mut variant = file.clone().into_os_string();
+    variant.push(".");
+    variant.push(encoding.extension());
+    let variant = PathBuf::from(variant);
+
+    // Variants are not checked by `filters::confined`, keep them in root.
+    let Ok(canonical) = tokio::fs::canonicalize(&variant).await else {
+      continue;
+    };
+    let Ok(variant_metadata) = tokio::fs::metadata(&canonical).await else {
+      continue;
+    };
+    if canonical.starts_with(root) && variant_metadata.is_file() {
+      let info = FileInfo::new(&variant_metadata);
+      let representation = Representation {
+        path: variant,
+        mime,
+        encoding: Some(encoding),
+        dynamic: false,
+      };
+      return (representation, info);
+    }
+  }
+
+  let dynamic = accepted.first().copied().filter(|_| {
+    compress
+      && !headers.contains_key(header::RANGE)
+      && metadata.len() >= compression::MIN_COMPRESS_SIZE
+      && compression::is_compressible(&mime)
+  });
+
+  match dynamic {
+    Some(encoding) => {
+      let suffix = format!("-{}", encoding.token());
+      let info = FileInfo::with_suffix(&metadata, &suffix);
+      let representation = Representation {
+        path: file,
+        mime,
+        encoding: Some(encoding),
+        dynamic: true,
+      };
+      (representation, info)
+    }
+    None => {
+      let info = FileInfo::new(&metadata);
+      let representation =
+        Representation { path: file, mime, encoding: None, dynamic: false };
+      (representation, info)
+    }
+  }
+}
+
+/// Responds to a request for `file` below `root`, which must be canonical.
+pub async fn serve_file(
+  root: &Path,
+  file: PathBuf,
+  metadata: std::fs::Metadata,
+  method: &Method,
+  headers: &HeaderMap,
+  options: &FileOptions,
+) -> Response {
+  let (representation, info) =
+    negotiate(root, file, metadata, headers, options.compress).await;
+  let condition = evaluate(method, headers, &info);
+
+  file_response(
+    representation,
+    &info,
+    condition,
+    options.cache_control.as_ref(),
+  )
+}
+
+/// Serves the files below `root`, which must be canonical.
+pub fn files(
+  root: PathBuf,
+  options: FileOptions,
+) -> impl Filter<Extract = (Response,), Error = Rejection> + Clone {
+  let root = Arc::new(root);
+  let options = Arc::new(options);
+
+  warp::get()
+    .or(warp::head())
+    .unify()
+    .and(warp::method())
+    .and(warp::path::full())
+    .and(warp::header::headers_cloned())
+    .and_then(move |method: Method, path: FullPath, headers: HeaderMap| {
+      let root = root.clone();
+      let options = options.clone();
+      async move {
+        let (file, metadata) = match resolve(&root, &path).await {
+          Some(Resolved::File(file, metadata)) => (file, metadata),
+          Some(Resolved::Redirect(location)) => {
+            let uri = location
+              .parse::<warp::http::Uri>()
+              .map_err(|_| warp::reject::not_found())?;
+            return Ok(warp::redirect::redirect(uri).into_response());
+          }
+          None => return Err(warp::reject::not_found()),
+        };
+
+        Ok::<_, Rejection>(
+          serve_file(&root, file, metadata, &method, &headers, &options).await,
+        )
+      }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn info() -> FileInfo {
+    FileInfo {
+      len: 100,
+      modified: UNIX_EPOCH + std::time::Duration::from_secs(1_000_000),
+      etag: "\"abc\"".parse().unwrap(),
+    }
+  }
+
+  fn eval(pairs: &[(&str, &str)]) -> Condition {
+    let mut headers = HeaderMap::new();
+    for (name, value) in pairs {
+      headers.insert(
+        header::HeaderName::from_bytes(name.as_bytes()).unwrap(),
+        value.parse().unwrap(),
+      );
+    }
+    evaluate(&Method::GET, &headers, &info())
+  }
+
+  #[test]
+  fn test_evaluate() {
+    assert_eq!(eval(&[]), Condition::Full);
+    assert_eq!(
+      eval(&[("range", "bytes=0-9")]),
+      Condition::Partial(vec![(0, 9)])
+    );
+    assert_eq!(
+      eval(&[("range", "bytes=90-,-5,0-0")]),
+      Condition::Partial(vec![(90, 99), (95, 99), (0, 0)])
+    );
+    assert_eq!(
+      eval(&[("range", "bytes=200-")]),
+      Condition::RangeNotSatisfiable
+    );
+    assert_eq!(eval(&[("range", "bytes=9-0")]), Condition::Full);
+    assert_eq!(eval(&[("if-none-match", "W/\"abc\"")]), Condition::NotModified);
+    assert_eq!(
+      eval(&[("if-match", "\"other\"")]),
+      Condition::PreconditionFailed
+    );
+    assert_eq!(
+      eval(&[("if-modified-since", "Mon, 12 Jan 1970 13:46:40 GMT")]),
+      Condition::NotModified
+    );
+    assert_eq!(
+      eval(&[("range", "bytes=0-9"), ("if-range", "\"other\"")]),
+      Condition::Full
+    );
+    assert_eq!(
+      eval(&[("range", "bytes=0-9"), ("if-range", "\"abc\"")]),
+      Condition::Partial(vec![(0, 9)])
+    );
+  }
+}
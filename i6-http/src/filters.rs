@@ -0,0 +1,197 @@
+use percent_encoding::percent_decode_str;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use warp::http::header::{HeaderName, HeaderValue};
+use warp::http::{Method, StatusCode};
+use warp::path::FullPath;
+use warp::reply::Response;
+use warp::{Filter, Rejection, Reply};
+
+/// Returns the request path relative to the served root, or `None` if it
+/// contains `..` or is not valid UTF-8 after decoding.
+pub fn relative_path(path: &str) -> Option<PathBuf> {
+  let decoded = percent_decode_str(path).decode_utf8().ok()?;
+
+  let mut relative = PathBuf::new();
+  for segment in decoded.split('/') {
+    match segment {
+      "" | "." => {}
+      ".." => return None,
+      segment if segment.contains('\\') => return None,
+      segment => relative.push(segment),
+    }
+  }
+
+  Some(relative)
+}
+
+pub fn is_hidden(relative: &Path) -> bool {
+  relative
+    .components()
+    .any(|c| c.as_os_str().to_string_lossy().starts_with('.'))
+}
+
+/// Rejects requests for hidden files unless `hidden` is set, and for paths
+/// that resolve outside of `root` through symlinks unless `follow_symlinks`
+/// is set. `root` must be canonical.
+pub fn confined(
+  root: PathBuf,
+  hidden: bool,
+  follow_symlinks: bool,
+) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+  let root = Arc::new(root);
+
+  warp::path::full()
+    .and_then(move |path: FullPath| {
+      let root = root.clone();
+      async move {
+        let relative =
+          relative_path(path.as_str()).ok_or_else(warp::reject::not_found)?;
+
+        if !hidden && is_hidden(&relative) {
+          return Err(warp::reject::not_found());
+        }
+
+        if !follow_symlinks {
+          // Paths that do not exist are left to the file server to reject.
+          if let Ok(target) =
+            tokio::fs::canonicalize(root.join(&relative)).await
+          {
+            if !target.starts_with(root.as_path()) {
+              return Err(warp::reject::not_found());
+            }
+          }
+        }
+
+        Ok(())
+      }
+    })
+    .untuple_one()
+}
+
+/// Address of the client, set as a request extension by listeners that warp
+/// does not serve itself, like HTTP/3.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RemoteAddr(pub SocketAddr);
+
+/// Like `warp::addr::remote()`, but also sees a `RemoteAddr` extension.
+pub fn remote(
+) -> impl Filter<Extract = (Option<SocketAddr>,), Error = Infallible> + Clone {
+  warp::addr::remote().and(warp::ext::optional::<RemoteAddr>()).map(
+    |remote: Option<SocketAddr>, extension: Option<RemoteAddr>| {
+      remote.or(extension.map(|RemoteAddr(addr)| addr))
+    },
+  )
+}
+
+/// Like `warp::put()` and friends, but rejects other methods as not found.
+/// Otherwise a `GET` of a missing file answers `405 Method Not Allowed` once
+/// a route for another method is added.
+pub fn method_is(
+  method: Method,
+) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+  warp::method()
+    .and_then(move |request: Method| {
+      let matches = request == method;
+      async move {
+        if matches {
+          Ok(())
+        } else {
+          Err(warp::reject::not_found())
+        }
+      }
+    })
+    .untuple_one()
+}
+
+/// Status that warp answers a rejection with.
+pub fn rejection_status(rejection: &Rejection) -> StatusCode {
+  use warp::reject::{
+    InvalidHeader, InvalidQuery, LengthRequired, MethodNotAllowed,
+    MissingHeader, PayloadTooLarge, UnsupportedMediaType,
+  };
+
+  if rejection.is_not_found() {
+    StatusCode::NOT_FOUND
+  } else if rejection.find::<MethodNotAllowed>().is_some() {
+    StatusCode::METHOD_NOT_ALLOWED
+  } else if rejection.find::<PayloadTooLarge>().is_some() {
+    StatusCode::PAYLOAD_TOO_LARGE
+  } else if rejection.find::<LengthRequired>().is_some() {
+    StatusCode::LENGTH_REQUIRED
+  } else if rejection.find::<UnsupportedMediaType>().is_some() {
+    StatusCode::UNSUPPORTED_MEDIA_TYPE
+  } else if rejection.find::<MissingHeader>().is_some()
+    || rejection.find::<InvalidHeader>().is_some()
+    || rejection.find::<InvalidQuery>().is_some()
+  {
+    StatusCode::BAD_REQUEST
+  } else {
+    StatusCode::INTERNAL_SERVER_ERROR
+  }
+}
+
+/// Answers every rejection like warp would, so that the responses can still
+/// be changed by filters around the routes, such as CORS.
+pub async fn recover(rejection: Rejection) -> Result<Response, Rejection> {
+  let status = rejection_status(&rejection);
+  if status == StatusCode::NOT_FOUND {
+    return Ok(status.into_response());
+  }
+
+  let reason = status.canonical_reason().unwrap_or_default();
+  Ok(warp::reply::with_status(format!("{}\n", reason), status).into_response())
+}
+
+/// Parses a response header given as `Name: value`.
+pub fn parse_header(line: &str) -> Result<(HeaderName, HeaderValue), String> {
+  let invalid = || format!("Invalid header {:?}, expected 'Name: value'", line);
+
+  let (name, value) = line.split_once(':').ok_or_else(invalid)?;
+  let name =
+    HeaderName::from_bytes(name.trim().as_bytes()).map_err(|_| invalid())?;
+  let value = HeaderValue::from_str(value.trim()).map_err(|_| invalid())?;
+
+  Ok((name, value))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_relative_path() {
+    assert_eq!(relative_path("/a/b%20c.txt"), Some(PathBuf::from("a/b c.txt")));
+    assert_eq!(relative_path("/"), Some(PathBuf::new()));
+    assert_eq!(relative_path("/a/../../etc/passwd"), None);
+    assert_eq!(relative_path("/a/%2e%2e/b"), None);
+    assert!(is_hidden(&relative_path("/a/.git/config").unwrap()));
+  }
+
+  #[test]
+  fn test_parse_header() {
+    let (name, value) =
+      parse_header("Cross-Origin-Opener-Policy: same-origin").unwrap();
+    assert_eq!(name, "cross-origin-opener-policy");
+    assert_eq!(value, "same-origin");
+    assert_eq!(parse_header("X-Empty:").unwrap().1, "");
+    assert!(parse_header("no colon").is_err());
+    assert!(parse_header("Bad Name: value").is_err());
+  }
+
+  #[tokio::test]
+  async fn test_remote() {
+    let addr: SocketAddr = ([192, 0, 2, 1], 443).into();
+    let filter = remote();
+
+    assert_eq!(warp::test::request().filter(&filter).await.unwrap(), None);
+    let remote = warp::test::request()
+      .extension(RemoteAddr(addr))
+      .filter(&filter)
+      .await
+      .unwrap();
+    assert_eq!(remote, Some(addr));
+  }
+}
@@ -0,0 +1,219 @@
+//! HTTP/3 over QUIC for `i6 https --http3`, on the UDP port of each https
+//! listener. Browsers connect over TCP first and switch once a response
+//! advertised HTTP/3 with `Alt-Svc`.
+//!
+//! Requests are converted to the types of warp and run through the same
+//! routes, with the address of the client as a `RemoteAddr` extension.
+
+use h3::server::RequestResolver;
+use quinn::crypto::rustls::QuicServerConfig;
+use quinn::rustls;
+use quinn::rustls::pki_types::pem::PemObject;
+use quinn::rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use std::error::Error;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Arc;
+use tokio::task::JoinSet;
+use tokio_util::sync::CancellationToken;
+use warp::filters::BoxedFilter;
+use warp::http::header::{self, HeaderValue};
+use warp::hyper::body::{Buf, Bytes, HttpBody};
+use warp::hyper::service::Service;
+use warp::hyper::Body;
+use warp::reply::Response;
+use warp::Filter;
+
+use crate::filters::RemoteAddr;
+use crate::proxy::remove_hop_by_hop;
+
+/// How long browsers remember that HTTP/3 is available, in seconds.
+const ALT_SVC_MAX_AGE: u32 = 24 * 60 * 60;
+
+type BoxError = Box<dyn Error + Send + Sync>;
+
+/// Binds a QUIC endpoint at `addr` that serves the PEM certificate chain and
+/// private key at the given paths.
+pub fn bind(
+  addr: SocketAddr,
+  cert_path: &Path,
+  key_path: &Path,
+) -> Result<quinn::Endpoint, Box<dyn Error>> {
+  let read = |path: &Path| {
+    std::fs::read(path)
+      .map_err(|e| format!("Failed to read {}: {}", path.display(), e))
+  };
+
+  let certs = CertificateDer::pem_slice_iter(&read(cert_path)?)
+    .collect::<Result<Vec<_>, _>>()
+    .map_err(|e| {
+      format!("Invalid certificate {}: {}", cert_path.display(), e)
+    })?;
+  let key = PrivateKeyDer::from_pem_slice(&read(key_path)?)
+    .map_err(|e| format!("Invalid key {}: {}", key_path.display(), e))?;
+
+  let provider = Arc::new(rustls::crypto::ring::default_provider());
+  let mut tls = rustls::ServerConfig::builder_with_provider(provider)
+    .with_protocol_versions(&[&rustls::version::TLS13])?
+    .with_no_client_auth()
+    .with_single_cert(certs, key)?;
+  tls.alpn_protocols = vec![b"h3".to_vec()];
+
+  let config = quinn::ServerConfig::with_crypto(Arc::new(
+    QuicServerConfig::try_from(tls)?,
+  ));
+  let endpoint = quinn::Endpoint::server(config, addr)
+    .map_err(|e| format!("Failed to bind {} for HTTP/3: {}", addr, e))?;
+
+  Ok(endpoint)
+}
+
+/// Adds `Alt-Svc` to the responses of `routes`, announcing HTTP/3 on `port`.
+pub fn advertise(
+  port: u16,
+  routes: BoxedFilter<(Response,)>,
+) -> BoxedFilter<(Response,)> {
+  let alt_svc = format!("h3=\":{}\"; ma={}", port, ALT_SVC_MAX_AGE);
+  let alt_svc = HeaderValue::from_str(&alt_svc).unwrap();
+
+  routes
+    .map(move |mut response: Response| {
+      response.headers_mut().insert(header::ALT_SVC, alt_svc.clone());
+      response
+    })
+    .boxed()
+}
+
+/// Serves `routes` on `endpoint` until `shutdown`, then lets the open
+/// requests finish.
+pub async fn serve(
+  endpoint: quinn::Endpoint,
+  routes: BoxedFilter<(Response,)>,
+  shutdown: CancellationToken,
+) {
+  let mut connections = JoinSet::new();
+
+  loop {
+    // Finished connections are dropped as they go, not only at shutdown.
+    while connections.try_join_next().is_some() {}
+
+    let incoming = tokio::select! {
+      incoming = endpoint.accept() => incoming,
+      _ = shutdown.cancelled() => None,
+    };
+    let Some(incoming) = incoming else { break };
+
+    let routes = routes.clone();
+    let shutdown = shutdown.clone();
+    connections.spawn(async move {
+      if let Err(e) = connection(incoming, routes, shutdown).await {
+        tracing::debug!("HTTP/3 connection failed: {}", e);
+      }
+    });
+  }
+
+  while connections.join_next().await.is_some() {}
+  endpoint.wait_idle().await;
+}
+
+async fn connection(
+  incoming: quinn::Incoming,
+  routes: BoxedFilter<(Response,)>,
+  shutdown: CancellationToken,
+) -> Result<(), BoxError> {
+  let connection = incoming.await?;
+  let remote = connection.remote_address();
+  let connection = h3_quinn::Connection::new(connection);
+  let mut connection =
+    h3::server::Connection::<_, Bytes>::new(connection).await?;
+  let mut requests = JoinSet::new();
+  let mut closing = false;
+
+  loop {
+    while requests.try_join_next().is_some() {}
+
+    let accepted = tokio::select! {
+      accepted = connection.accept() => Some(accepted),
+      _ = shutdown.cancelled(), if !closing => None,
+    };
+
+    match accepted {
+      // Sends `GOAWAY`, after which `accept` ends once the requests that
+      // were already sent arrived.
+      None => {
+        closing = true;
+        connection.shutdown(0).await?;
+      }
+      Some(Ok(Some(resolver))) => {
+        let routes = routes.clone();
+        requests.spawn(async move {
+          if let Err(e) = request(resolver, routes, remote).await {
+            tracing::debug!("HTTP/3 request failed: {}", e);
+          }
+        });
+      }
+      Some(Ok(None)) => break,
+      Some(Err(e)) if e.is_h3_no_error() => break,
+      Some(Err(e)) => return Err(e.into()),
+    }
+  }
+
+  while requests.join_next().await.is_some() {}
+
+  Ok(())
+}
+
+async fn request(
+  resolver: RequestResolver<h3_quinn::Connection, Bytes>,
+  routes: BoxedFilter<(Response,)>,
+  remote: SocketAddr,
+) -> Result<(), BoxError> {
+  let (request, stream) = resolver.resolve_request().await?;
+  let (mut send, mut recv) = stream.split();
+
+  // The request body is passed on while the routes read it.
+  let (mut sender, body) = Body::channel();
+  tokio::spawn(async move {
+    while let Ok(Some(mut chunk)) = recv.recv_data().await {
+      let chunk = chunk.copy_to_bytes(chunk.remaining());
+      if sender.send_data(chunk).await.is_err() {
+        break;
+      }
+    }
+  });
+
+  let head = request.method() == http::Method::HEAD;
+  let mut builder = warp::http::Request::builder()
+    .method(request.method().as_str())
+    .uri(request.uri().to_string())
+    .version(warp::http::Version::HTTP_3)
+    .extension(RemoteAddr(remote));
+  for (name, value) in request.headers() {
+    builder = builder.header(name.as_str(), value.as_bytes());
+  }
+  // HTTP/3 sends the host as `:authority` only.
+  if let Some(authority) = request.uri().authority() {
+    if !request.headers().contains_key(http::header::HOST) {
+      builder = builder.header(header::HOST, authority.as_str());
+    }
+  }
+
+  let response = warp::service(routes).call(builder.body(body)?).await?;
+  let (mut parts, mut body) = response.into_parts();
+  remove_hop_by_hop(&mut parts.headers);
+
+  let mut response = http::Response::builder().status(parts.status.as_u16());
+  for (name, value) in &parts.headers {
+    response = response.header(name.as_str(), value.as_bytes());
+  }
+  send.send_response(response.body(())?).await?;
+
+  if !head {
+    while let Some(data) = body.data().await {
+      send.send_data(data?).await?;
+    }
+  }
+  send.finish().await?;
+
+  Ok(())
+}
@@ -1,3 +1,25 @@
+pub mod access_log;
+pub mod auth;
+pub mod cert;
+pub mod cli;
+pub mod compression;
+pub mod config;
+pub mod cors;
+pub mod fallback;
+pub mod files;
+pub mod filters;
+#[cfg(feature = "http3")]
+pub mod http3;
+pub mod listing;
+pub mod livereload;
+pub mod proxy;
+pub mod server;
+#[cfg(test)]
+mod test_utils;
+pub mod upload;
+pub mod webdav;
+
+use config::ServerConfig;
 use rcgen::{
   generate_simple_self_signed, BasicConstraints, Certificate,
   CertificateParams, DistinguishedName, DnType, IsCa,
@@ -5,10 +27,11 @@ use rcgen::{
 use std::error::Error;
 use std::fs::{File, OpenOptions};
 use std::io::Write;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use std::thread::sleep;
-use tracing_subscriber::fmt::format::FmtSpan;
-use warp::Filter;
+use warp::filters::BoxedFilter;
+use warp::{Filter, Reply};
 
 pub fn open_or_create_file(path: &str) -> Result<File, Box<dyn Error>> {
   let path = Path::new(path);
@@ -45,41 +68,203 @@ pub fn generate_self_signed_cert_key_files(
   Ok(())
 }
 
-pub fn create_server_http(port: u16) -> Result<(), Box<dyn Error>> {
-  tracing_subscriber::fmt().with_span_events(FmtSpan::CLOSE).init();
+/// Builds the routes for `config`, failing if the root cannot be served.
+pub fn routes(
+  config: &ServerConfig,
+) -> Result<BoxedFilter<(warp::reply::Response,)>, Box<dyn Error>> {
+  let root = config
+    .root
+    .canonicalize()
+    .map_err(|e| format!("Cannot serve {}: {}", config.root.display(), e))?;
 
-  let rt = tokio::runtime::Runtime::new()?;
-  rt.block_on(
-    warp::serve(warp::fs::dir(".").with(warp::trace::request()))
-      .run(([0, 0, 0, 0], port)),
-  );
+  let cache_control = config
+    .cache_control
+    .as_deref()
+    .map(warp::http::HeaderValue::from_str)
+    .transpose()
+    .map_err(|_| "Invalid Cache-Control value")?;
+
+  // Scripts cannot be injected into compressed pages.
+  let options = files::FileOptions {
+    cache_control,
+    compress: config.compress && !config.watch,
+  };
+  let files = files::files(root.clone(), options.clone()).boxed();
+  let files = if config.listing {
+    files
+      .or(listing::listing(
+        root.clone(),
+        config.hidden,
+        config.follow_symlinks,
+        config.upload,
+      ))
+      .unify()
+      .boxed()
+  } else {
+    files
+  };
+  let files = if config.upload {
+    let options = upload::UploadOptions {
+      max_size: config.max_upload_size,
+      overwrite: config.overwrite,
+      hidden: config.hidden,
+    };
+    files.or(upload::upload(root.clone(), options)).unify().boxed()
+  } else {
+    files
+  };
+  let files = if config.webdav {
+    let options = webdav::DavOptions {
+      writable: config.upload,
+      max_size: config.max_upload_size,
+      overwrite: config.overwrite,
+      hidden: config.hidden,
+      follow_symlinks: config.follow_symlinks,
+    };
+    // Before the uploads, whose `PUT` does not replace files by default.
+    webdav::webdav(root.clone(), options).or(files).unify().boxed()
+  } else {
+    files
+  };
+  let files = if config.spa {
+    files.or(fallback::spa(root.clone(), options)).unify().boxed()
+  } else {
+    files
+  };
+
+  let files = if config.watch {
+    let reload = livereload::LiveReload::watch(&root, &config.watch_ignore)?;
+    livereload::events(reload)
+      .or(files.and_then(livereload::inject))
+      .unify()
+      .boxed()
+  } else {
+    files
+  };
+
+  let files = filters::confined(root, config.hidden, config.follow_symlinks)
+    .and(files)
+    .boxed();
+  let auth = auth::Auth::from_config(config)?;
+  let files = if config.proxy.is_empty() {
+    files
+  } else {
+    let rules = config
+      .proxy
+      .iter()
+      .map(|rule| rule.parse())
+      .collect::<Result<Vec<proxy::ProxyRule>, _>>()?;
+    let timeout = std::time::Duration::from_secs(config.proxy_timeout);
+    proxy::proxy(rules, timeout, auth.is_some()).or(files).unify().boxed()
+  };
+  let files = match auth {
+    Some(auth) => auth::authenticated(Arc::new(auth))
+      .and(files)
+      .recover(auth::recover)
+      .unify()
+      .boxed(),
+    None => files,
+  };
+  let files = match &config.not_found {
+    Some(page) => {
+      files.recover(fallback::not_found_page(page.clone())).unify().boxed()
+    }
+    None => files,
+  };
+  // Rejections are only turned into responses by warp after all filters, so
+  // CORS and the custom headers would miss them.
+  let files = files.recover(filters::recover).unify().boxed();
+  let files = if config.cors.is_empty() {
+    files
+  } else {
+    cors::cors(cors::Cors::new(&config.cors), files).boxed()
+  };
+  let headers = config
+    .headers
+    .iter()
+    .map(|header| filters::parse_header(header))
+    .collect::<Result<warp::http::HeaderMap, _>>()?;
+  let files = if headers.is_empty() {
+    files
+  } else {
+    files
+      .with(warp::reply::with::headers(headers))
+      .map(Reply::into_response)
+      .boxed()
+  };
+
+  Ok(files)
+}
+
+/// Prints warnings of warp and hyper to stderr, unless the application
+/// already installed a subscriber.
+fn init_tracing() {
+  let _ = tracing_subscriber::fmt()
+    .with_max_level(tracing::Level::WARN)
+    .with_writer(std::io::stderr)
+    .try_init();
+}
+
+/// Prints where `server` serves `root`.
+fn print_addresses(
+  server: &server::Server,
+  root: &Path,
+  scheme: &str,
+) -> Result<(), Box<dyn Error>> {
+  let root = root.canonicalize()?;
+  for addr in server.local_addrs() {
+    println!("Serving {} at {}://{}", root.display(), scheme, addr);
+  }
 
   Ok(())
 }
 
+pub fn create_server_http(port: u16) -> Result<(), Box<dyn Error>> {
+  create_server_http_with_config(ServerConfig { port, ..Default::default() })
+}
+
+pub fn create_server_http_with_config(
+  config: ServerConfig,
+) -> Result<(), Box<dyn Error>> {
+  init_tracing();
+
+  let rt = tokio::runtime::Runtime::new()?;
+  rt.block_on(async {
+    let root = config.root.clone();
+    let server = server::Server::start(config).await?;
+    print_addresses(&server, &root, "http")?;
+    server.run_until_ctrl_c().await
+  })
+}
+
 pub fn create_server_https(port: u16) -> Result<(), Box<dyn Error>> {
-  let key_file_path = &format!(
-    "{}/i6-http-d4cd362e-89ef-4267-9e35-4cc8a79b60eb/key.pem",
-    std::env::temp_dir().to_str().unwrap_or(".")
-  );
+  create_server_https_with_config(ServerConfig { port, ..Default::default() })
+}
+
+pub fn create_server_https_with_config(
+  config: ServerConfig,
+) -> Result<(), Box<dyn Error>> {
+  let (cert_path, key_path) = match (&config.cert, &config.key) {
+    (Some(cert), Some(key)) => (cert.clone(), key.clone()),
+    (None, None) => cert::server_cert(&cert::sans_with_defaults(&config.san))?,
+    _ => return Err("Both a certificate and a key are required".into()),
+  };
 
-  let cert_file_path = &format!(
-    "{}/i6-http-d4cd362e-89ef-4267-9e35-4cc8a79b60eb/cert.pem",
-    std::env::temp_dir().to_str().unwrap_or(".")
-  );
+  for path in [&cert_path, &key_path] {
+    if !path.is_file() {
+      return Err(format!("Cannot read {}", path.display()).into());
+    }
+  }
 
-  generate_self_signed_cert_key_files(cert_file_path, key_file_path)?;
+  println!("Using certificate {}", cert_path.display());
 
-  tracing_subscriber::fmt().with_span_events(FmtSpan::CLOSE).init();
+  init_tracing();
 
   let rt = tokio::runtime::Runtime::new()?;
-  rt.block_on(
-    warp::serve(warp::fs::dir(".").with(warp::trace::request()))
-      .tls()
-      .cert_path(cert_file_path)
-      .key_path(key_file_path)
-      .run(([0, 0, 0, 0], port)),
-  );
-
-  Ok(())
+  rt.block_on(async {
+    let root = config.root.clone();
+    let server = server::Server::start_tls(config, cert_path, key_path).await?;
+    print_addresses(&server, &root, "https")?;
+    server.run_until_ctrl_c().await
+  })
 }
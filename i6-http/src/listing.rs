@@ -0,0 +1,401 @@
+//! Generated directory listings, as HTML or as JSON for clients that send
+//! `Accept: application/json`.
+
+use percent_encoding::{utf8_percent_encode, AsciiSet, CONTROLS};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
+use warp::http::{header, StatusCode, Uri};
+use warp::path::FullPath;
+use warp::reply::Response;
+use warp::{Filter, Rejection, Reply};
+
+use crate::filters;
+
+/// Characters escaped in the links of a listing, on top of the controls.
+/// Names with `:` could otherwise be read as a scheme like `javascript:`.
+pub const LINK: &AsciiSet = &CONTROLS
+  .add(b' ')
+  .add(b'"')
+  .add(b'#')
+  .add(b'%')
+  .add(b'&')
+  .add(b'\'')
+  .add(b':')
+  .add(b'<')
+  .add(b'>')
+  .add(b'?');
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ListingEntry {
+  pub name: String,
+  pub is_dir: bool,
+  pub size: u64,
+  /// Seconds since the Unix epoch.
+  pub mtime: u64,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SortKey {
+  #[default]
+  Name,
+  Size,
+  Mtime,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+  #[default]
+  Asc,
+  Desc,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(default)]
+pub struct SortQuery {
+  pub sort: SortKey,
+  pub order: SortOrder,
+}
+
+/// Reads the entries of `dir`, skipping hidden ones unless `hidden` is set,
+/// and ones that resolve outside of `confine_to` when given.
+pub async fn read_entries(
+  dir: &std::path::Path,
+  hidden: bool,
+  confine_to: Option<&std::path::Path>,
+) -> std::io::Result<Vec<ListingEntry>> {
+  let mut entries = Vec::new();
+  let mut read_dir = tokio::fs::read_dir(dir).await?;
+
+  while let Some(entry) = read_dir.next_entry().await? {
+    let name = entry.file_name().to_string_lossy().into_owned();
+    if !hidden && name.starts_with('.') {
+      continue;
+    }
+
+    if let Some(root) = confine_to {
+      match tokio::fs::canonicalize(entry.path()).await {
+        Ok(target) if target.starts_with(root) => {}
+        _ => continue,
+      }
+    }
+
+    // Follow symlinks, so links show the size and type of their target.
+    let Ok(metadata) = tokio::fs::metadata(entry.path()).await else {
+      continue;
+    };
+
+    entries.push(ListingEntry {
+      name,
+      is_dir: metadata.is_dir(),
+      size: if metadata.is_dir() { 0 } else { metadata.len() },
+      mtime: metadata
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_secs())
+        .unwrap_or(0),
+    });
+  }
+
+  Ok(entries)
+}
+
+/// Sorts directories before files, then by `query`.
+pub fn sort_entries(entries: &mut [ListingEntry], query: SortQuery) {
+  entries.sort_by(|a, b| {
+    let by_key = match query.sort {
+      SortKey::Name => a.name.cmp(&b.name),
+      SortKey::Size => a.size.cmp(&b.size).then_with(|| a.name.cmp(&b.name)),
+      SortKey::Mtime => a.mtime.cmp(&b.mtime).then_with(|| a.name.cmp(&b.name)),
+    };
+    let by_key = match query.order {
+      SortOrder::Asc => by_key,
+      SortOrder::Desc => by_key.reverse(),
+    };
+
+    b.is_dir.cmp(&a.is_dir).then(by_key)
+  });
+}
+
+pub fn escape_html(text: &str) -> String {
+  let mut out = String::with_capacity(text.len());
+  for c in text.chars() {
+    match c {
+      '&' => out.push_str("&amp;"),
+      '<' => out.push_str("&lt;"),
+      '>' => out.push_str("&gt;"),
+      '"' => out.push_str("&quot;"),
+      '\'' => out.push_str("&#39;"),
+      c => out.push(c),
+    }
+  }
+  out
+}
+
+pub fn human_size(size: u64) -> String {
+  const UNITS: [&str; 6] = ["B", "KiB", "MiB", "GiB", "TiB", "PiB"];
+
+  let mut value = size as f64;
+  let mut unit = 0;
+  while value >= 1024.0 && unit + 1 < UNITS.len() {
+    value /= 1024.0;
+    unit += 1;
+  }
+
+  if unit == 0 {
+    format!("{} B", size)
+  } else {
+    format!("{:.1} {}", value, UNITS[unit])
+  }
+}
+
+/// Formats seconds since the Unix epoch as `YYYY-MM-DD HH:MM` in UTC.
+pub fn format_utc(secs: u64) -> String {
+  let days = (secs / 86400) as i64;
+  let rem = secs % 86400;
+
+  // Civil from days, see http://howardhinnant.github.io/date_algorithms.html
+  let z = days + 719468;
+  let era = z.div_euclid(146097);
+  let doe = z.rem_euclid(146097);
+  let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+  let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+  let mp = (5 * doy + 2) / 153;
+  let day = doy - (153 * mp + 2) / 5 + 1;
+  let month = if mp < 10 { mp + 3 } else { mp - 9 };
+  let year = yoe + era * 400 + (month <= 2) as i64;
+
+  format!(
+    "{:04}-{:02}-{:02} {:02}:{:02}",
+    year,
+    month,
+    day,
+    rem / 3600,
+    rem % 3600 / 60
+  )
+}
+
+fn sort_link(label: &str, key: SortKey, query: SortQuery) -> String {
+  let name = match key {
+    SortKey::Name => "name",
+    SortKey::Size => "size",
+    SortKey::Mtime => "mtime",
+  };
+  let (order, arrow) = match (query.sort == key, query.order) {
+    (true, SortOrder::Asc) => ("desc", " &#9650;"),
+    (true, SortOrder::Desc) => ("asc", " &#9660;"),
+    (false, _) => ("asc", ""),
+  };
+
+  format!(
+    "<a href=\"?sort={}&amp;order={}\">{}</a>{}",
+    name, order, label, arrow
+  )
+}
+
+pub fn render_html(
+  path: &str,
+  entries: &[ListingEntry],
+  query: SortQuery,
+  upload: bool,
+) -> String {
+  let title = escape_html(&format!("Index of {}", path));
+
+  let mut rows = String::new();
+  if path != "/" {
+    rows.push_str(
+      "<tr><td><a href=\"../\">../</a></td><td></td><td></td></tr>\n",
+    );
+  }
+  for entry in entries {
+    let suffix = if entry.is_dir { "/" } else { "" };
+    let size =
+      if entry.is_dir { "-".to_owned() } else { human_size(entry.size) };
+    rows.push_str(&format!(
+      "<tr><td><a href=\"./{}{}\">{}{}</a></td><td>{}</td><td>{}</td></tr>\n",
+      utf8_percent_encode(&entry.name, LINK),
+      suffix,
+      escape_html(&entry.name),
+      suffix,
+      size,
+      format_utc(entry.mtime),
+    ));
+  }
+
+  let form = if upload {
+    "<form method=\"post\" enctype=\"multipart/form-data\">\n\
+     <input type=\"file\" name=\"file\" multiple required>\n\
+     <button type=\"submit\">Upload</button>\n</form>\n"
+  } else {
+    ""
+  };
+
+  format!(
+    "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+     <meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\n\
+     <title>{title}</title>\n<style>\n\
+     body {{ font-family: sans-serif; margin: 2em; }}\n\
+     table {{ border-collapse: collapse; }}\n\
+     th, td {{ padding: 0.2em 1.5em 0.2em 0; text-align: left; }}\n\
+     td:nth-child(2) {{ text-align: right; }}\n\
+     </style>\n</head>\n<body>\n<h1>{title}</h1>\n<table>\n\
+     <tr><th>{}</th><th>{}</th><th>{}</th></tr>\n{rows}</table>\n\
+     {form}</body>\n</html>\n",
+    sort_link("Name", SortKey::Name, query),
+    sort_link("Size", SortKey::Size, query),
+    sort_link("Modified", SortKey::Mtime, query),
+  )
+}
+
+fn wants_json(accept: Option<&str>) -> bool {
+  accept.is_some_and(|accept| {
+    accept
+      .split(',')
+      .any(|t| t.split(';').next().unwrap_or("").trim() == "application/json")
+  })
+}
+
+async fn list_directory(
+  root: Arc<PathBuf>,
+  (hidden, follow_symlinks, upload): (bool, bool, bool),
+  path: FullPath,
+  query: SortQuery,
+  accept: Option<String>,
+) -> Result<Response, Rejection> {
+  let relative = filters::relative_path(path.as_str())
+    .ok_or_else(warp::reject::not_found)?;
+  let dir = root.join(relative);
+
+  match tokio::fs::metadata(&dir).await {
+    Ok(metadata) if metadata.is_dir() => {}
+    _ => return Err(warp::reject::not_found()),
+  }
+
+  // Relative links need the trailing slash.
+  if !path.as_str().ends_with('/') {
+    let location = format!("{}/", path.as_str());
+    let uri = location.parse::<Uri>().map_err(|_| warp::reject::not_found())?;
+    return Ok(warp::redirect::redirect(uri).into_response());
+  }
+
+  let confine_to = (!follow_symlinks).then_some(root.as_path());
+  let mut entries = read_entries(&dir, hidden, confine_to)
+    .await
+    .map_err(|_| warp::reject::not_found())?;
+  sort_entries(&mut entries, query);
+
+  if wants_json(accept.as_deref()) {
+    return Ok(warp::reply::json(&entries).into_response());
+  }
+
+  let html = render_html(path.as_str(), &entries, query, upload);
+  Ok(
+    warp::reply::with_status(warp::reply::html(html), StatusCode::OK)
+      .into_response(),
+  )
+}
+
+/// Lists directories under `root`, which must be canonical, leaving out the
+/// entries that `filters::confined` rejects. Meant to run after the file
+/// server, which serves `index.html` where it exists. With `upload` the page
+/// has a form that posts files to the directory.
+pub fn listing(
+  root: PathBuf,
+  hidden: bool,
+  follow_symlinks: bool,
+  upload: bool,
+) -> impl Filter<Extract = (Response,), Error = Rejection> + Clone {
+  let root = Arc::new(root);
+
+  warp::get()
+    .or(warp::head())
+    .unify()
+    .and(warp::any().map(move || root.clone()))
+    .and(warp::any().map(move || (hidden, follow_symlinks, upload)))
+    .and(warp::path::full())
+    .and(warp::query::<SortQuery>())
+    .and(warp::header::optional::<String>(header::ACCEPT.as_str()))
+    .and_then(list_directory)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::test_utils;
+
+  fn fixture() -> PathBuf {
+    let dir = test_utils::fixture_dir("listing");
+    std::fs::create_dir_all(dir.join("sub dir")).unwrap();
+    std::fs::create_dir_all(dir.join(".hidden")).unwrap();
+    std::fs::write(dir.join("b.txt"), vec![0u8; 2048]).unwrap();
+    std::fs::write(dir.join("a<script>.txt"), b"a").unwrap();
+    dir
+  }
+
+  #[test]
+  fn test_format() {
+    assert_eq!(format_utc(0), "1970-01-01 00:00");
+    assert_eq!(format_utc(1709251199), "2024-02-29 23:59");
+    assert_eq!(human_size(512), "512 B");
+    assert_eq!(human_size(1536), "1.5 KiB");
+  }
+
+  #[test]
+  fn test_render_html_links() {
+    let entry = ListingEntry {
+      name: "javascript:alert('x')&y".to_owned(),
+      is_dir: false,
+      size: 0,
+      mtime: 0,
+    };
+    let html = render_html("/", &[entry], SortQuery::default(), false);
+
+    assert!(html.contains("href=\"./javascript%3Aalert(%27x%27)%26y\""));
+    assert!(!html.contains("href=\"javascript:"));
+  }
+
+  #[tokio::test]
+  async fn test_listing() {
+    let root = fixture();
+    #[cfg(unix)]
+    std::os::unix::fs::symlink("/", root.join("escape")).unwrap();
+    let filter = listing(root.clone(), false, false, false);
+
+    let res = warp::test::request().path("/").reply(&filter).await;
+    assert_eq!(res.status(), 200);
+    let body = String::from_utf8(res.body().to_vec()).unwrap();
+    assert!(body.contains("href=\"./sub%20dir/\""));
+    assert!(body.contains("a&lt;script&gt;.txt"));
+    assert!(!body.contains(".hidden"));
+    assert!(!body.contains("escape"));
+    assert!(!body.contains("href=\"../\""));
+
+    let res = warp::test::request()
+      .path("/?sort=size&order=desc")
+      .header("accept", "application/json")
+      .reply(&filter)
+      .await;
+    let entries: serde_json::Value =
+      serde_json::from_slice(res.body()).unwrap();
+    let names: Vec<&str> = entries
+      .as_array()
+      .unwrap()
+      .iter()
+      .map(|e| e["name"].as_str().unwrap())
+      .collect();
+    assert_eq!(names, ["sub dir", "b.txt", "a<script>.txt"]);
+
+    let res = warp::test::request().path("/sub%20dir").reply(&filter).await;
+    assert_eq!(res.status(), 301);
+    assert_eq!(res.headers()["location"], "/sub%20dir/");
+
+    let res = warp::test::request().path("/b.txt").reply(&filter).await;
+    assert_eq!(res.status(), 404);
+
+    std::fs::remove_dir_all(root).unwrap();
+  }
+}
@@ -0,0 +1,244 @@
+//! Live reload for `--watch`: HTML responses get a small script that listens
+//! for server-sent events and reloads the page when served files change.
+
+use futures_util::stream::{self, Stream, StreamExt};
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use notify::{EventKind, RecursiveMode, Watcher};
+use std::convert::Infallible;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc};
+use std::time::Duration;
+use tokio::sync::broadcast;
+use warp::http::{header, HeaderValue, StatusCode};
+use warp::hyper::body::{self, Body};
+use warp::reply::Response;
+use warp::sse::Event;
+use warp::{Filter, Rejection, Reply};
+
+/// Path of the event stream, unlikely to clash with served files.
+pub const EVENTS_PATH: &str = "__i6/livereload";
+
+/// Quiet time after a change before reloading, so that saving many files
+/// at once reloads only once.
+const DEBOUNCE: Duration = Duration::from_millis(150);
+
+/// Ignored in addition to the patterns of the user.
+pub const DEFAULT_IGNORE: [&str; 6] =
+  [".git", "node_modules", "target", "*.swp", "*~", ".DS_Store"];
+
+/// Reconnects after a server restart and reloads if the server changed.
+const SCRIPT: &str = r#"<script>
+(() => {
+  let server;
+  const source = new EventSource("/__i6/livereload");
+  source.addEventListener("hello", (e) => {
+    if (server && server !== e.data) location.reload();
+    server = e.data;
+  });
+  source.addEventListener("reload", () => location.reload());
+})();
+</script>
+"#;
+
+/// Builds the matcher for `patterns`. Patterns without a slash match any
+/// file or directory name, like in `.gitignore`.
+pub fn ignore_set(patterns: &[String]) -> Result<GlobSet, Box<dyn Error>> {
+  let mut builder = GlobSetBuilder::new();
+
+  for pattern in
+    DEFAULT_IGNORE.iter().copied().chain(patterns.iter().map(String::as_str))
+  {
+    let glob = Glob::new(pattern.trim_start_matches('/'))
+      .map_err(|e| format!("Invalid ignore pattern {:?}: {}", pattern, e))?;
+    builder.add(glob);
+  }
+
+  Ok(builder.build()?)
+}
+
+fn is_ignored(set: &GlobSet, relative: &Path) -> bool {
+  set.is_match(relative)
+    || relative
+      .components()
+      .any(|component| set.is_match(component.as_os_str()))
+}
+
+/// Watches a directory and notifies subscribers of debounced changes.
+pub struct LiveReload {
+  sender: broadcast::Sender<()>,
+  /// Tells clients apart from a previous server, so they reload on restart.
+  instance: String,
+  _watcher: notify::RecommendedWatcher,
+}
+
+impl LiveReload {
+  /// Starts watching `root`, which must be canonical.
+  pub fn watch(
+    root: &Path,
+    ignore: &[String],
+  ) -> Result<Arc<Self>, Box<dyn Error>> {
+    let ignore = ignore_set(ignore)?;
+    let root: PathBuf = root.to_owned();
+    let (changes, debounced) = mpsc::channel::<()>();
+    let (sender, _) = broadcast::channel(16);
+
+    let mut watcher = notify::recommended_watcher({
+      let root = root.clone();
+      move |event: notify::Result<notify::Event>| {
+        let Ok(event) = event else {
+          return;
+        };
+        // Serving a file reads it, which must not count as a change.
+        if matches!(event.kind, EventKind::Access(_)) {
+          return;
+        }
+
+        let relevant = event.paths.iter().any(|path| {
+          let relative = path.strip_prefix(&root).unwrap_or(path);
+          !is_ignored(&ignore, relative)
+        });
+        if relevant {
+          let _ = changes.send(());
+        }
+      }
+    })?;
+    watcher.watch(&root, RecursiveMode::Recursive)?;
+
+    let notify = sender.clone();
+    std::thread::spawn(move || {
+      while debounced.recv().is_ok() {
+        while debounced.recv_timeout(DEBOUNCE).is_ok() {}
+        let _ = notify.send(());
+      }
+    });
+
+    Ok(Arc::new(Self {
+      sender,
+      instance: crate::auth::generate_token(),
+      _watcher: watcher,
+    }))
+  }
+
+  pub fn subscribe(&self) -> broadcast::Receiver<()> {
+    self.sender.subscribe()
+  }
+}
+
+fn events_stream(
+  reload: &LiveReload,
+) -> impl Stream<Item = Result<Event, Infallible>> + Send {
+  let hello = Event::default().event("hello").data(reload.instance.clone());
+
+  stream::once(async move { Ok(hello) }).chain(stream::unfold(
+    reload.subscribe(),
+    |mut receiver| async move {
+      match receiver.recv().await {
+        // Missed changes still mean a reload.
+        Ok(()) | Err(broadcast::error::RecvError::Lagged(_)) => {
+          Some((Ok(Event::default().event("reload").data("")), receiver))
+        }
+        Err(broadcast::error::RecvError::Closed) => None,
+      }
+    },
+  ))
+}
+
+/// Streams change events to the script at `EVENTS_PATH`.
+pub fn events(
+  reload: Arc<LiveReload>,
+) -> impl Filter<Extract = (Response,), Error = Rejection> + Clone {
+  warp::get()
+    .and(warp::path("__i6"))
+    .and(warp::path("livereload"))
+    .and(warp::path::end())
+    .map(move || {
+      warp::sse::reply(warp::sse::keep_alive().stream(events_stream(&reload)))
+        .into_response()
+    })
+}
+
+/// Adds the live reload script to complete, uncompressed HTML responses.
+pub async fn inject(response: Response) -> Result<Response, Rejection> {
+  let is_html = response
+    .headers()
+    .get(header::CONTENT_TYPE)
+    .and_then(|value| value.to_str().ok())
+    .is_some_and(|value| value.starts_with("text/html"));
+
+  if response.status() != StatusCode::OK
+    || !is_html
+    || response.headers().contains_key(header::CONTENT_ENCODING)
+  {
+    return Ok(response);
+  }
+
+  let (mut parts, body) = response.into_parts();
+  let Ok(bytes) = body::to_bytes(body).await else {
+    return Err(warp::reject::not_found());
+  };
+
+  let html = String::from_utf8_lossy(&bytes);
+  let at = html.to_ascii_lowercase().rfind("</body>").unwrap_or(html.len());
+  let html = format!("{}{}{}", &html[..at], SCRIPT, &html[at..]);
+
+  parts.headers.insert(header::CONTENT_LENGTH, HeaderValue::from(html.len()));
+  Ok(Response::from_parts(parts, Body::from(html)))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::test_utils;
+
+  #[test]
+  fn test_ignore() {
+    let set = ignore_set(&["dist/**".into(), "*.log".into()]).unwrap();
+    assert!(is_ignored(&set, Path::new(".git/index")));
+    assert!(is_ignored(&set, Path::new("web/node_modules/a.js")));
+    assert!(is_ignored(&set, Path::new("dist/app.js")));
+    assert!(is_ignored(&set, Path::new("logs/debug.log")));
+    assert!(!is_ignored(&set, Path::new("src/index.html")));
+  }
+
+  #[tokio::test]
+  async fn test_inject() {
+    let response =
+      warp::reply::html("<html><BODY>hi</BODY></html>").into_response();
+    let response = inject(response).await.unwrap();
+    let len = response.headers()[header::CONTENT_LENGTH].clone();
+    let bytes = body::to_bytes(response.into_body()).await.unwrap();
+    let html = String::from_utf8(bytes.to_vec()).unwrap();
+
+    assert!(html.starts_with("<html><BODY>hi<script>"));
+    assert!(html.ends_with("</script>\n</BODY></html>"));
+    assert_eq!(len, html.len().to_string());
+  }
+
+  #[tokio::test]
+  async fn test_watch() {
+    let root = test_utils::fixture_dir("watch");
+    std::fs::create_dir_all(root.join(".git")).unwrap();
+
+    let reload = LiveReload::watch(&root, &[]).unwrap();
+    let mut events = Box::pin(events_stream(&reload));
+    events.next().await.unwrap().unwrap();
+
+    std::fs::write(root.join(".git").join("index"), "ignored").unwrap();
+    for i in 0..3 {
+      std::fs::write(root.join("index.html"), format!("{}", i)).unwrap();
+    }
+
+    let event = tokio::time::timeout(Duration::from_secs(5), events.next())
+      .await
+      .unwrap();
+    assert!(event.is_some());
+
+    // The writes were debounced into one reload.
+    let more =
+      tokio::time::timeout(Duration::from_millis(500), events.next()).await;
+    assert!(more.is_err());
+
+    std::fs::remove_dir_all(root).unwrap();
+  }
+}
@@ -0,0 +1,521 @@
+//! Reverse proxy for rules like `/api=http://127.0.0.1:8080`, so that a
+//! frontend and its backend share one origin during development.
+//!
+//! A target without a path keeps the request path, like `/api/users` to
+//! `http://127.0.0.1:8080/api/users`. A target with a path replaces the
+//! prefix, so `/api=http://127.0.0.1:8080/v1` forwards `/api/users` to
+//! `/v1/users`.
+//!
+//! With `--auth` or `--token`, the credentials that this server checks are
+//! not passed on, so that the backend does not learn them: `Basic` and
+//! `Bearer` authorization headers and the `token` query parameter.
+
+use futures_util::{SinkExt, StreamExt, TryStreamExt};
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio_tungstenite::tungstenite;
+use warp::http::header::{self, HeaderMap, HeaderName, HeaderValue};
+use warp::http::{Method, Request, StatusCode};
+use warp::hyper::body::{Body, Buf};
+use warp::hyper::client::HttpConnector;
+use warp::hyper::Client;
+use warp::path::FullPath;
+use warp::reply::Response;
+use warp::ws::{Message, WebSocket, Ws};
+use warp::{Filter, Rejection, Reply};
+
+use crate::filters;
+
+/// Headers that only apply to one connection, see RFC 9110 section 7.6.1.
+const HOP_BY_HOP: [&str; 8] = [
+  "connection",
+  "keep-alive",
+  "proxy-authenticate",
+  "proxy-authorization",
+  "te",
+  "trailer",
+  "transfer-encoding",
+  "upgrade",
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProxyRule {
+  /// Path prefix without a trailing slash, empty for `/`.
+  pub prefix: String,
+  /// Host and port of the backend.
+  pub authority: String,
+  /// Replaces `prefix` in forwarded paths, which are kept as is if `None`.
+  pub path: Option<String>,
+}
+
+impl FromStr for ProxyRule {
+  type Err = String;
+
+  /// Parses `/PREFIX=http://HOST:PORT[/PATH]`.
+  fn from_str(rule: &str) -> Result<Self, Self::Err> {
+    let invalid = || {
+      format!("Invalid proxy rule {:?}, expected /PATH=http://HOST:PORT", rule)
+    };
+
+    let (prefix, target) = rule.split_once('=').ok_or_else(invalid)?;
+    let prefix = prefix.trim();
+    if !prefix.starts_with('/') {
+      return Err(invalid());
+    }
+
+    let target = target.trim();
+    let Some(rest) = target.strip_prefix("http://") else {
+      return Err(format!(
+        "Invalid proxy target {:?}, only http:// backends are supported",
+        target
+      ));
+    };
+
+    let (authority, path) = match rest.find(['/', '?']) {
+      Some(i) => (&rest[..i], Some(&rest[i..])),
+      None => (rest, None),
+    };
+    if authority.is_empty()
+      || authority.parse::<warp::http::uri::Authority>().is_err()
+      || path.is_some_and(|path| path.contains('?'))
+    {
+      return Err(invalid());
+    }
+
+    Ok(Self {
+      prefix: prefix.trim_end_matches('/').to_owned(),
+      authority: authority.to_owned(),
+      path: path.map(|path| path.trim_end_matches('/').to_owned()),
+    })
+  }
+}
+
+impl ProxyRule {
+  fn matches(&self, path: &str) -> bool {
+    path
+      .strip_prefix(self.prefix.as_str())
+      .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+  }
+
+  /// Returns the path and query to request from the backend.
+  fn forward_path(&self, path: &str, query: &str) -> String {
+    let mut forwarded = match &self.path {
+      Some(base) => format!("{}{}", base, &path[self.prefix.len()..]),
+      None => path.to_owned(),
+    };
+    if forwarded.is_empty() {
+      forwarded.push('/');
+    }
+    if !query.is_empty() {
+      forwarded.push('?');
+      forwarded.push_str(query);
+    }
+
+    forwarded
+  }
+
+  /// Maps a `Location` that points at the backend to the proxied path, so
+  /// that redirects stay on this server.
+  fn rewrite_location(&self, location: &str) -> Option<String> {
+    let rest = location
+      .strip_prefix("http://")?
+      .strip_prefix(self.authority.as_str())?;
+
+    let (prefix, rest) = match &self.path {
+      Some(base) => (self.prefix.as_str(), rest.strip_prefix(base.as_str())?),
+      None => ("", rest),
+    };
+    if !(rest.is_empty() || rest.starts_with(['/', '?'])) {
+      return None;
+    }
+
+    let location = format!("{}{}", prefix, rest);
+    Some(if location.is_empty() { "/".to_owned() } else { location })
+  }
+}
+
+#[derive(Debug)]
+pub enum ProxyError {
+  Unavailable(String),
+  Timeout,
+}
+
+impl Reply for ProxyError {
+  fn into_response(self) -> Response {
+    let (status, message) = match self {
+      ProxyError::Unavailable(e) => {
+        eprintln!("Warning: proxy request failed: {}", e);
+        (StatusCode::BAD_GATEWAY, "Backend is unavailable")
+      }
+      ProxyError::Timeout => {
+        (StatusCode::GATEWAY_TIMEOUT, "Backend did not respond in time")
+      }
+    };
+
+    warp::reply::with_status(format!("{}\n", message), status).into_response()
+  }
+}
+
+struct Proxy {
+  /// Longest prefix first, so the most specific rule wins.
+  rules: Vec<ProxyRule>,
+  client: Client<HttpConnector>,
+  timeout: Duration,
+  /// Remove the credentials of `auth` before forwarding.
+  authenticated: bool,
+}
+
+/// A request matched by one of the rules.
+struct Target {
+  proxy: Arc<Proxy>,
+  rule: ProxyRule,
+  path_and_query: String,
+  headers: HeaderMap,
+  remote: Option<SocketAddr>,
+}
+
+/// Removes hop-by-hop headers, including those listed in `Connection`.
+pub(crate) fn remove_hop_by_hop(headers: &mut HeaderMap) {
+  let listed: Vec<HeaderName> = headers
+    .get_all(header::CONNECTION)
+    .iter()
+    .filter_map(|value| value.to_str().ok())
+    .flat_map(|value| value.split(','))
+    .filter_map(|name| HeaderName::from_bytes(name.trim().as_bytes()).ok())
+    .collect();
+
+  for name in listed {
+    headers.remove(name);
+  }
+  for name in HOP_BY_HOP {
+    headers.remove(name);
+  }
+}
+
+/// Headers to send to the backend. `Host` is set by the client to the
+/// backend, and the original one is passed as `X-Forwarded-Host`.
+fn request_headers(target: &Target) -> HeaderMap {
+  let mut headers = target.headers.clone();
+  remove_hop_by_hop(&mut headers);
+
+  if target.proxy.authenticated {
+    let consumed = headers.get(header::AUTHORIZATION).is_some_and(|value| {
+      value.to_str().ok().and_then(|value| value.split_once(' ')).is_some_and(
+        |(scheme, _)| {
+          scheme.eq_ignore_ascii_case("basic")
+            || scheme.eq_ignore_ascii_case("bearer")
+        },
+      )
+    });
+    if consumed {
+      headers.remove(header::AUTHORIZATION);
+    }
+  }
+
+  if let Some(host) = headers.remove(header::HOST) {
+    headers.insert("x-forwarded-host", host);
+  }
+
+  if let Some(remote) = target.remote {
+    let forwarded_for = match headers.get("x-forwarded-for") {
+      Some(previous) => {
+        format!("{}, {}", previous.to_str().unwrap_or(""), remote.ip())
+      }
+      None => remote.ip().to_string(),
+    };
+    if let Ok(value) = HeaderValue::from_str(&forwarded_for) {
+      headers.insert("x-forwarded-for", value);
+    }
+  }
+
+  headers
+}
+
+async fn forward<S, B>(
+  target: Target,
+  method: Method,
+  body: S,
+) -> Result<Response, Rejection>
+where
+  S: futures_util::Stream<Item = Result<B, warp::Error>> + Send + 'static,
+  B: Buf,
+{
+  let headers = request_headers(&target);
+  // A request without either header has no body, which must not turn into
+  // an empty chunked one.
+  let body = if headers.contains_key(header::CONTENT_LENGTH)
+    || target.headers.contains_key(header::TRANSFER_ENCODING)
+  {
+    Body::wrap_stream(body.map_ok(|mut buf| buf.copy_to_bytes(buf.remaining())))
+  } else {
+    Body::empty()
+  };
+
+  let uri =
+    format!("http://{}{}", target.rule.authority, target.path_and_query);
+  let mut request = match Request::builder().method(method).uri(uri).body(body)
+  {
+    Ok(request) => request,
+    Err(e) => {
+      return Ok(ProxyError::Unavailable(e.to_string()).into_response())
+    }
+  };
+  *request.headers_mut() = headers;
+
+  let proxy = &target.proxy;
+  let mut response =
+    match tokio::time::timeout(proxy.timeout, proxy.client.request(request))
+      .await
+    {
+      Ok(Ok(response)) => response,
+      Ok(Err(e)) => {
+        return Ok(ProxyError::Unavailable(e.to_string()).into_response())
+      }
+      Err(_) => return Ok(ProxyError::Timeout.into_response()),
+    };
+
+  let headers = response.headers_mut();
+  remove_hop_by_hop(headers);
+  let location = headers
+    .get(header::LOCATION)
+    .and_then(|value| value.to_str().ok())
+    .and_then(|location| target.rule.rewrite_location(location));
+  if let Some(value) = location.and_then(|l| HeaderValue::from_str(&l).ok()) {
+    headers.insert(header::LOCATION, value);
+  }
+
+  Ok(response)
+}
+
+/// Handshake headers that the WebSocket client sets itself.
+const WEBSOCKET_HANDSHAKE: [&str; 4] = [
+  "host",
+  "sec-websocket-key",
+  "sec-websocket-version",
+  "sec-websocket-extensions",
+];
+
+async fn websocket(target: Target, ws: Ws) -> Result<Response, Rejection> {
+  use tungstenite::client::IntoClientRequest;
+  use tungstenite::http;
+
+  let url = format!("ws://{}{}", target.rule.authority, target.path_and_query);
+  let mut request = match url.into_client_request() {
+    Ok(request) => request,
+    Err(e) => {
+      return Ok(ProxyError::Unavailable(e.to_string()).into_response())
+    }
+  };
+
+  for (name, value) in request_headers(&target).iter() {
+    if WEBSOCKET_HANDSHAKE.contains(&name.as_str()) {
+      continue;
+    }
+    if let (Ok(name), Ok(value)) = (
+      http::HeaderName::from_bytes(name.as_str().as_bytes()),
+      http::HeaderValue::from_bytes(value.as_bytes()),
+    ) {
+      request.headers_mut().append(name, value);
+    }
+  }
+
+  let connect = tokio_tungstenite::connect_async(request);
+  let (backend, response) =
+    match tokio::time::timeout(target.proxy.timeout, connect).await {
+      Ok(Ok(connected)) => connected,
+      Ok(Err(e)) => {
+        return Ok(ProxyError::Unavailable(e.to_string()).into_response())
+      }
+      Err(_) => return Ok(ProxyError::Timeout.into_response()),
+    };
+
+  let protocol = response
+    .headers()
+    .get("sec-websocket-protocol")
+    .and_then(|value| HeaderValue::from_bytes(value.as_bytes()).ok());
+
+  let mut reply =
+    ws.on_upgrade(move |client| relay(client, backend)).into_response();
+  if let Some(protocol) = protocol {
+    reply.headers_mut().insert("sec-websocket-protocol", protocol);
+  }
+
+  Ok(reply)
+}
+
+/// Pings are left out, since each side answers them on its own connection.
+fn to_backend(message: Message) -> Option<tungstenite::Message> {
+  if message.is_text() {
+    let text = message.to_str().ok()?.to_owned();
+    Some(tungstenite::Message::Text(text))
+  } else if message.is_binary() {
+    Some(tungstenite::Message::Binary(message.into_bytes()))
+  } else if message.is_close() {
+    let frame = message.close_frame().map(|(code, reason)| {
+      tungstenite::protocol::CloseFrame {
+        code: code.into(),
+        reason: reason.to_owned().into(),
+      }
+    });
+    Some(tungstenite::Message::Close(frame))
+  } else {
+    None
+  }
+}
+
+fn to_client(message: tungstenite::Message) -> Option<Message> {
+  match message {
+    tungstenite::Message::Text(text) => Some(Message::text(text)),
+    tungstenite::Message::Binary(data) => Some(Message::binary(data)),
+    tungstenite::Message::Close(Some(frame)) => {
+      Some(Message::close_with(frame.code, frame.reason))
+    }
+    tungstenite::Message::Close(None) => Some(Message::close()),
+    _ => None,
+  }
+}
+
+/// Passes messages both ways until either side closes.
+async fn relay<S>(
+  client: WebSocket,
+  backend: tokio_tungstenite::WebSocketStream<S>,
+) where
+  S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin,
+{
+  let (mut client_tx, mut client_rx) = client.split();
+  let (mut backend_tx, mut backend_rx) = backend.split();
+
+  let upstream = async {
+    while let Some(Ok(message)) = client_rx.next().await {
+      if let Some(message) = to_backend(message) {
+        if backend_tx.send(message).await.is_err() {
+          break;
+        }
+      }
+    }
+  };
+  let downstream = async {
+    while let Some(Ok(message)) = backend_rx.next().await {
+      if let Some(message) = to_client(message) {
+        if client_tx.send(message).await.is_err() {
+          break;
+        }
+      }
+    }
+  };
+
+  tokio::select! {
+    _ = upstream => {}
+    _ = downstream => {}
+  }
+}
+
+/// Removes the `token` query parameter, see `auth`.
+fn remove_token(query: &str) -> String {
+  query
+    .split('&')
+    .filter(|pair| {
+      pair.split_once('=').map_or(*pair, |(key, _)| key) != "token"
+    })
+    .collect::<Vec<_>>()
+    .join("&")
+}
+
+/// Forwards requests that match one of `rules`, including WebSocket
+/// upgrades. `timeout` limits connecting and waiting for the response
+/// headers, but not streaming the body. `authenticated` removes the
+/// credentials of `auth` from the forwarded requests.
+pub fn proxy(
+  mut rules: Vec<ProxyRule>,
+  timeout: Duration,
+  authenticated: bool,
+) -> impl Filter<Extract = (Response,), Error = Rejection> + Clone {
+  rules.sort_by_key(|rule| std::cmp::Reverse(rule.prefix.len()));
+
+  let mut connector = HttpConnector::new();
+  connector.set_connect_timeout(Some(timeout));
+  let proxy = Arc::new(Proxy {
+    rules,
+    client: Client::builder().build(connector),
+    timeout,
+    authenticated,
+  });
+
+  let query = warp::query::raw().or(warp::any().map(String::new)).unify();
+  let target = warp::path::full()
+    .and(query)
+    .and(warp::header::headers_cloned())
+    .and(filters::remote())
+    .and_then(
+      move |path: FullPath,
+            query: String,
+            headers: HeaderMap,
+            remote: Option<SocketAddr>| {
+        let proxy = proxy.clone();
+        async move {
+          let rule = proxy
+            .rules
+            .iter()
+            .find(|rule| rule.matches(path.as_str()))
+            .cloned()
+            .ok_or_else(warp::reject::not_found)?;
+          let query =
+            if proxy.authenticated { remove_token(&query) } else { query };
+
+          Ok::<_, Rejection>(Target {
+            path_and_query: rule.forward_path(path.as_str(), &query),
+            proxy,
+            rule,
+            headers,
+            remote,
+          })
+        }
+      },
+    );
+
+  let websocket = target.clone().and(warp::ws()).and_then(websocket);
+  let http =
+    target.and(warp::method()).and(warp::body::stream()).and_then(forward);
+
+  websocket.or(http).unify()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_rule() {
+    let rule: ProxyRule = "/api=http://127.0.0.1:8080".parse().unwrap();
+    assert!(rule.matches("/api"));
+    assert!(rule.matches("/api/users"));
+    assert!(!rule.matches("/apis"));
+    assert_eq!(rule.forward_path("/api/users", "a=1"), "/api/users?a=1");
+    assert_eq!(
+      rule.rewrite_location("http://127.0.0.1:8080/api/login").as_deref(),
+      Some("/api/login")
+    );
+    assert_eq!(rule.rewrite_location("http://example.com/"), None);
+
+    let rule: ProxyRule = "/api/=http://localhost:3000/v1/".parse().unwrap();
+    assert_eq!(rule.prefix, "/api");
+    assert_eq!(rule.forward_path("/api/users", ""), "/v1/users");
+    assert_eq!(rule.forward_path("/api", ""), "/v1");
+    assert_eq!(
+      rule.rewrite_location("http://localhost:3000/v1/login?next=1").as_deref(),
+      Some("/api/login?next=1")
+    );
+    assert_eq!(rule.rewrite_location("http://localhost:3000/v10"), None);
+
+    let rule: ProxyRule = "/=http://localhost:3000/".parse().unwrap();
+    assert!(rule.matches("/anything"));
+    assert_eq!(rule.forward_path("/anything", ""), "/anything");
+
+    assert!("api=http://localhost".parse::<ProxyRule>().is_err());
+    assert!("/api=https://localhost".parse::<ProxyRule>().is_err());
+    assert!("/api=http://".parse::<ProxyRule>().is_err());
+    assert!("/api".parse::<ProxyRule>().is_err());
+  }
+}
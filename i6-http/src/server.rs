@@ -0,0 +1,197 @@
+//! A running server that can be embedded in a tokio application and stopped
+//! again, unlike `create_server_http` which blocks until the process exits.
+//!
+//! ```no_run
+//! # async fn run() -> Result<(), Box<dyn std::error::Error>> {
+//! use i6_http::config::ServerConfig;
+//! use i6_http::server::Server;
+//!
+//! let server = Server::start(ServerConfig { port: 0, ..Default::default() })
+//!   .await?;
+//! println!("Listening on {}", server.local_addr());
+//!
+//! server.shutdown();
+//! server.wait().await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::error::Error;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::task::JoinSet;
+use tokio_util::sync::CancellationToken;
+
+use crate::access_log::{self, AccessLog};
+use crate::config::ServerConfig;
+
+/// How long Ctrl-C waits for open requests, like downloads or live reload
+/// streams, before the remaining connections are closed.
+pub const DRAIN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Stops a `Server` from another task.
+#[derive(Debug, Clone)]
+pub struct ShutdownHandle(CancellationToken);
+
+impl ShutdownHandle {
+  /// Stops accepting connections and lets open requests finish.
+  pub fn shutdown(&self) {
+    self.0.cancel();
+  }
+}
+
+/// Listeners of a config, serving on the tokio runtime they started on.
+///
+/// Dropping the server closes its listeners and connections immediately,
+/// call `shutdown` and `wait` to let open requests finish first.
+#[derive(Debug)]
+pub struct Server {
+  addrs: Vec<SocketAddr>,
+  shutdown: CancellationToken,
+  servers: JoinSet<()>,
+}
+
+impl Server {
+  /// Binds all addresses of `config` and serves plain http. Port `0` binds
+  /// a free port, see `local_addrs`.
+  pub async fn start(config: ServerConfig) -> Result<Self, Box<dyn Error>> {
+    Self::bind(config, None).await
+  }
+
+  /// Binds all addresses of `config` and serves https with the PEM
+  /// certificate chain and private key at the given paths.
+  pub async fn start_tls(
+    config: ServerConfig,
+    cert_path: PathBuf,
+    key_path: PathBuf,
+  ) -> Result<Self, Box<dyn Error>> {
+    Self::bind(config, Some((cert_path, key_path))).await
+  }
+
+  async fn bind(
+    config: ServerConfig,
+    tls: Option<(PathBuf, PathBuf)>,
+  ) -> Result<Self, Box<dyn Error>> {
+    let routes = match AccessLog::from_config(&config)? {
+      Some(log) => access_log::logged(log, crate::routes(&config)?),
+      None => crate::routes(&config)?,
+    };
+
+    if config.http3 && tls.is_none() {
+      return Err("HTTP/3 is only served with https".into());
+    }
+    #[cfg(not(feature = "http3"))]
+    if config.http3 {
+      return Err("HTTP/3 requires building with the `http3` feature".into());
+    }
+
+    let shutdown = CancellationToken::new();
+    let mut addrs = Vec::new();
+    let mut servers = JoinSet::new();
+    for addr in config.addresses()? {
+      let signal = shutdown.clone().cancelled_owned();
+
+      let addr = match &tls {
+        Some((cert_path, key_path)) => {
+          let routes = routes.clone();
+
+          // Bound first, so that the TCP listener takes the same port when
+          // it is picked by the system.
+          #[cfg(feature = "http3")]
+          let (addr, routes) = if config.http3 {
+            let endpoint = crate::http3::bind(addr, cert_path, key_path)?;
+            let addr = endpoint.local_addr()?;
+            let routes = crate::http3::advertise(addr.port(), routes);
+            servers.spawn(crate::http3::serve(
+              endpoint,
+              routes.clone(),
+              shutdown.clone(),
+            ));
+            (addr, routes)
+          } else {
+            (addr, routes)
+          };
+
+          let (addr, server) = warp::serve(routes)
+            .tls()
+            .cert_path(cert_path)
+            .key_path(key_path)
+            .try_bind_with_graceful_shutdown(addr, signal)
+            .map_err(|e| {
+              format!("Failed to serve {}: {}", cert_path.display(), e)
+            })?;
+          servers.spawn(server);
+          addr
+        }
+        None => {
+          let (addr, server) = warp::serve(routes.clone())
+            .try_bind_with_graceful_shutdown(addr, signal)
+            .map_err(|e| format!("Failed to bind {}: {}", addr, e))?;
+          servers.spawn(server);
+          addr
+        }
+      };
+
+      addrs.push(addr);
+    }
+
+    Ok(Self { addrs, shutdown, servers })
+  }
+
+  /// The bound addresses, in the order of `ServerConfig::bind`.
+  pub fn local_addrs(&self) -> &[SocketAddr] {
+    &self.addrs
+  }
+
+  /// The first bound address.
+  pub fn local_addr(&self) -> SocketAddr {
+    self.addrs[0]
+  }
+
+  pub fn shutdown_handle(&self) -> ShutdownHandle {
+    ShutdownHandle(self.shutdown.clone())
+  }
+
+  /// Stops accepting connections and lets open requests finish, see `wait`.
+  pub fn shutdown(&self) {
+    self.shutdown.cancel();
+  }
+
+  /// Waits until all listeners stopped after `shutdown` and their open
+  /// connections are closed.
+  pub async fn wait(mut self) -> Result<(), Box<dyn Error>> {
+    while let Some(result) = self.servers.join_next().await {
+      result?;
+    }
+
+    Ok(())
+  }
+
+  /// Serves until Ctrl-C, then waits up to `DRAIN_TIMEOUT` for open
+  /// requests.
+  pub async fn run_until_ctrl_c(self) -> Result<(), Box<dyn Error>> {
+    let handle = self.shutdown_handle();
+    let wait = self.wait();
+    tokio::pin!(wait);
+
+    tokio::select! {
+      result = &mut wait => return result,
+      result = tokio::signal::ctrl_c() => result?,
+    }
+
+    println!("Shutting down, waiting for open requests");
+    handle.shutdown();
+
+    match tokio::time::timeout(DRAIN_TIMEOUT, wait).await {
+      Ok(result) => result,
+      Err(_) => {
+        eprintln!(
+          "Closing connections still open after {}s",
+          DRAIN_TIMEOUT.as_secs()
+        );
+        Ok(())
+      }
+    }
+  }
+}
@@ -0,0 +1,16 @@
+//! Fixtures shared by the unit tests.
+
+use std::path::PathBuf;
+
+/// Creates an empty directory for the fixture `name` in the system temp
+/// directory, replacing what an earlier run left behind.
+pub fn fixture_dir(name: &str) -> PathBuf {
+  let dir = std::env::temp_dir().join(format!(
+    "i6-http-{}-{}",
+    name,
+    std::process::id()
+  ));
+  let _ = std::fs::remove_dir_all(&dir);
+  std::fs::create_dir_all(&dir).unwrap();
+  dir.canonicalize().unwrap()
+}
@@ -0,0 +1,413 @@
+//! Opt-in uploads, as raw `PUT` requests to a file path or as multipart
+//! forms posted to a directory from its listing page.
+//!
+//! Writes always stay inside the served root: paths with `..` are refused,
+//! parent directories must resolve inside the root, and existing symlinks are
+//! never written through.
+
+use futures_util::{Stream, StreamExt};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::io::AsyncWriteExt;
+use warp::http::{header, Method, StatusCode, Uri};
+use warp::hyper::body::Buf;
+use warp::multipart::FormData;
+use warp::path::FullPath;
+use warp::reply::Response;
+use warp::{Filter, Rejection, Reply};
+
+use crate::filters;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UploadOptions {
+  /// Largest accepted request body in bytes.
+  pub max_size: u64,
+  /// Replace existing files instead of answering `409 Conflict`.
+  pub overwrite: bool,
+  /// Accept names that start with a dot.
+  pub hidden: bool,
+}
+
+#[derive(Debug)]
+pub enum UploadError {
+  BadPath,
+  Forbidden,
+  Conflict,
+  TooLarge,
+  Io(std::io::Error),
+}
+
+impl From<std::io::Error> for UploadError {
+  fn from(e: std::io::Error) -> Self {
+    UploadError::Io(e)
+  }
+}
+
+impl Reply for UploadError {
+  fn into_response(self) -> Response {
+    let (status, message) = match self {
+      UploadError::BadPath => (StatusCode::BAD_REQUEST, "Invalid path"),
+      UploadError::Forbidden => {
+        (StatusCode::FORBIDDEN, "Path is outside of the served directory")
+      }
+      UploadError::Conflict => (StatusCode::CONFLICT, "File already exists"),
+      UploadError::TooLarge => {
+        (StatusCode::PAYLOAD_TOO_LARGE, "Upload exceeds the size limit")
+      }
+      UploadError::Io(e) => {
+        eprintln!("Warning: upload failed: {}", e);
+        (StatusCode::INTERNAL_SERVER_ERROR, "Upload failed")
+      }
+    };
+
+    warp::reply::with_status(format!("{}\n", message), status).into_response()
+  }
+}
+
+/// Checks a single file name sent by a client.
+fn sanitize_file_name(name: &str, hidden: bool) -> Result<&str, UploadError> {
+  let name = name.trim();
+  if name.is_empty()
+    || name == "."
+    || name == ".."
+    || name.contains(['/', '\\', '\0'])
+  {
+    return Err(UploadError::BadPath);
+  }
+
+  if !hidden && name.starts_with('.') {
+    return Err(UploadError::Forbidden);
+  }
+
+  Ok(name)
+}
+
+/// Resolves the file to write for a request path, creating its parent
+/// directories inside `root`, which must be canonical.
+pub(crate) async fn target_path(
+  root: &Path,
+  relative: &Path,
+  hidden: bool,
+) -> Result<PathBuf, UploadError> {
+  let name = relative
+    .file_name()
+    .and_then(|name| name.to_str())
+    .ok_or(UploadError::BadPath)?;
+  sanitize_file_name(name, hidden)?;
+
+  if !hidden
+    && relative
+      .components()
+      .any(|c| c.as_os_str().to_string_lossy().starts_with('.'))
+  {
+    return Err(UploadError::Forbidden);
+  }
+
+  let parent = root.join(relative.parent().unwrap_or(Path::new("")));
+
+  // Check the deepest existing directory before creating anything, so a
+  // symlink cannot make us create directories outside of the root.
+  let mut existing = parent.as_path();
+  while !tokio::fs::try_exists(existing).await.unwrap_or(false) {
+    existing = existing.parent().ok_or(UploadError::BadPath)?;
+  }
+  if !tokio::fs::canonicalize(existing).await?.starts_with(root) {
+    return Err(UploadError::Forbidden);
+  }
+
+  tokio::fs::create_dir_all(&parent).await?;
+  let parent = tokio::fs::canonicalize(&parent).await?;
+  if !parent.starts_with(root) {
+    return Err(UploadError::Forbidden);
+  }
+
+  let target = parent.join(name);
+  match tokio::fs::symlink_metadata(&target).await {
+    Ok(metadata) if metadata.file_type().is_symlink() => {
+      Err(UploadError::Forbidden)
+    }
+    Ok(metadata) if metadata.is_dir() => Err(UploadError::Conflict),
+    _ => Ok(target),
+  }
+}
+
+async fn write_stream<S, B>(
+  file: &mut tokio::fs::File,
+  mut stream: S,
+  max_size: u64,
+) -> Result<u64, UploadError>
+where
+  S: Stream<Item = Result<B, warp::Error>> + Unpin,
+  B: Buf,
+{
+  let mut written = 0;
+
+  while let Some(chunk) = stream.next().await {
+    let mut chunk = chunk.map_err(|e| {
+      UploadError::Io(std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+    })?;
+
+    written += chunk.remaining() as u64;
+    if written > max_size {
+      return Err(UploadError::TooLarge);
+    }
+
+    while chunk.has_remaining() {
+      let len = chunk.chunk().len();
+      file.write_all(chunk.chunk()).await?;
+      chunk.advance(len);
+    }
+  }
+
+  file.flush().await?;
+  Ok(written)
+}
+
+fn temp_path(target: &Path) -> PathBuf {
+  static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+  let name = target.file_name().unwrap_or_default().to_string_lossy();
+  target.with_file_name(format!(
+    ".{}.i6-upload-{}-{}",
+    name,
+    std::process::id(),
+    COUNTER.fetch_add(1, Ordering::Relaxed)
+  ))
+}
+
+/// Writes `stream` to `target`. Returns true if the file was created, false
+/// if an existing file was replaced.
+pub(crate) async fn save<S, B>(
+  target: &Path,
+  stream: S,
+  options: UploadOptions,
+) -> Result<bool, UploadError>
+where
+  S: Stream<Item = Result<B, warp::Error>> + Unpin,
+  B: Buf,
+{
+  let existed = tokio::fs::try_exists(target).await.unwrap_or(false);
+  if existed && !options.overwrite {
+    return Err(UploadError::Conflict);
+  }
+
+  // Write next to the target and rename, so readers never see a partial
+  // file and failed uploads leave nothing behind.
+  let temp = temp_path(target);
+  let mut file = tokio::fs::OpenOptions::new()
+    .write(true)
+    .create_new(true)
+    .open(&temp)
+    .await?;
+
+  let result = match write_stream(&mut file, stream, options.max_size).await {
+    Ok(_) if !options.overwrite => {
+      // Fails if the file appeared while uploading.
+      tokio::fs::hard_link(&temp, target).await.map_err(|e| match e.kind() {
+        std::io::ErrorKind::AlreadyExists => UploadError::Conflict,
+        _ => UploadError::Io(e),
+      })
+    }
+    Ok(_) => tokio::fs::rename(&temp, target).await.map_err(Into::into),
+    Err(e) => Err(e),
+  };
+
+  let _ = tokio::fs::remove_file(&temp).await;
+  result.map(|_| !existed)
+}
+
+async fn put_file<S, B>(
+  root: Arc<PathBuf>,
+  options: UploadOptions,
+  path: FullPath,
+  content_length: Option<u64>,
+  body: S,
+) -> Result<Response, Rejection>
+where
+  S: Stream<Item = Result<B, warp::Error>> + Unpin,
+  B: Buf,
+{
+  if content_length.is_some_and(|len| len > options.max_size) {
+    return Ok(UploadError::TooLarge.into_response());
+  }
+
+  let result = async {
+    let relative =
+      filters::relative_path(path.as_str()).ok_or(UploadError::BadPath)?;
+    let target = target_path(&root, &relative, options.hidden).await?;
+    save(&target, body, options).await
+  }
+  .await;
+
+  Ok(match result {
+    Ok(true) => StatusCode::CREATED.into_response(),
+    Ok(false) => StatusCode::NO_CONTENT.into_response(),
+    Err(e) => e.into_response(),
+  })
+}
+
+async fn post_form(
+  root: Arc<PathBuf>,
+  options: UploadOptions,
+  path: FullPath,
+  form: FormData,
+) -> Result<Response, Rejection> {
+  let result = async {
+    let relative =
+      filters::relative_path(path.as_str()).ok_or(UploadError::BadPath)?;
+    let dir = tokio::fs::canonicalize(root.join(relative))
+      .await
+      .map_err(|_| UploadError::BadPath)?;
+    if !dir.starts_with(root.as_path()) {
+      return Err(UploadError::Forbidden);
+    }
+    if !tokio::fs::metadata(&dir).await?.is_dir() {
+      return Err(UploadError::BadPath);
+    }
+
+    let mut form = form;
+    while let Some(part) = form.next().await {
+      let part = part.map_err(|_| UploadError::BadPath)?;
+      let Some(name) = part.filename().map(str::to_owned) else {
+        continue;
+      };
+      // Browsers may send the full client path.
+      let name = name.rsplit(['/', '\\']).next().unwrap_or_default();
+      let name = sanitize_file_name(name, options.hidden)?;
+
+      let target = dir.join(name);
+      if tokio::fs::symlink_metadata(&target)
+        .await
+        .is_ok_and(|m| m.file_type().is_symlink() || m.is_dir())
+      {
+        return Err(UploadError::Conflict);
+      }
+
+      save(&target, Box::pin(part.stream()), options).await?;
+    }
+
+    Ok(())
+  }
+  .await;
+
+  Ok(match result {
+    Ok(()) => {
+      // Back to the listing that posted the form.
+      let location = path.as_str().parse::<Uri>().unwrap_or_default();
+      warp::redirect::see_other(location).into_response()
+    }
+    Err(e) => e.into_response(),
+  })
+}
+
+/// Accepts uploads below `root`, which must be canonical.
+pub fn upload(
+  root: PathBuf,
+  options: UploadOptions,
+) -> impl Filter<Extract = (Response,), Error = Rejection> + Clone {
+  let root = Arc::new(root);
+  let with_state = {
+    let root = root.clone();
+    warp::any().map(move || (root.clone(), options))
+  };
+
+  let put = filters::method_is(Method::PUT)
+    .and(with_state.clone())
+    .and(warp::path::full())
+    .and(warp::header::optional::<u64>(header::CONTENT_LENGTH.as_str()))
+    .and(warp::body::stream())
+    .and_then(|(root, options), path, content_length, body| {
+      put_file(root, options, path, content_length, Box::pin(body))
+    });
+
+  let post = filters::method_is(Method::POST)
+    .and(with_state)
+    .and(warp::path::full())
+    .and(warp::multipart::form().max_length(options.max_size))
+    .and_then(|(root, options), path, form| {
+      post_form(root, options, path, form)
+    });
+
+  put.or(post).unify()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::test_utils;
+
+  fn fixture(name: &str) -> PathBuf {
+    test_utils::fixture_dir(&format!("upload-{}", name))
+  }
+
+  fn options(overwrite: bool) -> UploadOptions {
+    UploadOptions { max_size: 16, overwrite, hidden: false }
+  }
+
+  #[tokio::test]
+  async fn test_put() {
+    let root = fixture("put");
+    let filter = upload(root.clone(), options(false));
+    let put = |path: &str, body: &str| {
+      warp::test::request().method("PUT").path(path).body(body)
+    };
+
+    let res = put("/dir/a.txt", "hello").reply(&filter).await;
+    assert_eq!(res.status(), 201);
+    assert_eq!(std::fs::read(root.join("dir/a.txt")).unwrap(), b"hello");
+
+    let res = put("/dir/a.txt", "again").reply(&filter).await;
+    assert_eq!(res.status(), 409);
+
+    let res = put("/../escape.txt", "x").reply(&filter).await;
+    assert_eq!(res.status(), 400);
+
+    let res = put("/.env", "x").reply(&filter).await;
+    assert_eq!(res.status(), 403);
+
+    let res = put("/big.txt", &"x".repeat(17)).reply(&filter).await;
+    assert_eq!(res.status(), 413);
+    assert!(!root.join("big.txt").exists());
+
+    let filter = upload(root.clone(), options(true));
+    let res = put("/dir/a.txt", "again").reply(&filter).await;
+    assert_eq!(res.status(), 204);
+    assert_eq!(std::fs::read(root.join("dir/a.txt")).unwrap(), b"again");
+
+    #[cfg(unix)]
+    {
+      std::os::unix::fs::symlink("/tmp", root.join("out")).unwrap();
+      let res = put("/out/escape.txt", "x").reply(&filter).await;
+      assert_eq!(res.status(), 403);
+    }
+
+    assert_eq!(std::fs::read_dir(root.join("dir")).unwrap().count(), 1);
+    std::fs::remove_dir_all(root).unwrap();
+  }
+
+  #[tokio::test]
+  async fn test_multipart() {
+    let root = fixture("multipart");
+    let options = UploadOptions { max_size: 1024, ..options(false) };
+    let filter = upload(root.clone(), options);
+
+    let body = "--X\r\n\
+      Content-Disposition: form-data; name=\"file\"; filename=\"C:\\\\docs\\\\b.txt\"\r\n\
+      \r\n\
+      form data\r\n\
+      --X--\r\n";
+    let res = warp::test::request()
+      .method("POST")
+      .path("/")
+      .header("content-type", "multipart/form-data; boundary=X")
+      .body(body)
+      .reply(&filter)
+      .await;
+
+    assert_eq!(res.status(), 303);
+    assert_eq!(std::fs::read(root.join("b.txt")).unwrap(), b"form data");
+
+    std::fs::remove_dir_all(root).unwrap();
+  }
+}
@@ -0,0 +1,622 @@
+//! WebDAV for `--webdav`, so that file managers can mount the served
+//! directory as a network drive. Reading works like the file server, and
+//! `PUT`, `DELETE`, `MKCOL`, `MOVE` and `COPY` need `--upload` as well, and
+//! replacing existing files needs `--overwrite`.
+//!
+//! Locks are not enforced. `LOCK` hands out a token without tracking it,
+//! which is enough for clients that refuse to write without one.
+//!
+//! `PROPFIND` always answers the same live properties, whatever the request
+//! body asks for, and treats `Depth: infinity` like `Depth: 1`.
+
+use headers::{Header, LastModified};
+use percent_encoding::utf8_percent_encode;
+use rand::Rng;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use warp::http::header::{self, HeaderValue};
+use warp::http::uri::Authority;
+use warp::http::{Method, StatusCode, Uri};
+use warp::path::FullPath;
+use warp::reply::Response;
+use warp::{Filter, Rejection, Reply};
+
+use crate::files::FileInfo;
+use crate::filters;
+use crate::listing::{self, escape_html};
+use crate::upload::{self, UploadError, UploadOptions};
+
+const XML_HEADER: &str = "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DavOptions {
+  /// Accept requests that change files.
+  pub writable: bool,
+  /// Largest accepted `PUT` body in bytes.
+  pub max_size: u64,
+  /// Let `PUT`, `MOVE` and `COPY` replace existing files, like uploads.
+  pub overwrite: bool,
+  /// List and accept names that start with a dot.
+  pub hidden: bool,
+  /// List symlinks that point outside of the root.
+  pub follow_symlinks: bool,
+}
+
+#[derive(Debug)]
+pub enum DavError {
+  BadPath,
+  Forbidden,
+  ReadOnly,
+  NotFound,
+  /// `MKCOL` of something that exists.
+  Exists,
+  /// The parent collection is missing.
+  Conflict,
+  /// The destination exists and `Overwrite: F` was sent.
+  PreconditionFailed,
+  /// The destination is a URL of another server.
+  OtherServer,
+  UnsupportedBody,
+  Upload(UploadError),
+  Io(std::io::Error),
+}
+
+impl From<std::io::Error> for DavError {
+  fn from(e: std::io::Error) -> Self {
+    match e.kind() {
+      std::io::ErrorKind::NotFound => DavError::NotFound,
+      _ => DavError::Io(e),
+    }
+  }
+}
+
+impl From<UploadError> for DavError {
+  fn from(e: UploadError) -> Self {
+    DavError::Upload(e)
+  }
+}
+
+impl Reply for DavError {
+  fn into_response(self) -> Response {
+    let (status, message) = match self {
+      DavError::BadPath => (StatusCode::BAD_REQUEST, "Invalid path"),
+      DavError::Forbidden => {
+        (StatusCode::FORBIDDEN, "Path is outside of the served directory")
+      }
+      DavError::ReadOnly => (StatusCode::FORBIDDEN, "Uploads are disabled"),
+      DavError::NotFound => (StatusCode::NOT_FOUND, "Not found"),
+      DavError::Exists => (StatusCode::METHOD_NOT_ALLOWED, "Already exists"),
+      DavError::Conflict => {
+        (StatusCode::CONFLICT, "Parent collection does not exist")
+      }
+      DavError::PreconditionFailed => {
+        (StatusCode::PRECONDITION_FAILED, "Destination already exists")
+      }
+      DavError::OtherServer => {
+        (StatusCode::BAD_GATEWAY, "Destination is on another server")
+      }
+      DavError::UnsupportedBody => {
+        (StatusCode::UNSUPPORTED_MEDIA_TYPE, "Request body is not supported")
+      }
+      DavError::Upload(e) => return e.into_response(),
+      DavError::Io(e) => {
+        eprintln!("Warning: WebDAV request failed: {}", e);
+        (StatusCode::INTERNAL_SERVER_ERROR, "Request failed")
+      }
+    };
+
+    warp::reply::with_status(format!("{}\n", message), status).into_response()
+  }
+}
+
+fn method(name: &str) -> Method {
+  Method::from_bytes(name.as_bytes()).expect("WebDAV method names are tokens")
+}
+
+/// Formats a typed header the way it is sent.
+fn header_text<H: Header>(header: H) -> String {
+  let mut values = Vec::new();
+  header.encode(&mut values);
+  values
+    .first()
+    .and_then(|value| value.to_str().ok())
+    .unwrap_or_default()
+    .to_owned()
+}
+
+/// Resolves a request path to a location below `root`, which must be
+/// canonical. The location itself may not exist, but its parent directory
+/// must, inside of `root`.
+async fn resolve(
+  root: &Path,
+  path: &str,
+  hidden: bool,
+) -> Result<PathBuf, DavError> {
+  let relative = filters::relative_path(path).ok_or(DavError::BadPath)?;
+  let Some(name) = relative.file_name() else {
+    return Ok(root.to_owned());
+  };
+
+  if !hidden && filters::is_hidden(&relative) {
+    return Err(DavError::Forbidden);
+  }
+
+  let parent = root.join(relative.parent().unwrap_or(Path::new("")));
+  let parent =
+    tokio::fs::canonicalize(&parent).await.map_err(|_| DavError::Conflict)?;
+  if !parent.starts_with(root) {
+    return Err(DavError::Forbidden);
+  }
+  if !tokio::fs::metadata(&parent).await?.is_dir() {
+    return Err(DavError::Conflict);
+  }
+
+  Ok(parent.join(name))
+}
+
+/// Answers a request with `result`, or its error.
+fn respond(result: Result<Response, DavError>) -> Result<Response, Rejection> {
+  Ok(result.unwrap_or_else(Reply::into_response))
+}
+
+/// Appends the `response` element of one resource to a multistatus body.
+fn write_response(
+  body: &mut String,
+  href: &str,
+  name: &str,
+  metadata: &std::fs::Metadata,
+) {
+  let _ = write!(
+    body,
+    "<D:response><D:href>{}</D:href><D:propstat><D:prop>\
+     <D:displayname>{}</D:displayname>",
+    escape_html(href),
+    escape_html(name),
+  );
+
+  if metadata.is_dir() {
+    body.push_str("<D:resourcetype><D:collection/></D:resourcetype>");
+  } else {
+    let info = FileInfo::new(metadata);
+    let content_type = mime_guess::from_path(name).first_or_octet_stream();
+    let _ = write!(
+      body,
+      "<D:resourcetype/><D:getcontentlength>{}</D:getcontentlength>\
+       <D:getcontenttype>{}</D:getcontenttype>\
+       <D:getetag>{}</D:getetag>",
+      info.len,
+      escape_html(content_type.as_ref()),
+      escape_html(&header_text(info.etag)),
+    );
+  }
+
+  if let Ok(modified) = metadata.modified() {
+    let _ = write!(
+      body,
+      "<D:getlastmodified>{}</D:getlastmodified>",
+      header_text(LastModified::from(modified)),
+    );
+  }
+
+  body.push_str(
+    "<D:supportedlock><D:lockentry>\
+     <D:lockscope><D:exclusive/></D:lockscope>\
+     <D:locktype><D:write/></D:locktype>\
+     </D:lockentry></D:supportedlock>\
+     </D:prop><D:status>HTTP/1.1 200 OK</D:status></D:propstat></D:response>\n",
+  );
+}
+
+fn multistatus(body: String) -> Response {
+  let body = format!(
+    "{}<D:multistatus xmlns:D=\"DAV:\">\n{}</D:multistatus>\n",
+    XML_HEADER, body
+  );
+  let mut response =
+    warp::reply::with_status(body, StatusCode::MULTI_STATUS).into_response();
+  response.headers_mut().insert(
+    header::CONTENT_TYPE,
+    HeaderValue::from_static("application/xml; charset=utf-8"),
+  );
+  response
+}
+
+async fn propfind(
+  root: Arc<PathBuf>,
+  options: DavOptions,
+  path: FullPath,
+  depth: Option<String>,
+) -> Result<Response, Rejection> {
+  respond(
+    async {
+      let relative =
+        filters::relative_path(path.as_str()).ok_or(DavError::BadPath)?;
+      let target = root.join(&relative);
+      let metadata = tokio::fs::metadata(&target).await?;
+
+      let mut href = path.as_str().to_owned();
+      if metadata.is_dir() && !href.ends_with('/') {
+        href.push('/');
+      }
+      let name = relative
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+      let mut body = String::new();
+      write_response(&mut body, &href, &name, &metadata);
+
+      if metadata.is_dir() && depth.as_deref().map(str::trim) != Some("0") {
+        let confine_to = (!options.follow_symlinks).then_some(root.as_path());
+        let mut entries =
+          listing::read_entries(&target, options.hidden, confine_to).await?;
+        listing::sort_entries(&mut entries, Default::default());
+
+        for entry in entries {
+          let Ok(metadata) =
+            tokio::fs::metadata(target.join(&entry.name)).await
+          else {
+            continue;
+          };
+          let mut child = format!(
+            "{}{}",
+            href,
+            utf8_percent_encode(&entry.name, listing::LINK)
+          );
+          if metadata.is_dir() {
+            child.push('/');
+          }
+          write_response(&mut body, &child, &entry.name, &metadata);
+        }
+      }
+
+      Ok(multistatus(body))
+    }
+    .await,
+  )
+}
+
+fn options_response(options: DavOptions) -> Response {
+  let allow = if options.writable {
+    "OPTIONS, GET, HEAD, PROPFIND, PUT, DELETE, MKCOL, MOVE, COPY, LOCK, UNLOCK"
+  } else {
+    "OPTIONS, GET, HEAD, PROPFIND, LOCK, UNLOCK"
+  };
+
+  let mut response = StatusCode::OK.into_response();
+  let headers = response.headers_mut();
+  headers.insert("dav", HeaderValue::from_static("1, 2"));
+  headers.insert(header::ALLOW, HeaderValue::from_static(allow));
+  // Lets Windows and Office edit files in place.
+  headers.insert("ms-author-via", HeaderValue::from_static("DAV"));
+  response
+}
+
+async fn put<S, B>(
+  root: Arc<PathBuf>,
+  options: DavOptions,
+  path: FullPath,
+  content_length: Option<u64>,
+  body: S,
+) -> Result<Response, Rejection>
+where
+  S: futures_util::Stream<Item = Result<B, warp::Error>> + Unpin,
+  B: warp::hyper::body::Buf,
+{
+  respond(
+    async {
+      if !options.writable {
+        return Err(DavError::ReadOnly);
+      }
+      if content_length.is_some_and(|len| len > options.max_size) {
+        return Err(UploadError::TooLarge.into());
+      }
+
+      // Unlike plain uploads, missing parents are not created.
+      resolve(&root, path.as_str(), options.hidden).await?;
+      let relative =
+        filters::relative_path(path.as_str()).ok_or(DavError::BadPath)?;
+      let target =
+        upload::target_path(&root, &relative, options.hidden).await?;
+
+      // Clients save changes by replacing the file, which needs
+      // `--overwrite` like uploads do.
+      let upload_options = UploadOptions {
+        max_size: options.max_size,
+        overwrite: options.overwrite,
+        hidden: options.hidden,
+      };
+      let created = upload::save(&target, body, upload_options).await?;
+
+      Ok(
+        if created { StatusCode::CREATED } else { StatusCode::NO_CONTENT }
+          .into_response(),
+      )
+    }
+    .await,
+  )
+}
+
+async fn delete(
+  root: Arc<PathBuf>,
+  options: DavOptions,
+  path: FullPath,
+) -> Result<Response, Rejection> {
+  respond(
+    async {
+      if !options.writable {
+        return Err(DavError::ReadOnly);
+      }
+
+      let target = resolve(&root, path.as_str(), options.hidden).await?;
+      if target == *root {
+        return Err(DavError::Forbidden);
+      }
+
+      // Symlinks are removed, not what they point to.
+      if tokio::fs::symlink_metadata(&target).await?.is_dir() {
+        tokio::fs::remove_dir_all(&target).await?;
+      } else {
+        tokio::fs::remove_file(&target).await?;
+      }
+
+      Ok(StatusCode::NO_CONTENT.into_response())
+    }
+    .await,
+  )
+}
+
+async fn mkcol(
+  root: Arc<PathBuf>,
+  options: DavOptions,
+  path: FullPath,
+  content_length: Option<u64>,
+) -> Result<Response, Rejection> {
+  respond(
+    async {
+      if !options.writable {
+        return Err(DavError::ReadOnly);
+      }
+      if content_length.is_some_and(|len| len > 0) {
+        return Err(DavError::UnsupportedBody);
+      }
+
+      let target = resolve(&root, path.as_str(), options.hidden).await?;
+      if tokio::fs::symlink_metadata(&target).await.is_ok() {
+        return Err(DavError::Exists);
+      }
+      tokio::fs::create_dir(&target).await?;
+
+      Ok(StatusCode::CREATED.into_response())
+    }
+    .await,
+  )
+}
+
+/// Copies a file, or a directory with its members when `recursive` is set.
+/// Symlinks inside of directories are left out.
+fn copy_all(
+  source: &Path,
+  target: &Path,
+  recursive: bool,
+) -> std::io::Result<()> {
+  if std::fs::symlink_metadata(source)?.is_dir() {
+    std::fs::create_dir(target)?;
+    if recursive {
+      for entry in std::fs::read_dir(source)? {
+        let entry = entry?;
+        if !entry.file_type()?.is_symlink() {
+          copy_all(&entry.path(), &target.join(entry.file_name()), true)?;
+        }
+      }
+    }
+  } else {
+    std::fs::copy(source, target)?;
+  }
+
+  Ok(())
+}
+
+/// The headers of `MOVE` and `COPY`.
+struct Transfer {
+  /// Authority of the request, which an absolute destination must match.
+  host: Option<Authority>,
+  destination: Option<String>,
+  overwrite: Option<String>,
+  depth: Option<String>,
+}
+
+async fn transfer(
+  root: Arc<PathBuf>,
+  options: DavOptions,
+  path: FullPath,
+  headers: Transfer,
+  moving: bool,
+) -> Result<Response, Rejection> {
+  respond(
+    async {
+      if !options.writable {
+        return Err(DavError::ReadOnly);
+      }
+
+      let source = resolve(&root, path.as_str(), options.hidden).await?;
+      let source_type = tokio::fs::symlink_metadata(&source).await?;
+      // Copies would read what the link points to, which may be outside.
+      if !moving && source_type.is_symlink() {
+        return Err(DavError::Forbidden);
+      }
+
+      // Either a path or an absolute URL of this server.
+      let destination = headers
+        .destination
+        .and_then(|destination| destination.parse::<Uri>().ok())
+        .ok_or(DavError::BadPath)?;
+      if destination
+        .authority()
+        .is_some_and(|authority| headers.host.as_ref() != Some(authority))
+      {
+        return Err(DavError::OtherServer);
+      }
+      let target = resolve(&root, destination.path(), options.hidden).await?;
+
+      if source == *root || target == *root || target.starts_with(&source) {
+        return Err(DavError::Forbidden);
+      }
+
+      let existed = tokio::fs::symlink_metadata(&target).await.is_ok();
+      if existed {
+        if !options.overwrite
+          || headers.overwrite.as_deref().map(str::trim) == Some("F")
+        {
+          return Err(DavError::PreconditionFailed);
+        }
+        if tokio::fs::symlink_metadata(&target).await?.is_dir() {
+          tokio::fs::remove_dir_all(&target).await?;
+        } else {
+          tokio::fs::remove_file(&target).await?;
+        }
+      }
+
+      if moving {
+        tokio::fs::rename(&source, &target).await?;
+      } else {
+        let recursive = headers.depth.as_deref().map(str::trim) != Some("0");
+        tokio::task::spawn_blocking(move || {
+          copy_all(&source, &target, recursive)
+        })
+        .await
+        .map_err(std::io::Error::other)??;
+      }
+
+      Ok(
+        if existed { StatusCode::NO_CONTENT } else { StatusCode::CREATED }
+          .into_response(),
+      )
+    }
+    .await,
+  )
+}
+
+/// Hands out a lock token without tracking it.
+fn lock(path: FullPath) -> Response {
+  let token =
+    format!("opaquelocktoken:{:032x}", rand::thread_rng().gen::<u128>());
+  let body = format!(
+    "{}<D:prop xmlns:D=\"DAV:\"><D:lockdiscovery><D:activelock>\
+     <D:locktype><D:write/></D:locktype>\
+     <D:lockscope><D:exclusive/></D:lockscope>\
+     <D:depth>infinity</D:depth><D:timeout>Second-3600</D:timeout>\
+     <D:locktoken><D:href>{}</D:href></D:locktoken>\
+     <D:lockroot><D:href>{}</D:href></D:lockroot>\
+     </D:activelock></D:lockdiscovery></D:prop>\n",
+    XML_HEADER,
+    token,
+    escape_html(path.as_str()),
+  );
+
+  let mut response = warp::reply::with_header(
+    body,
+    header::CONTENT_TYPE,
+    "application/xml; charset=utf-8",
+  )
+  .into_response();
+  if let Ok(value) = HeaderValue::from_str(&format!("<{}>", token)) {
+    response.headers_mut().insert("lock-token", value);
+  }
+  response
+}
+
+/// Serves WebDAV below `root`, which must be canonical. Meant to run before
+/// the file server, which answers `GET` and `HEAD`.
+pub fn webdav(
+  root: PathBuf,
+  options: DavOptions,
+) -> impl Filter<Extract = (Response,), Error = Rejection> + Clone {
+  let root = Arc::new(root);
+  let with_state = warp::any().map(move || (root.clone(), options));
+
+  let options_route =
+    filters::method_is(Method::OPTIONS).map(move || options_response(options));
+
+  let propfind = filters::method_is(method("PROPFIND"))
+    .and(with_state.clone())
+    .and(warp::path::full())
+    .and(warp::header::optional::<String>("depth"))
+    .and_then(|(root, options), path, depth| {
+      propfind(root, options, path, depth)
+    });
+
+  let put = filters::method_is(Method::PUT)
+    .and(with_state.clone())
+    .and(warp::path::full())
+    .and(warp::header::optional::<u64>(header::CONTENT_LENGTH.as_str()))
+    .and(warp::body::stream())
+    .and_then(|(root, options), path, content_length, body| {
+      put(root, options, path, content_length, Box::pin(body))
+    });
+
+  let delete = filters::method_is(Method::DELETE)
+    .and(with_state.clone())
+    .and(warp::path::full())
+    .and_then(|(root, options), path| delete(root, options, path));
+
+  let mkcol = filters::method_is(method("MKCOL"))
+    .and(with_state.clone())
+    .and(warp::path::full())
+    .and(warp::header::optional::<u64>(header::CONTENT_LENGTH.as_str()))
+    .and_then(|(root, options), path, content_length| {
+      mkcol(root, options, path, content_length)
+    });
+
+  let transfer_headers = warp::host::optional()
+    .and(warp::header::optional::<String>("destination"))
+    .and(warp::header::optional::<String>("overwrite"))
+    .and(warp::header::optional::<String>("depth"))
+    .map(|host, destination, overwrite, depth| Transfer {
+      host,
+      destination,
+      overwrite,
+      depth,
+    });
+
+  let moves = filters::method_is(method("MOVE"))
+    .and(with_state.clone())
+    .and(warp::path::full())
+    .and(transfer_headers)
+    .and_then(|(root, options), path, headers| {
+      transfer(root, options, path, headers, true)
+    });
+
+  let copy = filters::method_is(method("COPY"))
+    .and(with_state)
+    .and(warp::path::full())
+    .and(transfer_headers)
+    .and_then(|(root, options), path, headers| {
+      transfer(root, options, path, headers, false)
+    });
+
+  let lock =
+    filters::method_is(method("LOCK")).and(warp::path::full()).map(lock);
+
+  let unlock = filters::method_is(method("UNLOCK"))
+    .map(|| StatusCode::NO_CONTENT.into_response());
+
+  options_route
+    .or(propfind)
+    .unify()
+    .or(put)
+    .unify()
+    .or(delete)
+    .unify()
+    .or(mkcol)
+    .unify()
+    .or(moves)
+    .unify()
+    .or(copy)
+    .unify()
+    .or(lock)
+    .unify()
+    .or(unlock)
+    .unify()
+}
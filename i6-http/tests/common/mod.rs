@@ -0,0 +1,105 @@
+// Each test binary uses only some of the helpers.
+#![allow(dead_code)]
+
+use i6_http::config::ServerConfig;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+/// Creates an empty directory for the fixture `name` in the system temp
+/// directory, replacing what an earlier run left behind.
+pub fn fixture_dir(name: &str) -> PathBuf {
+  let dir = std::env::temp_dir().join(format!(
+    "i6-http-{}-{}",
+    name,
+    std::process::id()
+  ));
+  let _ = std::fs::remove_dir_all(&dir);
+  std::fs::create_dir_all(&dir).unwrap();
+  dir.canonicalize().unwrap()
+}
+
+pub struct TestResponse {
+  pub status: u16,
+  pub headers: HashMap<String, String>,
+  pub body: Vec<u8>,
+}
+
+/// Serves `config` on an ephemeral port.
+pub fn start(config: ServerConfig) -> SocketAddr {
+  let routes = i6_http::routes(&config).unwrap();
+  let (addr, server) = warp::serve(routes).bind_ephemeral(([127, 0, 0, 1], 0));
+  tokio::spawn(server);
+  addr
+}
+
+pub async fn request(
+  addr: SocketAddr,
+  method: &str,
+  path: &str,
+  headers: &[(&str, &str)],
+) -> TestResponse {
+  request_with_body(addr, method, path, headers, b"").await
+}
+
+/// Sends `body` with a `Content-Length` unless it is empty.
+pub async fn request_with_body(
+  addr: SocketAddr,
+  method: &str,
+  path: &str,
+  headers: &[(&str, &str)],
+  body: &[u8],
+) -> TestResponse {
+  let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+
+  let mut request = format!(
+    "{} {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n",
+    method, path, addr
+  );
+  for (name, value) in headers {
+    request.push_str(&format!("{}: {}\r\n", name, value));
+  }
+  if !body.is_empty() {
+    request.push_str(&format!("Content-Length: {}\r\n", body.len()));
+  }
+  request.push_str("\r\n");
+  stream.write_all(request.as_bytes()).await.unwrap();
+  stream.write_all(body).await.unwrap();
+
+  let mut raw = Vec::new();
+  stream.read_to_end(&mut raw).await.unwrap();
+
+  let split = raw.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+  let head = String::from_utf8(raw[..split].to_vec()).unwrap();
+  let mut lines = head.split("\r\n");
+  let status =
+    lines.next().unwrap().split(' ').nth(1).unwrap().parse().unwrap();
+  let headers: HashMap<String, String> = lines
+    .filter_map(|line| line.split_once(": "))
+    .map(|(name, value)| (name.to_ascii_lowercase(), value.to_owned()))
+    .collect();
+
+  let mut body = raw[split + 4..].to_vec();
+  if headers.get("transfer-encoding").is_some_and(|v| v == "chunked") {
+    body = dechunk(&body);
+  }
+
+  TestResponse { status, headers, body }
+}
+
+fn dechunk(mut raw: &[u8]) -> Vec<u8> {
+  let mut body = Vec::new();
+  loop {
+    let line_end = raw.windows(2).position(|w| w == b"\r\n").unwrap();
+    let size = std::str::from_utf8(&raw[..line_end]).unwrap();
+    let size =
+      usize::from_str_radix(size.split(';').next().unwrap(), 16).unwrap();
+    if size == 0 {
+      return body;
+    }
+    raw = &raw[line_end + 2..];
+    body.extend_from_slice(&raw[..size]);
+    raw = &raw[size + 2..];
+  }
+}
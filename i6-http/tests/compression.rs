@@ -0,0 +1,113 @@
+mod common;
+
+use async_compression::tokio::bufread::{GzipDecoder, ZstdDecoder};
+use common::{request, start};
+use i6_http::config::ServerConfig;
+use std::path::PathBuf;
+use tokio::io::AsyncReadExt;
+
+fn fixture(name: &str) -> (PathBuf, String) {
+  let dir = common::fixture_dir(&format!("compression-{}", name));
+
+  let text = "console.log('i6');\n".repeat(500);
+  std::fs::write(dir.join("app.js"), &text).unwrap();
+  std::fs::write(dir.join("app.js.br"), b"precompressed").unwrap();
+  std::fs::write(dir.join("style.css"), &text).unwrap();
+  std::fs::write(dir.join("image.png"), &text).unwrap();
+
+  (dir, text)
+}
+
+#[tokio::test]
+async fn test_compression() {
+  let (root, text) = fixture("dynamic");
+  let addr = start(ServerConfig { root: root.clone(), ..Default::default() });
+
+  let res =
+    request(addr, "GET", "/style.css", &[("Accept-Encoding", "gzip")]).await;
+  assert_eq!(res.status, 200);
+  assert_eq!(res.headers["content-encoding"], "gzip");
+  assert_eq!(res.headers["vary"], "accept-encoding");
+  assert!(res.body.len() < text.len());
+  let mut decoded = String::new();
+  GzipDecoder::new(res.body.as_slice())
+    .read_to_string(&mut decoded)
+    .await
+    .unwrap();
+  assert_eq!(decoded, text);
+
+  let res = request(
+    addr,
+    "GET",
+    "/style.css",
+    &[("Accept-Encoding", "gzip;q=0.5, zstd")],
+  )
+  .await;
+  assert_eq!(res.headers["content-encoding"], "zstd");
+  let mut decoded = String::new();
+  ZstdDecoder::new(res.body.as_slice())
+    .read_to_string(&mut decoded)
+    .await
+    .unwrap();
+  assert_eq!(decoded, text);
+
+  // The encoded representation has its own validator.
+  let etag = res.headers["etag"].clone();
+  let res = request(
+    addr,
+    "GET",
+    "/style.css",
+    &[("Accept-Encoding", "zstd"), ("If-None-Match", &etag)],
+  )
+  .await;
+  assert_eq!(res.status, 304);
+  let res =
+    request(addr, "GET", "/style.css", &[("If-None-Match", &etag)]).await;
+  assert_eq!(res.status, 200);
+
+  let res =
+    request(addr, "GET", "/app.js", &[("Accept-Encoding", "gzip, br")]).await;
+  assert_eq!(res.headers["content-encoding"], "br");
+  assert_eq!(res.headers["content-type"], "text/javascript");
+  assert_eq!(res.body, b"precompressed");
+
+  let res = request(
+    addr,
+    "GET",
+    "/style.css",
+    &[("Accept-Encoding", "gzip"), ("Range", "bytes=0-9")],
+  )
+  .await;
+  assert_eq!(res.status, 206);
+  assert!(!res.headers.contains_key("content-encoding"));
+  assert_eq!(res.body, &text.as_bytes()[..10]);
+
+  let res =
+    request(addr, "GET", "/image.png", &[("Accept-Encoding", "gzip")]).await;
+  assert!(!res.headers.contains_key("content-encoding"));
+
+  let res = request(addr, "GET", "/style.css", &[]).await;
+  assert!(!res.headers.contains_key("content-encoding"));
+  assert_eq!(res.body, text.as_bytes());
+
+  std::fs::remove_dir_all(root).unwrap();
+}
+
+#[tokio::test]
+async fn test_no_compression() {
+  let (root, _) = fixture("disabled");
+  let addr = start(ServerConfig {
+    root: root.clone(),
+    compress: false,
+    ..Default::default()
+  });
+
+  let res =
+    request(addr, "GET", "/style.css", &[("Accept-Encoding", "gzip")]).await;
+  assert!(!res.headers.contains_key("content-encoding"));
+
+  let res = request(addr, "GET", "/app.js", &[("Accept-Encoding", "br")]).await;
+  assert_eq!(res.headers["content-encoding"], "br");
+
+  std::fs::remove_dir_all(root).unwrap();
+}
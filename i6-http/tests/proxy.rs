@@ -0,0 +1,253 @@
+mod common;
+
+use common::{request, request_with_body, start};
+use futures_util::{SinkExt, StreamExt};
+use i6_http::config::ServerConfig;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::Message;
+use warp::http::HeaderMap;
+use warp::hyper::body::Bytes;
+use warp::Filter;
+
+fn fixture(name: &str) -> PathBuf {
+  let dir = common::fixture_dir(&format!("proxy-{}", name));
+  std::fs::write(dir.join("index.html"), "frontend").unwrap();
+  dir
+}
+
+/// Stands in for the backend of a frontend under development.
+fn backend() -> SocketAddr {
+  let echo = warp::path!("api" / "echo")
+    .and(warp::method())
+    .and(warp::path::full())
+    .and(warp::query::raw().or(warp::any().map(String::new)).unify())
+    .and(warp::header::headers_cloned())
+    .and(warp::body::bytes())
+    .map(
+      |method,
+       path: warp::path::FullPath,
+       query,
+       headers: HeaderMap,
+       body: Bytes| {
+        let header = |name: &str| {
+          headers
+            .get(name)
+            .map_or("-", |value| value.to_str().unwrap())
+            .to_owned()
+        };
+        format!(
+          "{} {}?{} host={} from={}/{} secret={} auth={} body={}",
+          method,
+          path.as_str(),
+          query,
+          header("host"),
+          header("x-forwarded-host"),
+          header("x-forwarded-for"),
+          header("x-secret"),
+          header("authorization"),
+          String::from_utf8_lossy(&body),
+        )
+      },
+    );
+
+  let redirect = warp::path!("api" / "redirect").and(warp::header("host")).map(
+    |host: String| {
+      warp::reply::with_header(
+        warp::http::StatusCode::FOUND,
+        "location",
+        format!("http://{}/api/echo", host),
+      )
+    },
+  );
+
+  let slow = warp::path!("api" / "slow").then(|| async {
+    tokio::time::sleep(Duration::from_secs(3)).await;
+    "late"
+  });
+
+  let ws = warp::path!("api" / "ws").and(warp::ws()).map(|ws: warp::ws::Ws| {
+    let reply = ws.on_upgrade(|socket| async {
+      let (tx, rx) = socket.split();
+      let _ = rx.forward(tx).await;
+    });
+    warp::reply::with_header(reply, "sec-websocket-protocol", "chat")
+  });
+
+  let routes = echo
+    .map(warp::Reply::into_response)
+    .or(redirect.map(warp::Reply::into_response))
+    .unify()
+    .or(slow.map(warp::Reply::into_response))
+    .unify()
+    .or(ws.map(warp::Reply::into_response))
+    .unify();
+
+  let (addr, server) = warp::serve(routes).bind_ephemeral(([127, 0, 0, 1], 0));
+  tokio::spawn(server);
+  addr
+}
+
+#[tokio::test]
+async fn test_proxy() {
+  let root = fixture("http");
+  let backend = backend();
+  let addr = start(ServerConfig {
+    root: root.clone(),
+    proxy: vec![format!("/api=http://{}", backend)],
+    ..Default::default()
+  });
+
+  let res = request(addr, "GET", "/", &[]).await;
+  assert_eq!(res.body, b"frontend");
+
+  let res = request_with_body(
+    addr,
+    "POST",
+    "/api/echo?a=1",
+    &[("X-Secret", "kept"), ("Connection", "close, x-secret")],
+    b"payload",
+  )
+  .await;
+  assert_eq!(res.status, 200);
+  assert_eq!(
+    String::from_utf8(res.body).unwrap(),
+    format!(
+      "POST /api/echo?a=1 host={} from={}/127.0.0.1 secret=- auth=- \
+       body=payload",
+      backend, addr
+    )
+  );
+
+  // Without `--auth` the credentials are meant for the backend.
+  let res = request(
+    addr,
+    "GET",
+    "/api/echo?token=backend",
+    &[("Authorization", "Bearer backend")],
+  )
+  .await;
+  let body = String::from_utf8(res.body).unwrap();
+  assert!(body.starts_with("GET /api/echo?token=backend "), "{}", body);
+  assert!(body.contains(" auth=Bearer backend "), "{}", body);
+
+  let res = request(addr, "GET", "/api/redirect", &[]).await;
+  assert_eq!(res.status, 302);
+  assert_eq!(res.headers["location"], "/api/echo");
+
+  let res = request(addr, "GET", "/apis", &[]).await;
+  assert_eq!(res.status, 404);
+
+  std::fs::remove_dir_all(root).unwrap();
+}
+
+#[tokio::test]
+async fn test_proxy_path() {
+  let root = fixture("path");
+  let backend = backend();
+  let addr = start(ServerConfig {
+    root: root.clone(),
+    proxy: vec![format!("/backend=http://{}/api", backend)],
+    ..Default::default()
+  });
+
+  let res = request(addr, "GET", "/backend/echo", &[]).await;
+  assert!(String::from_utf8(res.body).unwrap().starts_with("GET /api/echo?"));
+
+  let res = request(addr, "GET", "/backend/redirect", &[]).await;
+  assert_eq!(res.headers["location"], "/backend/echo");
+
+  std::fs::remove_dir_all(root).unwrap();
+}
+
+#[tokio::test]
+async fn test_proxy_credentials() {
+  let root = fixture("credentials");
+  let backend = backend();
+  let addr = start(ServerConfig {
+    root: root.clone(),
+    proxy: vec![format!("/api=http://{}", backend)],
+    token: Some("t0ken".into()),
+    ..Default::default()
+  });
+
+  let res = request(addr, "GET", "/api/echo?a=1&token=t0ken&b=2", &[]).await;
+  assert_eq!(res.status, 200);
+  let body = String::from_utf8(res.body).unwrap();
+  assert!(body.starts_with("GET /api/echo?a=1&b=2 "), "{}", body);
+
+  let res = request(
+    addr,
+    "GET",
+    "/api/echo",
+    &[("Authorization", "Bearer t0ken"), ("X-Secret", "kept")],
+  )
+  .await;
+  assert_eq!(res.status, 200);
+  let body = String::from_utf8(res.body).unwrap();
+  assert!(body.contains(" secret=kept auth=- "), "{}", body);
+
+  std::fs::remove_dir_all(root).unwrap();
+}
+
+#[tokio::test]
+async fn test_proxy_errors() {
+  let root = fixture("errors");
+  let backend = backend();
+  // Bound and closed again, so nothing listens on it.
+  let closed =
+    std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+  let addr = start(ServerConfig {
+    root: root.clone(),
+    proxy: vec![
+      format!("/api=http://{}", backend),
+      format!("/down=http://{}", closed),
+    ],
+    proxy_timeout: 1,
+    ..Default::default()
+  });
+
+  let res = request(addr, "GET", "/down/", &[]).await;
+  assert_eq!(res.status, 502);
+
+  let res = request(addr, "GET", "/api/slow", &[]).await;
+  assert_eq!(res.status, 504);
+
+  std::fs::remove_dir_all(root).unwrap();
+}
+
+#[tokio::test]
+async fn test_proxy_websocket() {
+  let root = fixture("websocket");
+  let backend = backend();
+  let addr = start(ServerConfig {
+    root: root.clone(),
+    proxy: vec![format!("/api=http://{}", backend)],
+    ..Default::default()
+  });
+
+  let mut request =
+    format!("ws://{}/api/ws", addr).into_client_request().unwrap();
+  request
+    .headers_mut()
+    .insert("sec-websocket-protocol", "chat".parse().unwrap());
+  let (mut socket, response) =
+    tokio_tungstenite::connect_async(request).await.unwrap();
+  assert_eq!(response.headers()["sec-websocket-protocol"], "chat");
+
+  socket.send(Message::Text("hello".into())).await.unwrap();
+  assert_eq!(
+    socket.next().await.unwrap().unwrap(),
+    Message::Text("hello".into())
+  );
+  socket.send(Message::Binary(vec![1, 2, 3])).await.unwrap();
+  assert_eq!(
+    socket.next().await.unwrap().unwrap(),
+    Message::Binary(vec![1, 2, 3])
+  );
+  socket.close(None).await.unwrap();
+
+  std::fs::remove_dir_all(root).unwrap();
+}
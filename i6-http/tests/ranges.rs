@@ -0,0 +1,131 @@
+mod common;
+
+use common::{request, start};
+use i6_http::config::ServerConfig;
+use std::path::PathBuf;
+
+fn fixture(name: &str) -> (PathBuf, Vec<u8>) {
+  let dir = common::fixture_dir(&format!("ranges-{}", name));
+
+  let data: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8).collect();
+  std::fs::write(dir.join("video.mp4"), &data).unwrap();
+
+  (dir, data)
+}
+
+#[tokio::test]
+async fn test_range_requests() {
+  let (root, data) = fixture("range");
+  let addr = start(ServerConfig { root: root.clone(), ..Default::default() });
+
+  let full = request(addr, "GET", "/video.mp4", &[]).await;
+  assert_eq!(full.status, 200);
+  assert_eq!(full.headers["accept-ranges"], "bytes");
+  assert_eq!(full.headers["content-type"], "video/mp4");
+  assert_eq!(full.body, data);
+  let etag = full.headers["etag"].clone();
+
+  let res =
+    request(addr, "GET", "/video.mp4", &[("Range", "bytes=100-199")]).await;
+  assert_eq!(res.status, 206);
+  assert_eq!(res.headers["content-range"], "bytes 100-199/200000");
+  assert_eq!(res.body, &data[100..200]);
+
+  // Resuming a download.
+  let res = request(
+    addr,
+    "GET",
+    "/video.mp4",
+    &[("Range", "bytes=150000-"), ("If-Range", &etag)],
+  )
+  .await;
+  assert_eq!(res.status, 206);
+  assert_eq!(res.body, &data[150_000..]);
+
+  let res = request(addr, "GET", "/video.mp4", &[("Range", "bytes=-10")]).await;
+  assert_eq!(res.body, &data[data.len() - 10..]);
+
+  let res = request(
+    addr,
+    "GET",
+    "/video.mp4",
+    &[("Range", "bytes=0-9"), ("If-Range", "\"stale\"")],
+  )
+  .await;
+  assert_eq!(res.status, 200);
+  assert_eq!(res.body.len(), data.len());
+
+  let res =
+    request(addr, "GET", "/video.mp4", &[("Range", "bytes=300000-")]).await;
+  assert_eq!(res.status, 416);
+  assert_eq!(res.headers["content-range"], "bytes */200000");
+
+  let res =
+    request(addr, "GET", "/video.mp4", &[("Range", "bytes=0-1,10-11")]).await;
+  assert_eq!(res.status, 206);
+  assert!(res.headers["content-type"].starts_with("multipart/byteranges"));
+  assert_eq!(res.headers["content-length"], res.body.len().to_string());
+  let body = String::from_utf8_lossy(&res.body);
+  assert!(body.contains("Content-Range: bytes 0-1/200000"));
+  assert!(body.contains("Content-Range: bytes 10-11/200000"));
+
+  std::fs::remove_dir_all(root).unwrap();
+}
+
+#[tokio::test]
+async fn test_conditional_requests() {
+  let (root, _) = fixture("conditional");
+  let addr = start(ServerConfig { root: root.clone(), ..Default::default() });
+
+  let full = request(addr, "HEAD", "/video.mp4", &[]).await;
+  assert_eq!(full.status, 200);
+  assert_eq!(full.headers["content-length"], "200000");
+  assert!(full.body.is_empty());
+  let etag = full.headers["etag"].clone();
+  let last_modified = full.headers["last-modified"].clone();
+
+  let res =
+    request(addr, "GET", "/video.mp4", &[("If-None-Match", &etag)]).await;
+  assert_eq!(res.status, 304);
+  assert_eq!(res.headers["etag"], etag);
+  assert!(res.body.is_empty());
+
+  let res = request(
+    addr,
+    "GET",
+    "/video.mp4",
+    &[("If-Modified-Since", &last_modified)],
+  )
+  .await;
+  assert_eq!(res.status, 304);
+
+  let res = request(
+    addr,
+    "GET",
+    "/video.mp4",
+    &[("If-None-Match", "\"other\""), ("If-Modified-Since", &last_modified)],
+  )
+  .await;
+  assert_eq!(res.status, 200);
+
+  let res =
+    request(addr, "GET", "/video.mp4", &[("If-Match", "\"other\"")]).await;
+  assert_eq!(res.status, 412);
+
+  std::fs::remove_dir_all(root).unwrap();
+}
+
+#[tokio::test]
+async fn test_cache_control() {
+  let (root, _) = fixture("cache");
+  let addr = start(ServerConfig {
+    root: root.clone(),
+    cache_control: Some("max-age=600".to_owned()),
+    ..Default::default()
+  });
+
+  let res = request(addr, "GET", "/video.mp4", &[]).await;
+  assert_eq!(res.headers["cache-control"], "max-age=600");
+
+  std::fs::remove_dir_all(root).unwrap();
+}
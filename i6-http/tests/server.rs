@@ -0,0 +1,101 @@
+mod common;
+
+use common::{request, start};
+use i6_http::config::ServerConfig;
+use i6_http::server::Server;
+use std::path::PathBuf;
+use std::time::Duration;
+use warp::Filter;
+
+fn fixture(name: &str) -> PathBuf {
+  let dir = common::fixture_dir(&format!("server-{}", name));
+  std::fs::write(dir.join("index.html"), "hello").unwrap();
+  dir
+}
+
+#[tokio::test]
+async fn test_start_and_shutdown() {
+  let root = fixture("start");
+  let server = Server::start(ServerConfig {
+    bind: vec!["127.0.0.1".into(), "127.0.0.1".into()],
+    port: 0,
+    root: root.clone(),
+    quiet: true,
+    ..Default::default()
+  })
+  .await
+  .unwrap();
+
+  let addrs = server.local_addrs().to_vec();
+  assert_eq!(addrs.len(), 2);
+  assert_ne!(addrs[0].port(), 0);
+  assert_ne!(addrs[0], addrs[1]);
+
+  for addr in &addrs {
+    let res = request(*addr, "GET", "/", &[]).await;
+    assert_eq!(res.body, b"hello");
+  }
+
+  server.shutdown_handle().shutdown();
+  server.wait().await.unwrap();
+  assert!(tokio::net::TcpStream::connect(addrs[0]).await.is_err());
+
+  std::fs::remove_dir_all(root).unwrap();
+}
+
+#[tokio::test]
+async fn test_shutdown_drains_requests() {
+  let root = fixture("drain");
+  let slow = warp::path("slow").then(|| async {
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    "done"
+  });
+  let (backend, backend_server) =
+    warp::serve(slow).bind_ephemeral(([127, 0, 0, 1], 0));
+  tokio::spawn(backend_server);
+
+  let server = Server::start(ServerConfig {
+    port: 0,
+    root: root.clone(),
+    proxy: vec![format!("/slow=http://{}/slow", backend)],
+    quiet: true,
+    ..Default::default()
+  })
+  .await
+  .unwrap();
+  let addr = server.local_addr();
+
+  let pending =
+    tokio::spawn(async move { request(addr, "GET", "/slow", &[]).await });
+  tokio::time::sleep(Duration::from_millis(100)).await;
+
+  server.shutdown();
+  server.wait().await.unwrap();
+
+  let res = pending.await.unwrap();
+  assert_eq!(res.status, 200);
+  assert_eq!(res.body, b"done");
+
+  std::fs::remove_dir_all(root).unwrap();
+}
+
+#[tokio::test]
+async fn test_headers_on_rejections() {
+  let root = fixture("rejections");
+  let addr = start(ServerConfig {
+    root: root.clone(),
+    cors: vec!["*".into()],
+    headers: vec!["X-Frame-Options: DENY".into()],
+    ..Default::default()
+  });
+
+  for (method, status) in [("GET", 404), ("DELETE", 405)] {
+    let origin = ("Origin", "http://localhost");
+    let res = request(addr, method, "/missing", &[origin]).await;
+    assert_eq!(res.status, status);
+    assert_eq!(res.headers["access-control-allow-origin"], "*");
+    assert_eq!(res.headers["x-frame-options"], "DENY");
+  }
+
+  std::fs::remove_dir_all(root).unwrap();
+}
@@ -0,0 +1,178 @@
+mod common;
+
+use i6_http::config::ServerConfig;
+use i6_http::server::Server;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio_rustls::rustls::pki_types::pem::PemObject;
+use tokio_rustls::rustls::pki_types::{CertificateDer, ServerName};
+use tokio_rustls::rustls::{ClientConfig, RootCertStore};
+use warp::http::{Request, Version};
+use warp::hyper::body::to_bytes;
+use warp::hyper::Body;
+
+/// Serves a fixture over https with a certificate for `localhost`, returning
+/// the server, the certificate path and the fixture directory.
+async fn start(name: &str, http3: bool) -> (Server, PathBuf, PathBuf) {
+  let dir = common::fixture_dir(&format!("tls-{}", name));
+  std::fs::create_dir_all(dir.join("root")).unwrap();
+  std::fs::write(dir.join("root").join("index.html"), "hello").unwrap();
+
+  let (cert_path, key_path) =
+    i6_http::cert::self_signed_in(&dir.join("cert"), &["localhost".to_owned()])
+      .unwrap();
+
+  let server = Server::start_tls(
+    ServerConfig {
+      port: 0,
+      root: dir.join("root"),
+      http3,
+      log_file: Some(dir.join("access.log")),
+      ..Default::default()
+    },
+    cert_path.clone(),
+    key_path,
+  )
+  .await
+  .unwrap();
+
+  (server, cert_path, dir)
+}
+
+fn root_store(cert_path: &Path) -> RootCertStore {
+  let mut roots = RootCertStore::empty();
+  roots
+    .add(
+      CertificateDer::from_pem_slice(&std::fs::read(cert_path).unwrap())
+        .unwrap(),
+    )
+    .unwrap();
+  roots
+}
+
+/// Fetches `/` like a browser, which offers HTTP/2 and HTTP/1.1.
+async fn get_h2(
+  addr: SocketAddr,
+  cert_path: &Path,
+) -> warp::http::Response<Body> {
+  let mut config = ClientConfig::builder()
+    .with_root_certificates(root_store(cert_path))
+    .with_no_client_auth();
+  config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+
+  let tcp = tokio::net::TcpStream::connect(addr).await.unwrap();
+  let tls = tokio_rustls::TlsConnector::from(Arc::new(config))
+    .connect(ServerName::try_from("localhost").unwrap(), tcp)
+    .await
+    .unwrap();
+  assert_eq!(tls.get_ref().1.alpn_protocol(), Some(&b"h2"[..]));
+
+  let (mut sender, connection) = warp::hyper::client::conn::Builder::new()
+    .http2_only(true)
+    .handshake(tls)
+    .await
+    .unwrap();
+  tokio::spawn(connection);
+
+  let request = Request::get("https://localhost/").body(Body::empty()).unwrap();
+  sender.send_request(request).await.unwrap()
+}
+
+#[tokio::test]
+async fn test_http2() {
+  let (server, cert_path, dir) = start("http2", false).await;
+
+  let res = get_h2(server.local_addr(), &cert_path).await;
+  assert_eq!(res.version(), Version::HTTP_2);
+  assert_eq!(res.status(), 200);
+  assert!(!res.headers().contains_key("alt-svc"));
+  assert_eq!(to_bytes(res.into_body()).await.unwrap(), "hello");
+
+  server.shutdown();
+  server.wait().await.unwrap();
+  std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[cfg(not(feature = "http3"))]
+#[tokio::test]
+async fn test_http3_requires_feature() {
+  let config = ServerConfig { port: 0, http3: true, ..Default::default() };
+  let err = Server::start_tls(config, "cert.pem".into(), "key.pem".into())
+    .await
+    .unwrap_err();
+  assert!(err.to_string().contains("`http3` feature"));
+}
+
+#[cfg(feature = "http3")]
+#[tokio::test]
+async fn test_http3() {
+  use quinn::crypto::rustls::QuicClientConfig;
+  use quinn::rustls;
+  use warp::hyper::body::Buf;
+
+  let (server, cert_path, dir) = start("http3", true).await;
+  let addr = server.local_addr();
+
+  let res = get_h2(addr, &cert_path).await;
+  assert_eq!(
+    res.headers()["alt-svc"],
+    format!("h3=\":{}\"; ma=86400", addr.port())
+  );
+
+  let mut roots = rustls::RootCertStore::empty();
+  roots
+    .add(
+      rustls::pki_types::CertificateDer::from_pem_slice(
+        &std::fs::read(&cert_path).unwrap(),
+      )
+      .unwrap(),
+    )
+    .unwrap();
+  let provider = Arc::new(rustls::crypto::ring::default_provider());
+  let mut tls = rustls::ClientConfig::builder_with_provider(provider)
+    .with_protocol_versions(&[&rustls::version::TLS13])
+    .unwrap()
+    .with_root_certificates(roots)
+    .with_no_client_auth();
+  tls.alpn_protocols = vec![b"h3".to_vec()];
+
+  let mut endpoint =
+    quinn::Endpoint::client("127.0.0.1:0".parse().unwrap()).unwrap();
+  endpoint.set_default_client_config(quinn::ClientConfig::new(Arc::new(
+    QuicClientConfig::try_from(tls).unwrap(),
+  )));
+  let connection = endpoint.connect(addr, "localhost").unwrap().await.unwrap();
+
+  let (mut driver, mut sender) =
+    h3::client::new(h3_quinn::Connection::new(connection)).await.unwrap();
+  tokio::spawn(async move { driver.wait_idle().await });
+
+  let request =
+    http::Request::get(format!("https://localhost:{}/", addr.port()))
+      .body(())
+      .unwrap();
+  let mut stream = sender.send_request(request).await.unwrap();
+  stream.finish().await.unwrap();
+
+  let res = stream.recv_response().await.unwrap();
+  assert_eq!(res.status(), 200);
+  assert!(res.headers().contains_key("alt-svc"));
+
+  let mut body = Vec::new();
+  while let Some(mut chunk) = stream.recv_data().await.unwrap() {
+    body.extend_from_slice(&chunk.copy_to_bytes(chunk.remaining()));
+  }
+  assert_eq!(body, b"hello");
+
+  drop(sender);
+  server.shutdown();
+  server.wait().await.unwrap();
+
+  // The HTTP/3 request is logged with the client address too.
+  let log = std::fs::read_to_string(dir.join("access.log")).unwrap();
+  let lines: Vec<&str> = log.lines().collect();
+  assert_eq!(lines.len(), 2);
+  assert!(lines.iter().all(|line| line.starts_with("127.0.0.1 ")));
+  std::fs::remove_dir_all(dir).unwrap();
+}
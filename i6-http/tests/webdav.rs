@@ -0,0 +1,246 @@
+mod common;
+
+use common::{request, request_with_body, start};
+use i6_http::config::ServerConfig;
+use std::path::PathBuf;
+
+fn fixture(name: &str) -> PathBuf {
+  let dir = common::fixture_dir(&format!("webdav-{}", name));
+  std::fs::create_dir_all(dir.join("docs")).unwrap();
+  std::fs::write(dir.join("docs").join("a b.txt"), "hello").unwrap();
+  std::fs::write(dir.join(".secret"), "hidden").unwrap();
+  dir
+}
+
+fn config(root: &std::path::Path, upload: bool) -> ServerConfig {
+  ServerConfig {
+    root: root.to_owned(),
+    webdav: true,
+    upload,
+    overwrite: upload,
+    ..Default::default()
+  }
+}
+
+#[tokio::test]
+async fn test_webdav_read() {
+  let root = fixture("read");
+  let addr = start(config(&root, false));
+
+  let res = request(addr, "OPTIONS", "/", &[]).await;
+  assert_eq!(res.status, 200);
+  assert_eq!(res.headers["dav"], "1, 2");
+  assert!(!res.headers["allow"].contains("PUT"));
+
+  let res = request(addr, "PROPFIND", "/", &[("Depth", "1")]).await;
+  assert_eq!(res.status, 207);
+  assert!(res.headers["content-type"].starts_with("application/xml"));
+  let body = String::from_utf8(res.body).unwrap();
+  assert!(body.contains("<D:href>/</D:href>"));
+  assert!(body.contains("<D:href>/docs/</D:href>"));
+  assert!(body.contains("<D:collection/>"));
+  assert!(!body.contains("a b.txt"));
+  assert!(!body.contains("secret"));
+
+  let res = request(addr, "PROPFIND", "/docs", &[("Depth", "1")]).await;
+  let body = String::from_utf8(res.body).unwrap();
+  assert!(body.contains("<D:href>/docs/a%20b.txt</D:href>"));
+  assert!(body.contains("<D:getcontentlength>5</D:getcontentlength>"));
+  assert!(body.contains("<D:getcontenttype>text/plain</D:getcontenttype>"));
+
+  let res = request(addr, "PROPFIND", "/docs", &[("Depth", "0")]).await;
+  let body = String::from_utf8(res.body).unwrap();
+  assert_eq!(body.matches("<D:response>").count(), 1);
+
+  let res = request(addr, "PROPFIND", "/missing", &[]).await;
+  assert_eq!(res.status, 404);
+  let res = request(addr, "PROPFIND", "/.secret", &[]).await;
+  assert_eq!(res.status, 404);
+
+  let res = request(addr, "GET", "/docs/a%20b.txt", &[]).await;
+  assert_eq!(res.body, b"hello");
+
+  // Writing needs uploads.
+  let res = request_with_body(addr, "PUT", "/new.txt", &[], b"x").await;
+  assert_eq!(res.status, 403);
+  let res = request(addr, "DELETE", "/docs/a%20b.txt", &[]).await;
+  assert_eq!(res.status, 403);
+  assert!(root.join("docs/a b.txt").exists());
+
+  std::fs::remove_dir_all(root).unwrap();
+}
+
+#[tokio::test]
+async fn test_webdav_write() {
+  let root = fixture("write");
+  let addr = start(config(&root, true));
+
+  let res = request(addr, "MKCOL", "/new", &[]).await;
+  assert_eq!(res.status, 201);
+  assert!(root.join("new").is_dir());
+  let res = request(
+    addr,
+    "COPY",
+    "/new",
+    &[("Destination", "http://example.com/copy")],
+  )
+  .await;
+  assert_eq!(res.status, 502);
+  assert!(!root.join("copy").exists());
+  let res = request(addr, "MKCOL", "/new", &[]).await;
+  assert_eq!(res.status, 405);
+  let res = request(addr, "MKCOL", "/missing/new", &[]).await;
+  assert_eq!(res.status, 409);
+
+  let res = request_with_body(addr, "PUT", "/new/c.txt", &[], b"one").await;
+  assert_eq!(res.status, 201);
+  let res = request_with_body(addr, "PUT", "/new/c.txt", &[], b"two").await;
+  assert_eq!(res.status, 204);
+  assert_eq!(std::fs::read(root.join("new/c.txt")).unwrap(), b"two");
+  let res = request_with_body(addr, "PUT", "/missing/c.txt", &[], b"x").await;
+  assert_eq!(res.status, 409);
+
+  let destination = format!("http://{}/copy", addr);
+  let res =
+    request(addr, "COPY", "/new", &[("Destination", &destination)]).await;
+  assert_eq!(res.status, 201);
+  assert_eq!(std::fs::read(root.join("copy/c.txt")).unwrap(), b"two");
+
+  let res = request(
+    addr,
+    "COPY",
+    "/docs/a%20b.txt",
+    &[("Destination", "/new/c.txt"), ("Overwrite", "F")],
+  )
+  .await;
+  assert_eq!(res.status, 412);
+  let res =
+    request(addr, "MOVE", "/docs/a%20b.txt", &[("Destination", "/new/c.txt")])
+      .await;
+  assert_eq!(res.status, 204);
+  assert_eq!(std::fs::read(root.join("new/c.txt")).unwrap(), b"hello");
+  assert!(!root.join("docs/a b.txt").exists());
+
+  let res =
+    request(addr, "MOVE", "/new", &[("Destination", "/new/inner")]).await;
+  assert_eq!(res.status, 403);
+
+  let res = request(addr, "DELETE", "/copy", &[]).await;
+  assert_eq!(res.status, 204);
+  assert!(!root.join("copy").exists());
+  let res = request(addr, "DELETE", "/copy", &[]).await;
+  assert_eq!(res.status, 404);
+  let res = request(addr, "DELETE", "/", &[]).await;
+  assert_eq!(res.status, 403);
+
+  let res = request(addr, "LOCK", "/new/c.txt", &[]).await;
+  assert_eq!(res.status, 200);
+  let token = &res.headers["lock-token"];
+  assert!(token.starts_with("<opaquelocktoken:"));
+  let res =
+    request(addr, "UNLOCK", "/new/c.txt", &[("Lock-Token", token)]).await;
+  assert_eq!(res.status, 204);
+
+  std::fs::remove_dir_all(root).unwrap();
+}
+
+#[tokio::test]
+async fn test_webdav_no_overwrite() {
+  let root = fixture("no-overwrite");
+  let addr = start(ServerConfig { overwrite: false, ..config(&root, true) });
+
+  let res = request_with_body(addr, "PUT", "/docs/new.txt", &[], b"new").await;
+  assert_eq!(res.status, 201);
+  let res =
+    request_with_body(addr, "PUT", "/docs/a%20b.txt", &[], b"replaced").await;
+  assert_eq!(res.status, 409);
+
+  for method in ["COPY", "MOVE"] {
+    let res = request(
+      addr,
+      method,
+      "/docs/new.txt",
+      &[("Destination", "/docs/a%20b.txt")],
+    )
+    .await;
+    assert_eq!(res.status, 412);
+  }
+  assert_eq!(std::fs::read(root.join("docs/a b.txt")).unwrap(), b"hello");
+  assert!(root.join("docs/new.txt").exists());
+
+  std::fs::remove_dir_all(root).unwrap();
+}
+
+#[tokio::test]
+async fn test_webdav_confined() {
+  let root = fixture("confined");
+  let addr = start(ServerConfig {
+    auth: vec!["user:pass".into()],
+    ..config(&root, true)
+  });
+
+  let res = request(addr, "PROPFIND", "/", &[]).await;
+  assert_eq!(res.status, 401);
+
+  let credentials = format!("Basic {}", {
+    use base64::Engine;
+    base64::engine::general_purpose::STANDARD.encode("user:pass")
+  });
+  let auth = ("Authorization", credentials.as_str());
+
+  let res = request(addr, "PROPFIND", "/", &[auth]).await;
+  assert_eq!(res.status, 207);
+
+  let res = request(
+    addr,
+    "COPY",
+    "/docs/a%20b.txt",
+    &[auth, ("Destination", "/../escape.txt")],
+  )
+  .await;
+  assert_eq!(res.status, 400);
+  let res = request(
+    addr,
+    "MOVE",
+    "/docs/a%20b.txt",
+    &[auth, ("Destination", "/.hidden.txt")],
+  )
+  .await;
+  assert_eq!(res.status, 403);
+  let res = request(addr, "DELETE", "/.secret", &[auth]).await;
+  assert_eq!(res.status, 404);
+  assert!(root.join(".secret").exists());
+
+  #[cfg(unix)]
+  {
+    let outside = root.with_extension("outside");
+    std::fs::create_dir_all(&outside).unwrap();
+    std::os::unix::fs::symlink(&outside, root.join("out")).unwrap();
+
+    let res = request(
+      addr,
+      "COPY",
+      "/docs/a%20b.txt",
+      &[auth, ("Destination", "/out/escape.txt")],
+    )
+    .await;
+    assert_eq!(res.status, 403);
+    let res = request(addr, "MKCOL", "/out/dir", &[auth]).await;
+    assert_eq!(res.status, 403);
+    assert_eq!(std::fs::read_dir(&outside).unwrap().count(), 0);
+
+    // Even when links may be followed for reading, copying one would take
+    // the outside directory into the root.
+    std::fs::write(outside.join("secret.txt"), "outside").unwrap();
+    let addr =
+      start(ServerConfig { follow_symlinks: true, ..config(&root, true) });
+    let res =
+      request(addr, "COPY", "/out", &[("Destination", "/inside")]).await;
+    assert_eq!(res.status, 403);
+    assert!(!root.join("inside").exists());
+
+    std::fs::remove_dir_all(outside).unwrap();
+  }
+
+  std::fs::remove_dir_all(root).unwrap();
+}
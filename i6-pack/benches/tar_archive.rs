@@ -0,0 +1,52 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use i6_pack::compression;
+use std::path::PathBuf;
+
+const DIRS: usize = 20;
+const FILES_PER_DIR: usize = 200;
+const FILE_SIZE: usize = 4096;
+
+/// Creates `tree` in a new temporary directory and returns that directory.
+fn create_fixture() -> PathBuf {
+  let dir = std::env::temp_dir()
+    .join(format!("i6-pack-bench-{}", uuid::Uuid::new_v4()));
+  let root = dir.join("tree");
+
+  for d in 0..DIRS {
+    let dir = root.join(format!("dir-{}", d));
+    std::fs::create_dir_all(&dir).unwrap();
+    for f in 0..FILES_PER_DIR {
+      let contents = vec![(d * FILES_PER_DIR + f) as u8; FILE_SIZE];
+      std::fs::write(dir.join(format!("file-{}.txt", f)), contents).unwrap();
+    }
+  }
+
+  dir
+}
+
+fn bench_create_tar_archive(c: &mut Criterion) {
+  // Packed from inside the fixture, so the archive holds `tree/...` paths.
+  let fixture = create_fixture();
+  std::env::set_current_dir(&fixture).unwrap();
+  let tar_file = "tree.tar";
+
+  let mut group = c.benchmark_group("create_tar_archive");
+  group.sample_size(10);
+
+  group.bench_function("sequential", |b| {
+    b.iter(|| compression::create_tar_archive("tree", tar_file).unwrap())
+  });
+
+  group.bench_function("parallel", |b| {
+    b.iter(|| {
+      compression::create_tar_archive_parallel("tree", tar_file).unwrap()
+    })
+  });
+
+  group.finish();
+
+  std::fs::remove_dir_all(fixture).unwrap();
+}
+
+criterion_group!(benches, bench_create_tar_archive);
+criterion_main!(benches);
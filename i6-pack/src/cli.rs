@@ -1,32 +1,62 @@
 use std::io::Write;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use crate::compression;
 use crate::encryptions;
+use crate::password;
+use crate::recovery;
+use crate::rekey::{self, Rekey};
+use crate::seekable;
+use crate::signing;
 use crate::utils;
 
 use crate::encryptions::encryption::Encryption;
 
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+  /// Pack into independent frames with an index, see `seekable`.
+  pub seekable: bool,
+  /// Only unpack this path, and everything below it.
+  pub path: Option<String>,
+  /// Secret key to sign the packed archive with.
+  pub sign: Option<String>,
+  /// Public key that the archive must be signed with before it is read.
+  pub verify_with: Option<String>,
+  /// Normalise the archive so the same tree always gives the same bytes.
+  pub reproducible: bool,
+  /// Write a Reed–Solomon recovery record with this percentage of parity.
+  pub recovery: Option<u8>,
+  /// Refuse new passwords with a lower strength score, from 0 to 4.
+  pub min_strength: Option<u8>,
+  /// Encrypt with a generated passphrase instead of prompting for one.
+  pub generate_passphrase: bool,
+}
+
 pub fn run(action: &str, target: &str, encrypt: bool) -> std::io::Result<()> {
-  let password = &if encrypt {
-    print!("Enter password: ");
-    std::io::stdout().flush().expect("Failed to flush stdout");
-    let password1 =
-      rpassword::read_password().expect("Failed to read password");
+  run_with_options(action, target, encrypt, &Options::default())
+}
 
+pub fn run_with_options(
+  action: &str,
+  target: &str,
+  encrypt: bool,
+  options: &Options,
+) -> std::io::Result<()> {
+  let password = &if (options.generate_passphrase && action == "pack") {
+    let passphrase =
+      password::generate_passphrase(password::DEFAULT_PASSPHRASE_WORDS);
+    println!("Generated passphrase, it is only shown once:");
+    println!("{}", passphrase);
+    passphrase
+  } else if encrypt {
     if (action == "pack") {
-      print!("Confirm password: ");
-      std::io::stdout().flush().expect("Failed to flush stdout");
-      let password2 =
-        rpassword::read_password().expect("Failed to read password");
-
-      if password1 != password2 {
-        eprintln!("Passwords do not match.");
-        std::process::exit(1);
-      }
+      let password =
+        prompt_new_password("Enter password: ", "Confirm password: ");
+      check_password_strength(&password, options.min_strength);
+      password
+    } else {
+      prompt_password("Enter password: ")
     }
-
-    password1
   } else {
     "".to_owned()
   };
@@ -36,17 +66,194 @@ pub fn run(action: &str, target: &str, encrypt: bool) -> std::io::Result<()> {
     .or_else(|_| utils::sanitize_output_path(target))
     .expect("Invalid target path");
 
-  return run_non_interactive(
+  return run_non_interactive_with_options(
     action,
     target_path.to_str().unwrap_or_default(),
     password,
+    options,
   );
 }
 
+fn prompt_password(prompt: &str) -> String {
+  print!("{}", prompt);
+  std::io::stdout().flush().expect("Failed to flush stdout");
+  rpassword::read_password().expect("Failed to read password")
+}
+
+fn prompt_new_password(prompt: &str, confirm: &str) -> String {
+  let password1 = prompt_password(prompt);
+  let password2 = prompt_password(confirm);
+
+  if password1 != password2 {
+    eprintln!("Passwords do not match.");
+    std::process::exit(1);
+  }
+
+  password1
+}
+
+fn check_password_strength(password: &str, min_strength: Option<u8>) {
+  if password.is_empty() {
+    eprintln!("Password must not be empty.");
+    std::process::exit(1);
+  }
+
+  let strength = password::estimate_strength(password);
+  let feedback = || {
+    for line in &strength.feedback {
+      eprintln!("  {}", line);
+    }
+  };
+
+  match min_strength {
+    Some(min) if strength.score < min => {
+      eprintln!(
+        "Password strength {}/4 is below the required {}/4.",
+        strength.score, min
+      );
+      feedback();
+      std::process::exit(1);
+    }
+    _ if strength.score < password::RECOMMENDED_STRENGTH => {
+      eprintln!("Warning: weak password, strength {}/4.", strength.score);
+      feedback();
+    }
+    _ => {}
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RekeyAction {
+  Change,
+  Add,
+  Remove(usize),
+  List,
+}
+
+pub fn run_rekey(target: &str, action: RekeyAction) -> std::io::Result<()> {
+  let archive = utils::validate_path(target)?;
+  let archive = archive.to_str().unwrap_or_default();
+
+  if (action == RekeyAction::List) {
+    for slot in rekey::active_slots(archive)? {
+      println!("Key slot {}: in use", slot);
+    }
+    return Ok(());
+  }
+
+  let password = prompt_password("Enter current password: ");
+
+  let new_password = |prompt, confirm| {
+    let password = prompt_new_password(prompt, confirm);
+    check_password_strength(&password, None);
+    password
+  };
+
+  let rekey = match action {
+    RekeyAction::Change => Rekey::ChangePassword(new_password(
+      "Enter new password: ",
+      "Confirm new password: ",
+    )),
+    RekeyAction::Add => Rekey::AddPassword(new_password(
+      "Enter additional password: ",
+      "Confirm additional password: ",
+    )),
+    RekeyAction::Remove(slot) => Rekey::RemoveSlot(slot),
+    RekeyAction::List => unreachable!(),
+  };
+
+  if rekey::key_slots_offset(archive)?.is_none() {
+    println!("Upgrading legacy archive to the key slot format...");
+  }
+
+  let slot = rekey::rekey_archive(archive, &password, &rekey)?;
+
+  match rekey {
+    Rekey::ChangePassword(_) => {
+      println!("Changed password in key slot {}", slot)
+    }
+    Rekey::AddPassword(_) => println!("Added password in key slot {}", slot),
+    Rekey::RemoveSlot(_) => println!("Removed key slot {}", slot),
+  }
+
+  Ok(())
+}
+
+pub fn run_keygen(name: &str) -> std::io::Result<()> {
+  let (secret, public) = signing::generate_key_pair(name)?;
+  println!("Wrote secret key {}", secret);
+  println!("Wrote public key {}", public);
+  Ok(())
+}
+
+pub fn run_repair(target: &str) -> std::io::Result<()> {
+  let archive = utils::validate_path(target)?;
+  let report = recovery::repair_archive(archive.to_str().unwrap_or_default())?;
+
+  if (report.repaired == 0 && report.repaired_parity == 0) {
+    println!("No damage found");
+  } else {
+    println!(
+      "Repaired {} damaged archive shards and {} damaged parity shards",
+      report.repaired, report.repaired_parity
+    );
+  }
+
+  Ok(())
+}
+
+pub fn run_mount(
+  target: &str,
+  mountpoint: &str,
+  encrypt: bool,
+) -> std::io::Result<()> {
+  let archive = utils::validate_path(target)?;
+  let password =
+    if encrypt { prompt_password("Enter password: ") } else { "".to_owned() };
+
+  #[cfg(feature = "mount")]
+  {
+    println!(
+      "Mounting {} at {}, unmount with `fusermount -u {}`",
+      archive.display(),
+      mountpoint,
+      mountpoint
+    );
+    crate::mount::mount_archive(
+      archive.to_str().unwrap_or_default(),
+      &password,
+      mountpoint,
+    )
+  }
+
+  #[cfg(not(feature = "mount"))]
+  {
+    let _ = (archive, password, mountpoint);
+    Err(std::io::Error::new(
+      std::io::ErrorKind::Unsupported,
+      "Mounting requires building with the `mount` feature",
+    ))
+  }
+}
+
 pub fn run_non_interactive(
   action: &str,
   target: &str,
   password: &str,
+) -> std::io::Result<()> {
+  run_non_interactive_with_options(
+    action,
+    target,
+    password,
+    &Options::default(),
+  )
+}
+
+pub fn run_non_interactive_with_options(
+  action: &str,
+  target: &str,
+  password: &str,
+  options: &Options,
 ) -> std::io::Result<()> {
   let encrypt = !password.is_empty();
   let extension = if encrypt { "i6pe" } else { "i6p" };
@@ -60,53 +267,153 @@ pub fn run_non_interactive(
 
   let file_out = &format!("{}.{}", target_path.display(), extension);
 
+  if let (Some(public_key), "unpack" | "list" | "verify") =
+    (&options.verify_with, action)
+  {
+    signing::verify_archive(target_path.to_str().unwrap(), public_key)?;
+  }
+
   match action {
     "pack" => {
-      compression::create_tar_archive(target_path.to_str().unwrap(), tar_file)?;
+      let compress_tar_file = if (options.reproducible) {
+        compression::create_tar_archive_reproducible(
+          target_path.to_str().unwrap(),
+          tar_file,
+          utils::source_date_epoch(),
+        )?;
+
+        if (encrypt) {
+          eprintln!(
+            "Warning: encrypted archives use random salts and nonces, \
+             only the unencrypted contents are reproducible"
+          );
+        }
 
-      if (encrypt) {
-        compression::compress_tar_file(tar_file, compressed_file)?;
-        encryptions::cha_cha20_poly1305::ChaCha20Poly1305::encrypt_file(
+        compression::compress_tar_file_reproducible
+      } else {
+        compression::create_tar_archive_parallel(
+          target_path.to_str().unwrap(),
+          tar_file,
+        )?;
+
+        compression::compress_tar_file
+      };
+
+      if (options.seekable) {
+        seekable::compress_tar_file_seekable(tar_file, file_out, password)?;
+      } else if (encrypt) {
+        compress_tar_file(tar_file, compressed_file)?;
+        encryptions::key_wrapped::KeyWrapped::encrypt_file(
           compressed_file,
           file_out,
           password,
         )?;
       } else {
-        compression::compress_tar_file(tar_file, file_out)?;
+        compress_tar_file(tar_file, file_out)?;
+      }
+
+      if let Some(secret_key) = &options.sign {
+        let signature = signing::sign_archive(file_out, secret_key)?;
+        println!("Wrote signature {}", signature);
+      }
+
+      if let Some(percent) = options.recovery {
+        let record = recovery::create_recovery_record(file_out, percent)?;
+        println!("Wrote recovery record {}", record);
       }
     }
     "unpack" => {
-      if (encrypt) {
-        encryptions::cha_cha20_poly1305::ChaCha20Poly1305::decrypt_file(
-          target_path.to_str().unwrap(),
-          compressed_file,
-          password,
-        )?;
-        compression::decompress_file(compressed_file, tar_file)?;
+      let archive = target_path.to_str().unwrap();
+      let output_dir =
+        &utils::remove_extension(archive, &format!(".{extension}"));
+
+      match &options.path {
+        Some(path) if seekable::is_seekable(archive) => {
+          seekable::SeekableArchive::open(archive, password)?
+            .extract(path, utils::unique_output_dir(output_dir))?;
+        }
+        Some(path) => {
+          decompress_archive(archive, password, tar_file, compressed_file)?;
+          compression::extract_tar_entries(
+            tar_file,
+            &utils::unique_output_dir(output_dir),
+            path,
+          )?;
+        }
+        None => {
+          decompress_archive(archive, password, tar_file, compressed_file)?;
+          compression::extract_tar_archive(tar_file, output_dir)?;
+        }
+      }
+    }
+    "list" => {
+      let archive = target_path.to_str().unwrap();
+
+      let entries = if seekable::is_seekable(archive) {
+        seekable::SeekableArchive::open(archive, password)?.entries().to_vec()
       } else {
-        compression::decompress_file(target_path.to_str().unwrap(), tar_file)?;
+        decompress_archive(archive, password, tar_file, compressed_file)?;
+        seekable::index_tar_file(tar_file)?
+      };
+
+      for entry in entries {
+        let suffix =
+          if entry.is_dir() && !entry.path.ends_with('/') { "/" } else { "" };
+        println!("{:>12}  {}{}", entry.size, entry.path, suffix);
+      }
+    }
+    "verify" => {
+      if options.verify_with.is_none() {
+        eprintln!("Verify requires a public key to verify with.");
+        std::process::exit(1);
       }
 
-      compression::extract_tar_archive(
-        tar_file,
-        &utils::remove_extension(
-          target_path.to_str().unwrap(),
-          &format!(".{extension}"),
-        ),
-      )?;
+      println!("Signature OK");
     }
     _ => {
-      eprintln!("Invalid action. Use 'pack' or 'unpack'.");
+      eprintln!("Invalid action. Use 'pack', 'unpack', 'list' or 'verify'.");
       std::process::exit(1);
     }
   }
 
   // Clean up temporary files
-  std::fs::remove_file(tar_file)?;
-
-  if (encrypt) {
-    std::fs::remove_file(compressed_file)?;
+  for file in [tar_file, compressed_file] {
+    if Path::new(file).exists() {
+      std::fs::remove_file(file)?;
+    }
   }
 
   Ok(())
 }
+
+fn decompress_archive(
+  archive: &str,
+  password: &str,
+  tar_file: &str,
+  compressed_file: &str,
+) -> std::io::Result<()> {
+  if (password.is_empty()) {
+    return compression::decompress_file(archive, tar_file);
+  }
+
+  if seekable::is_encrypted(archive)? {
+    return seekable::SeekableArchive::open(archive, password)?
+      .decompress_to(tar_file);
+  }
+
+  if encryptions::key_wrapped::is_key_wrapped(archive)? {
+    encryptions::key_wrapped::KeyWrapped::decrypt_file(
+      archive,
+      compressed_file,
+      password,
+    )?;
+  } else {
+    encryptions::cha_cha20_poly1305::ChaCha20Poly1305::decrypt_file(
+      archive,
+      compressed_file,
+      password,
+    )?;
+  }
+
+  compression::decompress_file(compressed_file, tar_file)
+}
@@ -6,6 +6,9 @@ use walkdir::WalkDir;
 use zstd::stream::{decode_all, encode_all};
 use zstd::Encoder;
 
+use crate::sparse;
+use crate::utils;
+
 pub fn create_tar_archive<P: AsRef<Path>>(
   folder: P,
   tar_file: &str,
@@ -55,23 +58,306 @@ pub fn create_tar_archive<P: AsRef<Path>>(
   Ok(())
 }
 
+/// Number of entries that may be read ahead of the tar writer.
+const PIPELINE_WINDOW: usize = 256;
+
+/// Files up to this size are read by the workers, larger files are streamed
+/// by the writer to bound memory use.
+const PIPELINE_READ_LIMIT: u64 = 1 << 20;
+
+enum PreparedEntry {
+  Dir(fs::Metadata),
+  File(fs::Metadata, Vec<u8>),
+  LargeFile(File, fs::Metadata),
+  Sparse(File, fs::Metadata, Vec<sparse::Segment>),
+  /// FIFOs and devices, which are archived without reading them.
+  Special,
+}
+
+fn prepare_entry(path: &Path) -> io::Result<PreparedEntry> {
+  // Follow symlinks, like `create_tar_archive` which appends the target.
+  let metadata = fs::metadata(path)?;
+
+  if metadata.is_dir() {
+    return Ok(PreparedEntry::Dir(metadata));
+  }
+  if !metadata.is_file() {
+    return Ok(PreparedEntry::Special);
+  }
+
+  let file = File::open(path)?;
+  if let Some(segments) = sparse::data_segments(&file, metadata.len())? {
+    return Ok(PreparedEntry::Sparse(file, metadata, segments));
+  }
+
+  if metadata.len() > PIPELINE_READ_LIMIT {
+    return Ok(PreparedEntry::LargeFile(file, metadata));
+  }
+
+  let mut contents = Vec::with_capacity(metadata.len() as usize);
+  io::Read::read_to_end(&mut &file, &mut contents)?;
+  Ok(PreparedEntry::File(metadata, contents))
+}
+
+/// How entry metadata is written to tar headers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum HeaderOptions {
+  Complete,
+  /// Normalise ownership, permissions and timestamps, using `mtime` when
+  /// given instead of the fixed time of `tar::HeaderMode::Deterministic`.
+  Reproducible {
+    mtime: Option<u64>,
+  },
+}
+
+impl HeaderOptions {
+  fn header(&self, metadata: &fs::Metadata) -> tar::Header {
+    self.apply(tar::Header::new_gnu(), metadata)
+  }
+
+  /// POSIX header for PAX sparse entries, GNU tar ignores the sparse records
+  /// in front of old GNU headers.
+  fn ustar_header(&self, metadata: &fs::Metadata) -> tar::Header {
+    self.apply(tar::Header::new_ustar(), metadata)
+  }
+
+  fn mode(&self) -> tar::HeaderMode {
+    match self {
+      HeaderOptions::Complete => tar::HeaderMode::Complete,
+      HeaderOptions::Reproducible { .. } => tar::HeaderMode::Deterministic,
+    }
+  }
+
+  fn apply(
+    &self,
+    mut header: tar::Header,
+    metadata: &fs::Metadata,
+  ) -> tar::Header {
+    header.set_metadata_in_mode(metadata, self.mode());
+    if let HeaderOptions::Reproducible { mtime: Some(mtime) } = self {
+      header.set_mtime(*mtime);
+    }
+
+    header
+  }
+}
+
+fn append_prepared<W: Write>(
+  archive: &mut Builder<W>,
+  path: &Path,
+  entry: PreparedEntry,
+  options: HeaderOptions,
+) -> io::Result<()> {
+  match entry {
+    PreparedEntry::Dir(metadata) => {
+      let mut header = options.header(&metadata);
+      archive.append_data(&mut header, path, io::empty())
+    }
+    PreparedEntry::File(metadata, contents) => {
+      let mut header = options.header(&metadata);
+      header.set_size(contents.len() as u64);
+      archive.append_data(&mut header, path, contents.as_slice())
+    }
+    PreparedEntry::LargeFile(file, metadata) => {
+      let mut header = options.header(&metadata);
+      archive.append_data(
+        &mut header,
+        path,
+        io::Read::take(file, metadata.len()),
+      )
+    }
+    PreparedEntry::Sparse(file, metadata, segments) => {
+      let mut header = options.ustar_header(&metadata);
+      sparse::append_sparse(
+        archive,
+        &mut header,
+        path,
+        file,
+        metadata.len(),
+        segments,
+      )
+    }
+    // The builder writes the header with the device numbers, and rejects
+    // sockets. Its mode is set from `options` in
+    // `create_tar_archive_pipeline`.
+    PreparedEntry::Special => archive.append_path(path),
+  }
+}
+
+/// Like `create_tar_archive`, but walks directories and reads files on all
+/// cores.
+///
+/// Directories are walked in parallel with entries sorted by name, and
+/// worker threads stat and read files ahead of a single writer. The writer
+/// consumes results strictly in walk order, so the output does not depend on
+/// thread scheduling.
+pub fn create_tar_archive_parallel<P: AsRef<Path>>(
+  folder: P,
+  tar_file: &str,
+) -> io::Result<()> {
+  create_tar_archive_pipeline(folder, tar_file, HeaderOptions::Complete)
+}
+
+/// Like `create_tar_archive_parallel`, but with ownership, permissions and
+/// timestamps normalised so the same tree always gives the same bytes.
+/// Timestamps are set to `mtime` when given, typically `SOURCE_DATE_EPOCH`.
+pub fn create_tar_archive_reproducible<P: AsRef<Path>>(
+  folder: P,
+  tar_file: &str,
+  mtime: Option<u64>,
+) -> io::Result<()> {
+  create_tar_archive_pipeline(
+    folder,
+    tar_file,
+    HeaderOptions::Reproducible { mtime },
+  )
+}
+
+fn create_tar_archive_pipeline<P: AsRef<Path>>(
+  folder: P,
+  tar_file: &str,
+  options: HeaderOptions,
+) -> io::Result<()> {
+  let tar_gz = File::create(tar_file)?;
+  let mut archive = Builder::new(io::BufWriter::new(tar_gz));
+  archive.mode(options.mode());
+
+  let workers = num_cpus::get().max(1);
+  let (job_tx, job_rx) = std::sync::mpsc::channel::<(
+    std::path::PathBuf,
+    std::sync::mpsc::SyncSender<io::Result<PreparedEntry>>,
+  )>();
+  let job_rx = std::sync::Mutex::new(job_rx);
+  let (ordered_tx, ordered_rx) = std::sync::mpsc::sync_channel(PIPELINE_WINDOW);
+
+  std::thread::scope(|s| -> io::Result<()> {
+    for _ in 0..workers {
+      let job_rx = &job_rx;
+      s.spawn(move || loop {
+        let job = job_rx.lock().unwrap().recv();
+        match job {
+          Ok((path, result_tx)) => {
+            let _ = result_tx.send(prepare_entry(&path));
+          }
+          Err(_) => break,
+        }
+      });
+    }
+
+    let folder = folder.as_ref();
+    s.spawn(move || {
+      let walk = jwalk::WalkDir::new(folder)
+        .sort(true)
+        .skip_hidden(false)
+        .follow_links(false);
+
+      for entry in walk {
+        let path = match entry {
+          Ok(e) => e.path(),
+          Err(e) => {
+            eprintln!("Warning: failed to read directory entry: {}", e);
+            continue;
+          }
+        };
+
+        let (result_tx, result_rx) = std::sync::mpsc::sync_channel(1);
+        if ordered_tx.send((path.clone(), result_rx)).is_err()
+          || job_tx.send((path, result_tx)).is_err()
+        {
+          break;
+        }
+      }
+    });
+
+    for (path, result_rx) in ordered_rx {
+      match result_rx.recv() {
+        Ok(Ok(entry)) => {
+          if let Err(e) = append_prepared(&mut archive, &path, entry, options) {
+            eprintln!("Warning: failed to append path {:?}: {}", path, e);
+          }
+        }
+        Ok(Err(e)) => {
+          eprintln!("Warning: failed to read {:?}, skipping: {}", path, e);
+        }
+        Err(_) => {
+          eprintln!("Warning: failed to read {:?}, skipping", path);
+        }
+      }
+    }
+
+    Ok(())
+  })?;
+
+  archive.into_inner()?.flush()
+}
+
 pub fn extract_tar_archive(tar_file: &str, output_dir: &str) -> io::Result<()> {
   let tar_gz = File::open(tar_file)?;
   let mut archive = tar::Archive::new(tar_gz);
 
-  let mut final_output_dir = output_dir.to_string();
-  if Path::new(output_dir).exists() {
-    final_output_dir = format!("{}-{}", output_dir, uuid::Uuid::new_v4());
+  let final_output_dir = utils::unique_output_dir(output_dir);
+
+  sparse::unpack(&mut archive, Path::new(&final_output_dir))
+}
+
+pub fn extract_tar_entries(
+  tar_file: &str,
+  output_dir: &str,
+  path: &str,
+) -> io::Result<usize> {
+  let tar_gz = File::open(tar_file)?;
+  let mut archive = tar::Archive::new(tar_gz);
+
+  std::fs::create_dir_all(output_dir)?;
+
+  let mut extracted = 0;
+  for entry in archive.entries()? {
+    let mut entry = entry?;
+    if utils::path_matches(&sparse::entry_path(&mut entry)?, path) {
+      sparse::unpack_in(&mut entry, Path::new(output_dir))?;
+      extracted += 1;
+    }
   }
 
-  std::fs::create_dir_all(&final_output_dir)?;
-  archive.unpack(&final_output_dir)?;
-  Ok(())
+  if extracted == 0 {
+    return Err(io::Error::new(
+      io::ErrorKind::NotFound,
+      format!("{} not found in archive", path),
+    ));
+  }
+
+  Ok(extracted)
 }
 
 pub fn compress_tar_file(
   tar_file: &str,
   compressed_file: &str,
+) -> io::Result<()> {
+  compress_tar_file_with(tar_file, compressed_file, num_cpus::get() as u32, 0)
+}
+
+/// Like `compress_tar_file`, but with a fixed worker count and job size, so
+/// the output only depends on the input and not on the machine.
+pub fn compress_tar_file_reproducible(
+  tar_file: &str,
+  compressed_file: &str,
+) -> io::Result<()> {
+  compress_tar_file_with(
+    tar_file,
+    compressed_file,
+    REPRODUCIBLE_WORKERS,
+    REPRODUCIBLE_JOB_SIZE,
+  )
+}
+
+const REPRODUCIBLE_WORKERS: u32 = 4;
+const REPRODUCIBLE_JOB_SIZE: u32 = 64 << 20;
+
+fn compress_tar_file_with(
+  tar_file: &str,
+  compressed_file: &str,
+  workers: u32,
+  job_size: u32,
 ) -> io::Result<()> {
   let tar = File::open(tar_file)?;
   let compressed = File::create(compressed_file)?;
@@ -83,7 +369,8 @@ pub fn compress_tar_file(
     &mut compressed_writer,
     compression_level,
   )?;
-  zstd.multithread(num_cpus::get() as u32)?;
+  zstd.multithread(workers)?;
+  zstd.set_parameter(zstd::stream::raw::CParameter::JobSize(job_size))?;
 
   zstd.long_distance_matching(true)?;
   zstd.window_log(31)?;
@@ -120,3 +407,208 @@ pub fn decompress_file(
 
   Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::test_utils;
+
+  fn read_entries(tar_file: &str) -> Vec<(String, bool, Vec<u8>)> {
+    let mut archive = tar::Archive::new(File::open(tar_file).unwrap());
+    let mut entries: Vec<_> = archive
+      .entries()
+      .unwrap()
+      .map(|entry| {
+        let mut entry = entry.unwrap();
+        let mut contents = Vec::new();
+        io::Read::read_to_end(&mut entry, &mut contents).unwrap();
+        (
+          entry.path().unwrap().to_string_lossy().trim_end_matches('/').into(),
+          entry.header().entry_type().is_dir(),
+          contents,
+        )
+      })
+      .collect();
+    entries.sort();
+    entries
+  }
+
+  #[test]
+  fn test_parallel_matches_sequential() {
+    let dir = test_utils::relative_dir();
+    let tree = Path::new(&dir).join("tree");
+    fs::create_dir_all(tree.join("a/b")).unwrap();
+    fs::create_dir_all(tree.join("c")).unwrap();
+    fs::write(tree.join("a/small.txt"), b"small").unwrap();
+    fs::write(tree.join("a/b/large.bin"), vec![7u8; 3 << 20]).unwrap();
+    for i in 0..50 {
+      fs::write(tree.join(format!("c/{}.txt", i)), i.to_string()).unwrap();
+    }
+    #[cfg(unix)]
+    std::os::unix::fs::symlink(
+      fs::canonicalize(tree.join("a/small.txt")).unwrap(),
+      tree.join("link.txt"),
+    )
+    .unwrap();
+
+    let tree = tree.to_str().unwrap();
+    let parallel_a = &format!("{}/parallel-a.tar", dir);
+    let parallel_b = &format!("{}/parallel-b.tar", dir);
+    let sequential = &format!("{}/sequential.tar", dir);
+
+    create_tar_archive_parallel(tree, parallel_a).unwrap();
+    create_tar_archive_parallel(tree, parallel_b).unwrap();
+    create_tar_archive(tree, sequential).unwrap();
+
+    assert_eq!(fs::read(parallel_a).unwrap(), fs::read(parallel_b).unwrap());
+    assert_eq!(read_entries(parallel_a), read_entries(sequential));
+
+    fs::remove_dir_all(dir).unwrap();
+  }
+
+  #[test]
+  fn test_sparse_roundtrip() {
+    let dir = test_utils::relative_dir();
+    let tree = Path::new(&dir).join("tree");
+    fs::create_dir_all(&tree).unwrap();
+
+    // Larger than the 8 GiB that fits in an octal tar size field.
+    let real_size = 9 << 30;
+    let mut file = File::create(tree.join("disk.img")).unwrap();
+    file.set_len(real_size).unwrap();
+    for (offset, data) in
+      [(0, b"head"), (5 << 30, b"5gib"), (real_size - 4, b"tail")]
+    {
+      io::Seek::seek(&mut file, io::SeekFrom::Start(offset)).unwrap();
+      file.write_all(data).unwrap();
+    }
+    drop(file);
+
+    if sparse::data_segments(
+      &File::open(tree.join("disk.img")).unwrap(),
+      real_size,
+    )
+    .unwrap()
+    .is_none()
+    {
+      eprintln!("Skipping, the filesystem does not report holes");
+      return fs::remove_dir_all(dir).unwrap();
+    }
+
+    let tar_file = &format!("{}/sparse.tar", dir);
+    create_tar_archive_parallel(&tree, tar_file).unwrap();
+    assert!(fs::metadata(tar_file).unwrap().len() < 1 << 20);
+
+    let entries = crate::seekable::index_tar_file(tar_file).unwrap();
+    let entry = entries.iter().find(|e| e.path.ends_with("disk.img")).unwrap();
+    assert!(entry.sparse);
+    assert_eq!(entry.size, real_size);
+
+    let output = &format!("{}/output", dir);
+    extract_tar_archive(tar_file, output).unwrap();
+
+    let extracted = Path::new(output).join(&tree);
+    let mut file = File::open(extracted.join("disk.img")).unwrap();
+    assert_eq!(file.metadata().unwrap().len(), real_size);
+    #[cfg(unix)]
+    assert!(
+      std::os::unix::fs::MetadataExt::blocks(&file.metadata().unwrap()) < 2048
+    );
+
+    for (offset, data) in [
+      (0, b"head"),
+      (5 << 30, b"5gib"),
+      (real_size - 4, b"tail"),
+      (1 << 30, &[0; 4]),
+    ] {
+      let mut buf = [0u8; 4];
+      io::Seek::seek(&mut file, io::SeekFrom::Start(offset)).unwrap();
+      io::Read::read_exact(&mut file, &mut buf).unwrap();
+      assert_eq!(&buf, data);
+    }
+
+    fs::remove_dir_all(dir).unwrap();
+  }
+
+  #[test]
+  fn test_large_file_header() {
+    let dir = test_utils::relative_dir();
+    let path = Path::new(&dir).join("file");
+    fs::write(&path, b"").unwrap();
+
+    let mut header =
+      HeaderOptions::Complete.header(&fs::metadata(&path).unwrap());
+    header.set_size(9 << 30);
+
+    let parsed = tar::Header::from_byte_slice(header.as_bytes());
+    assert_eq!(parsed.entry_size().unwrap(), 9 << 30);
+
+    fs::remove_dir_all(dir).unwrap();
+  }
+
+  #[test]
+  fn test_reproducible_ignores_mtime() {
+    let dir = test_utils::relative_dir();
+    let tree = Path::new(&dir).join("tree");
+    fs::create_dir_all(tree.join("a")).unwrap();
+    fs::write(tree.join("a/file.txt"), b"contents").unwrap();
+
+    let pack = |name: &str| {
+      let tar_file = format!("{}/{}.tar", dir, name);
+      let compressed_file = format!("{}/{}.tar.zst", dir, name);
+      create_tar_archive_reproducible(&tree, &tar_file, Some(1700000000))
+        .unwrap();
+      compress_tar_file_reproducible(&tar_file, &compressed_file).unwrap();
+      (fs::read(tar_file).unwrap(), fs::read(compressed_file).unwrap())
+    };
+
+    let first = pack("first");
+    File::options()
+      .write(true)
+      .open(tree.join("a/file.txt"))
+      .unwrap()
+      .set_modified(std::time::UNIX_EPOCH)
+      .unwrap();
+    let second = pack("second");
+    assert_eq!(first, second);
+
+    let mut archive = tar::Archive::new(first.0.as_slice());
+    for entry in archive.entries().unwrap() {
+      let header = entry.unwrap().header().clone();
+      assert_eq!(header.mtime().unwrap(), 1700000000);
+      assert_eq!(header.uid().unwrap(), 0);
+      assert_eq!(header.gid().unwrap(), 0);
+    }
+
+    fs::remove_dir_all(dir).unwrap();
+  }
+
+  #[cfg(unix)]
+  #[test]
+  fn test_parallel_fifo() {
+    let dir = test_utils::relative_dir();
+    let tree = Path::new(&dir).join("tree");
+    fs::create_dir_all(&tree).unwrap();
+    fs::write(tree.join("file.txt"), b"contents").unwrap();
+    let fifo =
+      std::ffi::CString::new(tree.join("fifo").to_str().unwrap().as_bytes())
+        .unwrap();
+    assert_eq!(unsafe { libc::mkfifo(fifo.as_ptr(), 0o644) }, 0);
+
+    // Opening the FIFO for reading would block until a writer shows up.
+    let tar_file = &format!("{}/fifo.tar", dir);
+    create_tar_archive_parallel(tree.to_str().unwrap(), tar_file).unwrap();
+
+    let mut archive = tar::Archive::new(File::open(tar_file).unwrap());
+    let fifo = archive
+      .entries()
+      .unwrap()
+      .map(|entry| entry.unwrap().header().clone())
+      .find(|header| header.path().unwrap().ends_with("fifo"))
+      .unwrap();
+    assert_eq!(fifo.entry_type(), tar::EntryType::Fifo);
+    assert_eq!(fifo.size().unwrap(), 0);
+
+    fs::remove_dir_all(dir).unwrap();
+  }
+}
@@ -51,10 +51,9 @@ impl Encryption for Aes256Gcm {
     let nonce = generate_nonce();
 
     let file_content = std::fs::read(input_file)?;
-    let ciphertext =
-      cipher.encrypt(&nonce, file_content.as_ref()).map_err(|_| {
-        io::Error::new(io::ErrorKind::Other, "Encryption failure")
-      })?;
+    let ciphertext = cipher
+      .encrypt(&nonce, file_content.as_ref())
+      .map_err(|_| io::Error::other("Encryption failure"))?;
 
     let mut output = File::create(output_file)?;
     output.write_all(&salt)?; // Prepend salt
@@ -80,9 +79,7 @@ impl Encryption for Aes256Gcm {
     let nonce = GenericArray::from_slice(nonce);
     let plaintext = match cipher.decrypt(nonce, ciphertext) {
       Ok(pt) => pt,
-      Err(_) => {
-        return Err(io::Error::new(io::ErrorKind::Other, "Decryption failure"))
-      }
+      Err(_) => return Err(io::Error::other("Decryption failure")),
     };
 
     let mut output = File::create(output_file)?;
@@ -21,7 +21,10 @@ fn generate_salt() -> [u8; SALT_LEN] {
   salt
 }
 
-fn derive_key_from_password_argon2(password: &str, salt: &[u8]) -> [u8; 32] {
+pub(crate) fn derive_key_from_password_argon2(
+  password: &str,
+  salt: &[u8],
+) -> [u8; 32] {
   let argon2 = Argon2::default();
   let salt = SaltString::encode_b64(salt).unwrap();
   let password_hash = argon2.hash_password(password.as_bytes(), &salt).unwrap();
@@ -45,10 +48,9 @@ impl Encryption for ChaCha20Poly1305 {
     let nonce = chacha20poly1305::ChaCha20Poly1305::generate_nonce(&mut OsRng);
 
     let file_content = std::fs::read(input_file)?;
-    let ciphertext =
-      cipher.encrypt(&nonce, file_content.as_ref()).map_err(|_| {
-        io::Error::new(io::ErrorKind::Other, "Encryption failure")
-      })?;
+    let ciphertext = cipher
+      .encrypt(&nonce, file_content.as_ref())
+      .map_err(|_| io::Error::other("Encryption failure"))?;
 
     let mut output = File::create(output_file)?;
     output.write_all(&salt)?; // Prepend salt
@@ -74,9 +76,7 @@ impl Encryption for ChaCha20Poly1305 {
     let nonce = GenericArray::from_slice(nonce);
     let plaintext = match cipher.decrypt(nonce, ciphertext) {
       Ok(pt) => pt,
-      Err(_) => {
-        return Err(io::Error::new(io::ErrorKind::Other, "Decryption failure"))
-      }
+      Err(_) => return Err(io::Error::other("Decryption failure")),
     };
 
     let mut output = File::create(output_file)?;
@@ -0,0 +1,54 @@
+use chacha20poly1305::{
+  aead::{Aead, KeyInit},
+  Nonce,
+};
+use rand::RngCore;
+use std::io;
+
+pub const NONCE_PREFIX_LEN: usize = 4;
+
+/// Encrypts a stream as independently authenticated chunks.
+///
+/// Every chunk is sealed with ChaCha20-Poly1305 under the same key, using a
+/// nonce made of a random per-archive prefix followed by the chunk index, so
+/// chunks can be decrypted in any order but not swapped or reordered.
+pub struct ChunkCipher {
+  cipher: chacha20poly1305::ChaCha20Poly1305,
+  nonce_prefix: [u8; NONCE_PREFIX_LEN],
+}
+
+impl ChunkCipher {
+  pub fn new(key: &[u8; 32], nonce_prefix: [u8; NONCE_PREFIX_LEN]) -> Self {
+    Self {
+      cipher: chacha20poly1305::ChaCha20Poly1305::new(key.into()),
+      nonce_prefix,
+    }
+  }
+
+  pub fn generate_nonce_prefix() -> [u8; NONCE_PREFIX_LEN] {
+    let mut prefix = [0u8; NONCE_PREFIX_LEN];
+    rand::thread_rng().fill_bytes(&mut prefix);
+    prefix
+  }
+
+  fn nonce(&self, index: u64) -> Nonce {
+    let mut nonce = [0u8; 12];
+    nonce[..NONCE_PREFIX_LEN].copy_from_slice(&self.nonce_prefix);
+    nonce[NONCE_PREFIX_LEN..].copy_from_slice(&index.to_be_bytes());
+    nonce.into()
+  }
+
+  pub fn seal(&self, index: u64, chunk: &[u8]) -> io::Result<Vec<u8>> {
+    self
+      .cipher
+      .encrypt(&self.nonce(index), chunk)
+      .map_err(|_| io::Error::other("Encryption failure"))
+  }
+
+  pub fn open(&self, index: u64, chunk: &[u8]) -> io::Result<Vec<u8>> {
+    self
+      .cipher
+      .decrypt(&self.nonce(index), chunk)
+      .map_err(|_| io::Error::other("Decryption failure"))
+  }
+}
@@ -0,0 +1,86 @@
+use chacha20poly1305::{
+  aead::{Aead, AeadCore, KeyInit, OsRng},
+  Nonce,
+};
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+use super::encryption::Encryption;
+use super::keyslots::{self, KeySlots};
+
+pub const MAGIC: &[u8; 8] = b"i6pkeys\x01";
+
+const NONCE_LEN: usize = 12;
+const HEADER_LEN: usize = MAGIC.len() + keyslots::HEADER_LEN;
+
+/// Returns true if the file starts with the key wrapped header.
+pub fn is_key_wrapped<P: AsRef<Path>>(file: P) -> io::Result<bool> {
+  let mut magic = [0u8; MAGIC.len()];
+  match File::open(file)?.read_exact(&mut magic) {
+    Ok(()) => Ok(&magic == MAGIC),
+    Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+    Err(e) => Err(e),
+  }
+}
+
+/// ChaCha20-Poly1305 with a random data key stored in password key slots, so
+/// the password can be changed without re-encrypting the file.
+pub struct KeyWrapped;
+
+impl Encryption for KeyWrapped {
+  fn encrypt_file(
+    input_file: &str,
+    output_file: &str,
+    password: &str,
+  ) -> io::Result<()> {
+    let data_key = KeySlots::generate_data_key();
+    let slots = KeySlots::new(&data_key, password)?;
+    let cipher = chacha20poly1305::ChaCha20Poly1305::new((&data_key).into());
+    let nonce = chacha20poly1305::ChaCha20Poly1305::generate_nonce(&mut OsRng);
+
+    let file_content = std::fs::read(input_file)?;
+    let ciphertext = cipher
+      .encrypt(&nonce, file_content.as_ref())
+      .map_err(|_| io::Error::other("Encryption failure"))?;
+
+    let mut output = File::create(output_file)?;
+    output.write_all(MAGIC)?;
+    output.write_all(&slots.to_bytes())?;
+    output.write_all(nonce.as_slice())?;
+    output.write_all(&ciphertext)?;
+
+    Ok(())
+  }
+
+  fn decrypt_file(
+    input_file: &str,
+    output_file: &str,
+    password: &str,
+  ) -> io::Result<()> {
+    let file_content = std::fs::read(input_file)?;
+    if file_content.len() < HEADER_LEN + NONCE_LEN
+      || &file_content[..MAGIC.len()] != MAGIC
+    {
+      return Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        "Not a key wrapped archive",
+      ));
+    }
+
+    let (header, rest) = file_content.split_at(HEADER_LEN);
+    let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+
+    let slots = KeySlots::from_bytes(&header[MAGIC.len()..])?;
+    let (_, data_key) = slots.unlock(password)?;
+    let cipher = chacha20poly1305::ChaCha20Poly1305::new((&data_key).into());
+
+    let plaintext = cipher
+      .decrypt(Nonce::from_slice(nonce), ciphertext)
+      .map_err(|_| io::Error::other("Decryption failure"))?;
+
+    let mut output = File::create(output_file)?;
+    output.write_all(&plaintext)?;
+    Ok(())
+  }
+}
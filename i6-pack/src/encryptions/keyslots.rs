@@ -0,0 +1,195 @@
+use chacha20poly1305::{
+  aead::{Aead, KeyInit},
+  Nonce,
+};
+use rand::RngCore;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+use super::cha_cha20_poly1305::derive_key_from_password_argon2;
+
+pub const MAX_SLOTS: usize = 8;
+pub const KEY_LEN: usize = 32;
+
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+const SLOT_LEN: usize = 1 + SALT_LEN + NONCE_LEN + KEY_LEN + TAG_LEN;
+
+/// Size of the serialized key slots. It is fixed, so the header of an
+/// existing archive can be rewritten in place without moving the payload.
+pub const HEADER_LEN: usize = MAX_SLOTS * SLOT_LEN;
+
+#[derive(Debug, Clone)]
+struct KeySlot {
+  salt: [u8; SALT_LEN],
+  nonce: [u8; NONCE_LEN],
+  wrapped_key: [u8; KEY_LEN + TAG_LEN],
+}
+
+impl KeySlot {
+  fn wrap(data_key: &[u8; KEY_LEN], password: &str) -> io::Result<Self> {
+    let mut salt = [0u8; SALT_LEN];
+    let mut nonce = [0u8; NONCE_LEN];
+    rand::thread_rng().fill_bytes(&mut salt);
+    rand::thread_rng().fill_bytes(&mut nonce);
+
+    let key = derive_key_from_password_argon2(password, &salt);
+    let cipher = chacha20poly1305::ChaCha20Poly1305::new((&key).into());
+    let wrapped = cipher
+      .encrypt(Nonce::from_slice(&nonce), data_key.as_ref())
+      .map_err(|_| io::Error::other("Encryption failure"))?;
+
+    Ok(Self { salt, nonce, wrapped_key: wrapped.try_into().unwrap() })
+  }
+
+  fn unwrap_key(&self, password: &str) -> Option<[u8; KEY_LEN]> {
+    let key = derive_key_from_password_argon2(password, &self.salt);
+    let cipher = chacha20poly1305::ChaCha20Poly1305::new((&key).into());
+    let data_key = cipher
+      .decrypt(Nonce::from_slice(&self.nonce), self.wrapped_key.as_ref())
+      .ok()?;
+
+    data_key.try_into().ok()
+  }
+}
+
+/// Password slots protecting a random data key, similar to LUKS keyslots.
+///
+/// The archive payload is encrypted with the data key, and every active slot
+/// holds a copy of that key encrypted with a key derived from one password.
+/// Changing, adding or removing a password only rewrites the slots.
+#[derive(Debug, Clone, Default)]
+pub struct KeySlots {
+  slots: [Option<KeySlot>; MAX_SLOTS],
+}
+
+impl KeySlots {
+  pub fn generate_data_key() -> [u8; KEY_LEN] {
+    let mut key = [0u8; KEY_LEN];
+    rand::thread_rng().fill_bytes(&mut key);
+    key
+  }
+
+  pub fn new(data_key: &[u8; KEY_LEN], password: &str) -> io::Result<Self> {
+    let mut slots = Self::default();
+    slots.add(data_key, password)?;
+    Ok(slots)
+  }
+
+  /// Returns the first slot that the password opens, and the data key.
+  pub fn unlock(&self, password: &str) -> io::Result<(usize, [u8; KEY_LEN])> {
+    self
+      .slots
+      .iter()
+      .enumerate()
+      .find_map(|(i, slot)| Some((i, slot.as_ref()?.unwrap_key(password)?)))
+      .ok_or_else(|| io::Error::other("Decryption failure"))
+  }
+
+  pub fn add(
+    &mut self,
+    data_key: &[u8; KEY_LEN],
+    password: &str,
+  ) -> io::Result<usize> {
+    let i =
+      self.slots.iter().position(|slot| slot.is_none()).ok_or_else(|| {
+        io::Error::other(format!("All {} key slots are in use", MAX_SLOTS))
+      })?;
+
+    self.slots[i] = Some(KeySlot::wrap(data_key, password)?);
+    Ok(i)
+  }
+
+  pub fn replace(
+    &mut self,
+    slot: usize,
+    data_key: &[u8; KEY_LEN],
+    password: &str,
+  ) -> io::Result<()> {
+    self.slots[slot] = Some(KeySlot::wrap(data_key, password)?);
+    Ok(())
+  }
+
+  pub fn remove(&mut self, slot: usize) -> io::Result<()> {
+    if self.slots.get(slot).map(Option::is_none).unwrap_or(true) {
+      return Err(io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("Key slot {} is not in use", slot),
+      ));
+    }
+
+    if self.active().count() == 1 {
+      return Err(io::Error::new(
+        io::ErrorKind::InvalidInput,
+        "Cannot remove the last key slot",
+      ));
+    }
+
+    self.slots[slot] = None;
+    Ok(())
+  }
+
+  pub fn active(&self) -> impl Iterator<Item = usize> + '_ {
+    self.slots.iter().enumerate().filter(|(_, s)| s.is_some()).map(|(i, _)| i)
+  }
+
+  pub fn to_bytes(&self) -> Vec<u8> {
+    let mut out = Vec::with_capacity(HEADER_LEN);
+
+    for slot in &self.slots {
+      match slot {
+        Some(slot) => {
+          out.push(1);
+          out.extend_from_slice(&slot.salt);
+          out.extend_from_slice(&slot.nonce);
+          out.extend_from_slice(&slot.wrapped_key);
+        }
+        None => out.resize(out.len() + SLOT_LEN, 0),
+      }
+    }
+
+    out
+  }
+
+  pub fn from_bytes(bytes: &[u8]) -> io::Result<Self> {
+    if bytes.len() != HEADER_LEN {
+      return Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        "Corrupt key slots",
+      ));
+    }
+
+    let mut slots = Self::default();
+
+    for (i, chunk) in bytes.chunks_exact(SLOT_LEN).enumerate() {
+      if chunk[0] == 0 {
+        continue;
+      }
+
+      let (salt, rest) = chunk[1..].split_at(SALT_LEN);
+      let (nonce, wrapped_key) = rest.split_at(NONCE_LEN);
+
+      slots.slots[i] = Some(KeySlot {
+        salt: salt.try_into().unwrap(),
+        nonce: nonce.try_into().unwrap(),
+        wrapped_key: wrapped_key.try_into().unwrap(),
+      });
+    }
+
+    Ok(slots)
+  }
+
+  pub fn read_from(file: &mut File, offset: u64) -> io::Result<Self> {
+    let mut bytes = vec![0u8; HEADER_LEN];
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(&mut bytes)?;
+    Self::from_bytes(&bytes)
+  }
+
+  pub fn write_to(&self, file: &mut File, offset: u64) -> io::Result<()> {
+    file.seek(SeekFrom::Start(offset))?;
+    file.write_all(&self.to_bytes())?;
+    file.sync_all()
+  }
+}
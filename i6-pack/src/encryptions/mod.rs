@@ -1,3 +1,6 @@
 pub mod aes256_gcm;
 pub mod cha_cha20_poly1305;
+pub mod chunked;
 pub mod encryption;
+pub mod key_wrapped;
+pub mod keyslots;
@@ -1,4 +1,14 @@
 pub mod cli;
 pub mod compression;
 pub mod encryptions;
+#[cfg(feature = "mount")]
+pub mod mount;
+pub mod password;
+pub mod recovery;
+pub mod rekey;
+pub mod seekable;
+pub mod signing;
+pub mod sparse;
+#[cfg(test)]
+mod test_utils;
 pub mod utils;
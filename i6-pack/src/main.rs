@@ -12,7 +12,7 @@ fn main() -> io::Result<()> {
     )
     .arg(
       Arg::new("action")
-        .help("Action to perform: pack or unpack")
+        .help("Action to perform: pack, unpack, list, verify, repair, rekey, keygen or mount")
         .required(true)
         .index(1),
     )
@@ -22,6 +22,11 @@ fn main() -> io::Result<()> {
         .required(true)
         .index(2),
     )
+    .arg(
+      Arg::new("mountpoint")
+        .help("mount: directory to mount the archive at")
+        .index(3),
+    )
     .arg(
       Arg::new("encrypt")
         .help("Flag to indicate encryption/decryption")
@@ -29,11 +34,116 @@ fn main() -> io::Result<()> {
         .long("encrypt")
         .action(clap::ArgAction::SetTrue),
     )
+    .arg(
+      Arg::new("seekable")
+        .help("Compress into independent frames with an index for fast access")
+        .short('s')
+        .long("seekable")
+        .action(clap::ArgAction::SetTrue),
+    )
+    .arg(
+      Arg::new("recovery")
+        .help("pack: write a recovery record with this much parity, e.g. 5%")
+        .long("recovery")
+        .value_parser(i6_pack::recovery::parse_percent),
+    )
+    .arg(
+      Arg::new("min-strength")
+        .help("pack: refuse passwords with a lower strength score (0-4)")
+        .long("min-strength")
+        .value_parser(clap::value_parser!(u8).range(0..=4)),
+    )
+    .arg(
+      Arg::new("generate-passphrase")
+        .help("pack: encrypt with a generated passphrase, printed once")
+        .long("generate-passphrase")
+        .action(clap::ArgAction::SetTrue),
+    )
+    .arg(
+      Arg::new("reproducible")
+        .help("Normalise ordering, ownership and timestamps for identical output")
+        .long("reproducible")
+        .action(clap::ArgAction::SetTrue),
+    )
+    .arg(
+      Arg::new("path")
+        .help("Only unpack this path from the archive")
+        .short('p')
+        .long("path"),
+    )
+    .arg(
+      Arg::new("add-slot")
+        .help("rekey: add a password in a free key slot")
+        .long("add-slot")
+        .action(clap::ArgAction::SetTrue),
+    )
+    .arg(
+      Arg::new("remove-slot")
+        .help("rekey: remove the password in the given key slot")
+        .long("remove-slot")
+        .value_parser(clap::value_parser!(usize)),
+    )
+    .arg(
+      Arg::new("list-slots")
+        .help("rekey: list the key slots in use")
+        .long("list-slots")
+        .action(clap::ArgAction::SetTrue),
+    )
+    .arg(
+      Arg::new("sign")
+        .help("pack: sign the archive with this Ed25519 secret key")
+        .long("sign"),
+    )
+    .arg(
+      Arg::new("verify-with")
+        .help("Refuse archives that are not signed by this public key")
+        .long("verify-with"),
+    )
     .get_matches();
 
   let action = matches.get_one::<String>("action").unwrap();
   let target = matches.get_one::<String>("target").unwrap();
   let encrypt = matches.get_flag("encrypt");
 
-  cli::run(action, target, encrypt)
+  if action == "keygen" {
+    return cli::run_keygen(target);
+  }
+
+  if action == "repair" {
+    return cli::run_repair(target);
+  }
+
+  if action == "mount" {
+    let mountpoint = matches
+      .get_one::<String>("mountpoint")
+      .expect("mount requires a mountpoint");
+    return cli::run_mount(target, mountpoint, encrypt);
+  }
+
+  if action == "rekey" {
+    let action = if matches.get_flag("list-slots") {
+      cli::RekeyAction::List
+    } else if matches.get_flag("add-slot") {
+      cli::RekeyAction::Add
+    } else if let Some(slot) = matches.get_one::<usize>("remove-slot") {
+      cli::RekeyAction::Remove(*slot)
+    } else {
+      cli::RekeyAction::Change
+    };
+
+    return cli::run_rekey(target, action);
+  }
+
+  let options = cli::Options {
+    seekable: matches.get_flag("seekable"),
+    path: matches.get_one::<String>("path").cloned(),
+    sign: matches.get_one::<String>("sign").cloned(),
+    verify_with: matches.get_one::<String>("verify-with").cloned(),
+    reproducible: matches.get_flag("reproducible"),
+    recovery: matches.get_one::<u8>("recovery").copied(),
+    min_strength: matches.get_one::<u8>("min-strength").copied(),
+    generate_passphrase: matches.get_flag("generate-passphrase"),
+  };
+
+  cli::run_with_options(action, target, encrypt, &options)
 }
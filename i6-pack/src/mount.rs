@@ -0,0 +1,575 @@
+//! Read-only FUSE filesystem over a seekable archive.
+//!
+//! Directory listings are served from the archive index, and reads only
+//! decompress the frames that hold the requested bytes. Hardlinks share the
+//! data of their target, and device entries are left out as they could not
+//! be opened from a read-only mount anyway.
+
+use fuser::{
+  FileAttr, FileType, Filesystem, MountOption, ReplyAttr, ReplyData,
+  ReplyDirectory, ReplyEntry, ReplyOpen, Request,
+};
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::{OsStr, OsString};
+use std::io;
+use std::path::Path;
+use std::time::{Duration, UNIX_EPOCH};
+
+use crate::seekable::{self, IndexEntry, SeekableArchive};
+use crate::sparse::{self, Segment};
+
+const TTL: Duration = Duration::from_secs(3600);
+const ROOT_INO: u64 = 1;
+
+struct Node {
+  parent: u64,
+  entry: Option<usize>,
+  is_dir: bool,
+  children: BTreeMap<OsString, u64>,
+}
+
+pub struct ArchiveFs {
+  archive: SeekableArchive,
+  nodes: Vec<Node>,
+  /// Sparse maps of the sparse entries read so far, with the map length.
+  sparse_maps: HashMap<usize, (Vec<Segment>, u64)>,
+  uid: u32,
+  gid: u32,
+}
+
+impl ArchiveFs {
+  pub fn new(archive: SeekableArchive) -> Self {
+    let mut fs = Self {
+      archive,
+      nodes: vec![Node {
+        parent: ROOT_INO,
+        entry: None,
+        is_dir: true,
+        children: BTreeMap::new(),
+      }],
+      sparse_maps: HashMap::new(),
+      uid: unsafe { libc::getuid() },
+      gid: unsafe { libc::getgid() },
+    };
+
+    let entries: Vec<(usize, IndexEntry)> =
+      fs.archive.entries().iter().cloned().enumerate().collect();
+
+    // Hardlinks always follow their target in the archive.
+    let mut paths: HashMap<Vec<&str>, usize> = HashMap::new();
+    for (i, e) in &entries {
+      let entry = match tar::EntryType::new(e.entry_type) {
+        tar::EntryType::Char | tar::EntryType::Block => continue,
+        tar::EntryType::Link => {
+          let target = e.link.as_deref().map(components);
+          match target.and_then(|target| paths.get(&target)) {
+            Some(target) => *target,
+            None => continue,
+          }
+        }
+        _ => *i,
+      };
+      paths.insert(components(&e.path), entry);
+      fs.insert(entry, &e.path, e.is_dir());
+    }
+
+    fs
+  }
+
+  fn node(&self, ino: u64) -> Option<&Node> {
+    self.nodes.get(ino.checked_sub(1)? as usize)
+  }
+
+  fn insert(&mut self, entry: usize, path: &str, is_dir: bool) {
+    let components = components(path);
+
+    let mut parent = ROOT_INO;
+    for (depth, name) in components.iter().enumerate() {
+      let last = depth + 1 == components.len();
+      let name = OsString::from(name);
+
+      let ino = match self.nodes[parent as usize - 1].children.get(&name) {
+        Some(ino) => *ino,
+        None => {
+          self.nodes.push(Node {
+            parent,
+            entry: None,
+            is_dir: !last || is_dir,
+            children: BTreeMap::new(),
+          });
+          let ino = self.nodes.len() as u64;
+          self.nodes[parent as usize - 1].children.insert(name, ino);
+          ino
+        }
+      };
+
+      if last {
+        self.nodes[ino as usize - 1].entry = Some(entry);
+      }
+      parent = ino;
+    }
+  }
+
+  fn entry(&self, ino: u64) -> Option<&IndexEntry> {
+    self.archive.entries().get(self.node(ino)?.entry?)
+  }
+
+  fn kind(&self, ino: u64) -> Option<FileType> {
+    if self.node(ino)?.is_dir {
+      return Some(FileType::Directory);
+    }
+    let kind = match self.entry(ino).map(|e| tar::EntryType::new(e.entry_type))
+    {
+      Some(tar::EntryType::Symlink) => FileType::Symlink,
+      Some(tar::EntryType::Fifo) => FileType::NamedPipe,
+      _ => FileType::RegularFile,
+    };
+    Some(kind)
+  }
+
+  /// Returns the target of the symlink at `ino`.
+  fn read_link(&self, ino: u64) -> Option<&str> {
+    if self.kind(ino)? != FileType::Symlink {
+      return None;
+    }
+    self.entry(ino)?.link.as_deref()
+  }
+
+  fn read_exact_at(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+    let mut filled = 0;
+    while filled < buf.len() {
+      match self.archive.read_at(offset + filled as u64, &mut buf[filled..])? {
+        0 => return Err(io::ErrorKind::UnexpectedEof.into()),
+        n => filled += n,
+      }
+    }
+    Ok(())
+  }
+
+  /// Reads `buf.len()` bytes at `offset` of the file in the sparse entry,
+  /// filling holes with zeros.
+  fn read_sparse(
+    &mut self,
+    entry: usize,
+    offset: u64,
+    buf: &mut [u8],
+  ) -> io::Result<()> {
+    let (data_offset, end) = {
+      let e = &self.archive.entries()[entry];
+      (e.data_offset, e.end)
+    };
+
+    if !self.sparse_maps.contains_key(&entry) {
+      let map = sparse::read_map(&mut self.archive.range(data_offset, end))?;
+      self.sparse_maps.insert(entry, map);
+    }
+    let (segments, map_len) = self.sparse_maps[&entry].clone();
+
+    buf.fill(0);
+    let end = offset + buf.len() as u64;
+    let mut raw = data_offset + map_len;
+    for segment in segments {
+      let from = segment.offset.max(offset);
+      let to = (segment.offset + segment.len).min(end);
+      if from < to {
+        let target = &mut buf[(from - offset) as usize..(to - offset) as usize];
+        self.read_exact_at(raw + from - segment.offset, target)?;
+      }
+      raw += segment.len;
+    }
+
+    Ok(())
+  }
+
+  /// Reads up to `size` bytes at `offset` of the file at `ino`, fewer at its
+  /// end.
+  fn read_file(
+    &mut self,
+    ino: u64,
+    offset: u64,
+    size: u32,
+  ) -> io::Result<Vec<u8>> {
+    if self.kind(ino) != Some(FileType::RegularFile) {
+      return Err(io::ErrorKind::NotFound.into());
+    }
+    let (entry, data_offset, len, is_sparse) = self
+      .node(ino)
+      .and_then(|node| node.entry)
+      .map(|i| {
+        let e = &self.archive.entries()[i];
+        (i, e.data_offset, e.size, e.sparse)
+      })
+      .ok_or(io::ErrorKind::NotFound)?;
+
+    let size = (size as u64).min(len.saturating_sub(offset)) as usize;
+    let mut buf = vec![0u8; size];
+    if is_sparse {
+      self.read_sparse(entry, offset, &mut buf)?;
+      return Ok(buf);
+    }
+
+    let mut filled = 0;
+    while filled < size {
+      let pos = data_offset + offset + filled as u64;
+      match self.archive.read_at(pos, &mut buf[filled..])? {
+        0 => break,
+        n => filled += n,
+      }
+    }
+    buf.truncate(filled);
+
+    Ok(buf)
+  }
+
+  fn attr(&self, ino: u64) -> Option<FileAttr> {
+    let node = self.node(ino)?;
+    let entry = self.entry(ino);
+
+    let kind = self.kind(ino)?;
+    let (size, default_perm) = match kind {
+      FileType::Directory => (0, 0o755),
+      FileType::Symlink => {
+        (self.read_link(ino).map_or(0, str::len) as u64, 0o777)
+      }
+      _ => (entry.map(|e| e.size).unwrap_or(0), 0o644),
+    };
+    let perm = entry.map(|e| e.mode & 0o7777).unwrap_or(default_perm) as u16;
+    let mtime =
+      UNIX_EPOCH + Duration::from_secs(entry.map(|e| e.mtime).unwrap_or(0));
+
+    Some(FileAttr {
+      ino,
+      size,
+      blocks: size.div_ceil(512),
+      atime: mtime,
+      mtime,
+      ctime: mtime,
+      crtime: mtime,
+      kind,
+      perm,
+      nlink: if node.is_dir { 2 } else { 1 },
+      uid: self.uid,
+      gid: self.gid,
+      rdev: 0,
+      blksize: 512,
+      flags: 0,
+    })
+  }
+}
+
+impl Filesystem for ArchiveFs {
+  fn lookup(
+    &mut self,
+    _req: &Request<'_>,
+    parent: u64,
+    name: &OsStr,
+    reply: ReplyEntry,
+  ) {
+    let attr = self
+      .node(parent)
+      .and_then(|node| node.children.get(name))
+      .and_then(|ino| self.attr(*ino));
+
+    match attr {
+      Some(attr) => reply.entry(&TTL, &attr, 0),
+      None => reply.error(libc::ENOENT),
+    }
+  }
+
+  fn getattr(
+    &mut self,
+    _req: &Request<'_>,
+    ino: u64,
+    _fh: Option<u64>,
+    reply: ReplyAttr,
+  ) {
+    match self.attr(ino) {
+      Some(attr) => reply.attr(&TTL, &attr),
+      None => reply.error(libc::ENOENT),
+    }
+  }
+
+  fn readlink(&mut self, _req: &Request<'_>, ino: u64, reply: ReplyData) {
+    match (self.node(ino), self.read_link(ino)) {
+      (_, Some(target)) => reply.data(target.as_bytes()),
+      (Some(_), None) => reply.error(libc::EINVAL),
+      (None, None) => reply.error(libc::ENOENT),
+    }
+  }
+
+  fn open(
+    &mut self,
+    _req: &Request<'_>,
+    ino: u64,
+    flags: i32,
+    reply: ReplyOpen,
+  ) {
+    match self.node(ino) {
+      None => reply.error(libc::ENOENT),
+      Some(node) if node.is_dir => reply.error(libc::EISDIR),
+      Some(_) if flags & libc::O_ACCMODE != libc::O_RDONLY => {
+        reply.error(libc::EROFS)
+      }
+      Some(_) => reply.opened(0, 0),
+    }
+  }
+
+  fn read(
+    &mut self,
+    _req: &Request<'_>,
+    ino: u64,
+    _fh: u64,
+    offset: i64,
+    size: u32,
+    _flags: i32,
+    _lock_owner: Option<u64>,
+    reply: ReplyData,
+  ) {
+    match self.read_file(ino, offset.max(0) as u64, size) {
+      Ok(data) => reply.data(&data),
+      Err(e) if e.kind() == io::ErrorKind::NotFound => {
+        reply.error(libc::ENOENT)
+      }
+      Err(e) => {
+        eprintln!("Warning: failed to read archive: {}", e);
+        reply.error(libc::EIO)
+      }
+    }
+  }
+
+  fn readdir(
+    &mut self,
+    _req: &Request<'_>,
+    ino: u64,
+    _fh: u64,
+    offset: i64,
+    mut reply: ReplyDirectory,
+  ) {
+    let Some(node) = self.node(ino) else {
+      return reply.error(libc::ENOENT);
+    };
+    if !node.is_dir {
+      return reply.error(libc::ENOTDIR);
+    }
+
+    let mut listing = vec![
+      (ino, FileType::Directory, OsString::from(".")),
+      (node.parent, FileType::Directory, OsString::from("..")),
+    ];
+    for (name, child) in &node.children {
+      let kind = self.kind(*child).unwrap_or(FileType::RegularFile);
+      listing.push((*child, kind, name.clone()));
+    }
+
+    for (i, (ino, kind, name)) in
+      listing.into_iter().enumerate().skip(offset.max(0) as usize)
+    {
+      if reply.add(ino, (i + 1) as i64, kind, name) {
+        break;
+      }
+    }
+
+    reply.ok();
+  }
+}
+
+/// Splits an archive path into its components, without `.` and `..`.
+fn components(path: &str) -> Vec<&str> {
+  path.split('/').filter(|c| !c.is_empty() && *c != "." && *c != "..").collect()
+}
+
+/// Mounts a seekable archive read-only at `mountpoint`, blocking until it is
+/// unmounted.
+pub fn mount_archive<P: AsRef<Path>>(
+  archive: &str,
+  password: &str,
+  mountpoint: P,
+) -> io::Result<()> {
+  if !seekable::is_seekable(archive) {
+    return Err(io::Error::new(
+      io::ErrorKind::InvalidInput,
+      "Only seekable archives can be mounted, pack with --seekable",
+    ));
+  }
+
+  let fs = ArchiveFs::new(SeekableArchive::open(archive, password)?);
+
+  fuser::mount2(
+    fs,
+    mountpoint,
+    &[
+      MountOption::RO,
+      MountOption::FSName("i6-pack".to_owned()),
+      MountOption::DefaultPermissions,
+    ],
+  )
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{compression, test_utils};
+  use std::fs;
+  use std::io::Write;
+
+  /// Packs `tar_file` into a seekable archive next to it and opens it.
+  fn open(tar_file: &str) -> ArchiveFs {
+    let archive = format!("{}.i6p", tar_file);
+    seekable::compress_tar_file_seekable(tar_file, &archive, "").unwrap();
+    ArchiveFs::new(SeekableArchive::open(&archive, "").unwrap())
+  }
+
+  fn lookup(fs: &ArchiveFs, path: &str) -> Option<u64> {
+    path.split('/').try_fold(ROOT_INO, |ino, name| {
+      fs.node(ino)?.children.get(OsStr::new(name)).copied()
+    })
+  }
+
+  fn names(fs: &ArchiveFs, ino: u64) -> Vec<&str> {
+    let children = &fs.node(ino).unwrap().children;
+    children.keys().map(|name| name.to_str().unwrap()).collect()
+  }
+
+  #[test]
+  fn test_tree() {
+    let dir = test_utils::temp_dir();
+    let tar_file = dir.join("tree.tar");
+    let tar_file = tar_file.to_str().unwrap();
+
+    let mut builder = tar::Builder::new(fs::File::create(tar_file).unwrap());
+    let mut append = |path: &str, data: &[u8], entry_type| {
+      let mut header = tar::Header::new_gnu();
+      header.set_entry_type(entry_type);
+      header.set_mode(0o640);
+      header.set_mtime(1_700_000_000);
+      header.set_size(data.len() as u64);
+      builder.append_data(&mut header, path, data).unwrap();
+    };
+    append("./top/", b"", tar::EntryType::Directory);
+    append("top/a.txt", b"aaa", tar::EntryType::Regular);
+    // Parents without entries of their own.
+    append("implicit/dir/b.txt", b"bb", tar::EntryType::Regular);
+    append("top/empty/", b"", tar::EntryType::Directory);
+    append("top/fifo", b"", tar::EntryType::Fifo);
+    append("top/null", b"", tar::EntryType::Char);
+    for (path, target, entry_type) in [
+      ("top/link", "a.txt", tar::EntryType::Symlink),
+      ("top/hard", "./top/a.txt", tar::EntryType::Link),
+      ("top/dangling", "top/missing", tar::EntryType::Link),
+    ] {
+      let mut header = tar::Header::new_gnu();
+      header.set_entry_type(entry_type);
+      header.set_mode(0o777);
+      header.set_size(0);
+      builder.append_link(&mut header, path, target).unwrap();
+    }
+    builder.finish().unwrap();
+    drop(builder);
+
+    let mut fs = open(tar_file);
+    assert_eq!(names(&fs, ROOT_INO), ["implicit", "top"]);
+    // Devices and hardlinks without a target are left out.
+    assert_eq!(
+      names(&fs, lookup(&fs, "top").unwrap()),
+      ["a.txt", "empty", "fifo", "hard", "link"]
+    );
+    assert_eq!(lookup(&fs, "top/missing"), None);
+
+    let implicit = lookup(&fs, "implicit/dir").unwrap();
+    assert!(fs.node(implicit).unwrap().is_dir);
+    assert_eq!(
+      fs.node(implicit).unwrap().parent,
+      lookup(&fs, "implicit").unwrap()
+    );
+    let attr = fs.attr(implicit).unwrap();
+    assert_eq!((attr.kind, attr.perm), (FileType::Directory, 0o755));
+
+    let attr = fs.attr(lookup(&fs, "top/a.txt").unwrap()).unwrap();
+    assert_eq!(attr.kind, FileType::RegularFile);
+    assert_eq!((attr.size, attr.perm), (3, 0o640));
+    assert_eq!(attr.mtime, UNIX_EPOCH + Duration::from_secs(1_700_000_000));
+    let attr = fs.attr(lookup(&fs, "top/empty").unwrap()).unwrap();
+    assert_eq!(attr.kind, FileType::Directory);
+
+    let link = lookup(&fs, "top/link").unwrap();
+    let attr = fs.attr(link).unwrap();
+    assert_eq!((attr.kind, attr.size), (FileType::Symlink, 5));
+    assert_eq!(fs.read_link(link), Some("a.txt"));
+    assert!(fs.read_file(link, 0, 10).is_err());
+    assert_eq!(fs.read_link(lookup(&fs, "top/a.txt").unwrap()), None);
+
+    let hard = lookup(&fs, "top/hard").unwrap();
+    let attr = fs.attr(hard).unwrap();
+    assert_eq!(attr.kind, FileType::RegularFile);
+    assert_eq!((attr.size, attr.perm), (3, 0o640));
+    assert_eq!(fs.read_file(hard, 0, 10).unwrap(), b"aaa");
+
+    let fifo = fs.attr(lookup(&fs, "top/fifo").unwrap()).unwrap();
+    assert_eq!(fifo.kind, FileType::NamedPipe);
+
+    assert_eq!(fs.node(ROOT_INO).unwrap().parent, ROOT_INO);
+    assert!(fs.attr(0).is_none());
+    assert!(fs.attr(fs.nodes.len() as u64 + 1).is_none());
+
+    fs::remove_dir_all(dir).unwrap();
+  }
+
+  #[test]
+  fn test_read_file() {
+    let dir = test_utils::relative_dir();
+    let tree = Path::new(&dir).join("tree");
+    fs::create_dir_all(&tree).unwrap();
+
+    fs::write(tree.join("small.txt"), b"hello").unwrap();
+    let large: Vec<u8> =
+      (0..seekable::FRAME_SIZE * 2 + 7).map(|i| (i % 251) as u8).collect();
+    fs::write(tree.join("large.bin"), &large).unwrap();
+
+    let sparse_len = 3 << 20;
+    let mut file = fs::File::create(tree.join("sparse.img")).unwrap();
+    file.set_len(sparse_len).unwrap();
+    for offset in [0, 2 << 20] {
+      io::Seek::seek(&mut file, io::SeekFrom::Start(offset)).unwrap();
+      file.write_all(b"data").unwrap();
+    }
+    drop(file);
+
+    let tar_file = format!("{}/tree.tar", dir);
+    compression::create_tar_archive_parallel(&tree, &tar_file).unwrap();
+    let mut fs = open(&tar_file);
+    let ino = |fs: &ArchiveFs, name: &str| {
+      lookup(fs, &format!("{}/{}", tree.display(), name)).unwrap()
+    };
+
+    let small = ino(&fs, "small.txt");
+    assert_eq!(fs.read_file(small, 0, 100).unwrap(), b"hello");
+    assert_eq!(fs.read_file(small, 1, 3).unwrap(), b"ell");
+    assert!(fs.read_file(small, 5, 10).unwrap().is_empty());
+    assert!(fs.read_file(small, 100, 10).unwrap().is_empty());
+
+    // Reads across frame boundaries and up to the end of the file.
+    let large_ino = ino(&fs, "large.bin");
+    let offset = seekable::FRAME_SIZE - 10;
+    assert_eq!(
+      fs.read_file(large_ino, offset, 20).unwrap(),
+      &large[offset as usize..offset as usize + 20]
+    );
+    let offset = large.len() as u64 - 5;
+    assert_eq!(
+      fs.read_file(large_ino, offset, 100).unwrap(),
+      &large[offset as usize..]
+    );
+
+    let sparse = ino(&fs, "sparse.img");
+    assert_eq!(fs.attr(sparse).unwrap().size, sparse_len);
+    assert_eq!(fs.read_file(sparse, 0, 6).unwrap(), b"data\0\0");
+    assert_eq!(fs.read_file(sparse, (2 << 20) - 2, 6).unwrap(), b"\0\0data");
+    assert_eq!(fs.read_file(sparse, sparse_len - 4, 100).unwrap(), [0; 4]);
+
+    // The root has no entry in the archive.
+    let err = fs.read_file(ROOT_INO, 0, 1).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::NotFound);
+
+    fs::remove_dir_all(dir).unwrap();
+  }
+}
@@ -0,0 +1,84 @@
+use rand::seq::SliceRandom;
+
+/// The EFF large wordlist for diceware passphrases, licensed CC BY 3.0 US by
+/// the Electronic Frontier Foundation, see
+/// https://www.eff.org/deeplinks/2016/07/new-wordlists-random-passphrases
+const WORDLIST: &str = include_str!("wordlists/eff_large_wordlist.txt");
+
+/// Six words from 7772 give about 77 bits of entropy.
+pub const DEFAULT_PASSPHRASE_WORDS: usize = 6;
+
+/// Scores below this get a warning when packing.
+pub const RECOMMENDED_STRENGTH: u8 = 3;
+
+pub struct Strength {
+  /// From 0, guessable within 10^3 guesses, to 4, more than 10^10 guesses.
+  pub score: u8,
+  pub feedback: Vec<String>,
+}
+
+pub fn estimate_strength(password: &str) -> Strength {
+  let entropy = zxcvbn::zxcvbn(password, &[]);
+
+  let mut feedback = Vec::new();
+  if let Some(f) = entropy.feedback() {
+    if let Some(warning) = f.warning() {
+      feedback.push(warning.to_string());
+    }
+    feedback.extend(f.suggestions().iter().map(|s| s.to_string()));
+  }
+
+  Strength { score: entropy.score() as u8, feedback }
+}
+
+/// The words of the list, without the four that contain a dash, like
+/// `t-shirt`, so that the words of a passphrase can be told apart.
+fn words() -> Vec<&'static str> {
+  WORDLIST
+    .lines()
+    .filter_map(|line| line.split_once('\t').map(|(_, word)| word))
+    .filter(|word| !word.contains('-'))
+    .collect()
+}
+
+/// Generates a diceware style passphrase of `count` words joined with dashes.
+pub fn generate_passphrase(count: usize) -> String {
+  let words = words();
+  let mut rng = rand::rngs::OsRng;
+
+  (0..count)
+    .map(|_| *words.choose(&mut rng).unwrap())
+    .collect::<Vec<_>>()
+    .join("-")
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_wordlist() {
+    let words = words();
+    assert_eq!(words.len(), 7772);
+    assert_eq!(words[0], "abacus");
+    assert!(!words.contains(&"t-shirt"));
+  }
+
+  #[test]
+  fn test_generate_passphrase() {
+    let passphrase = generate_passphrase(DEFAULT_PASSPHRASE_WORDS);
+    let words = words();
+    let parts: Vec<&str> = passphrase.split('-').collect();
+    assert_eq!(parts.len(), DEFAULT_PASSPHRASE_WORDS);
+    assert!(parts.iter().all(|part| words.contains(part)));
+    assert_ne!(passphrase, generate_passphrase(DEFAULT_PASSPHRASE_WORDS));
+    assert_eq!(estimate_strength(&passphrase).score, 4);
+  }
+
+  #[test]
+  fn test_estimate_strength() {
+    let weak = estimate_strength("password");
+    assert_eq!(weak.score, 0);
+    assert!(!weak.feedback.is_empty());
+  }
+}
@@ -0,0 +1,471 @@
+//! Reed–Solomon recovery records for archives.
+//!
+//! The record is stored next to the archive in `<archive>.i6r`. The archive is
+//! split into equally sized data shards, which are grouped and protected by
+//! parity shards. A SHA-256 hash of every shard locates the damaged ones, and
+//! a group can be rebuilt as long as no more of its shards are damaged than it
+//! has parity shards.
+
+use reed_solomon_erasure::galois_8::ReedSolomon;
+use sha2::{Digest, Sha256};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+pub const MAGIC: &[u8; 8] = b"i6precv\x01";
+
+/// Data shards per group, leaving room for parity in the 256 shards that
+/// GF(2^8) allows.
+const GROUP_SHARDS: u64 = 128;
+const MIN_SHARD_SIZE: u64 = 512;
+const MAX_SHARD_SIZE: u64 = 256 * 1024;
+const HASH_LEN: usize = 32;
+const HEADER_LEN: usize = MAGIC.len() + 8 + 4 + 1;
+
+pub fn recovery_path(archive: &str) -> String {
+  format!("{}.i6r", archive)
+}
+
+/// Parses a redundancy like `5%` or `5` into a percentage from 1 to 100.
+pub fn parse_percent(value: &str) -> Result<u8, String> {
+  let percent: u8 = value
+    .trim()
+    .trim_end_matches('%')
+    .parse()
+    .map_err(|_| format!("invalid percentage {:?}", value))?;
+
+  if (1..=100).contains(&percent) {
+    Ok(percent)
+  } else {
+    Err("percentage must be between 1 and 100".to_owned())
+  }
+}
+
+fn corrupt(message: &str) -> io::Error {
+  io::Error::new(io::ErrorKind::InvalidData, message.to_owned())
+}
+
+fn hash(shard: &[u8]) -> [u8; HASH_LEN] {
+  Sha256::digest(shard).into()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Layout {
+  archive_len: u64,
+  shard_size: u64,
+  percent: u8,
+}
+
+impl Layout {
+  fn new(archive_len: u64, percent: u8) -> Self {
+    let shard_size = archive_len
+      .div_ceil(GROUP_SHARDS)
+      .clamp(MIN_SHARD_SIZE, MAX_SHARD_SIZE)
+      .next_multiple_of(MIN_SHARD_SIZE);
+
+    Self { archive_len, shard_size, percent }
+  }
+
+  fn data_shards(&self) -> u64 {
+    self.archive_len.div_ceil(self.shard_size).max(1)
+  }
+
+  /// Returns the first data shard and the data and parity shard counts of
+  /// every group.
+  fn groups(&self) -> Vec<(u64, usize, usize)> {
+    let total = self.data_shards();
+
+    (0..total)
+      .step_by(GROUP_SHARDS as usize)
+      .map(|first| {
+        let data = (total - first).min(GROUP_SHARDS) as usize;
+        let parity = (data * self.percent as usize).div_ceil(100).max(1);
+        (first, data, parity)
+      })
+      .collect()
+  }
+
+  /// Sum of the parity shards of `groups`, without listing the groups of a
+  /// layout that may be corrupt.
+  fn parity_shards(&self) -> u64 {
+    let parity = |data: u64| (data * self.percent as u64).div_ceil(100).max(1);
+    let total = self.data_shards();
+    let rest = total % GROUP_SHARDS;
+
+    (total / GROUP_SHARDS) * parity(GROUP_SHARDS)
+      + if (rest > 0) { parity(rest) } else { 0 }
+  }
+
+  fn to_bytes(self) -> Vec<u8> {
+    let mut out = MAGIC.to_vec();
+    out.extend_from_slice(&self.archive_len.to_le_bytes());
+    out.extend_from_slice(&(self.shard_size as u32).to_le_bytes());
+    out.push(self.percent);
+    out
+  }
+
+  fn from_bytes(bytes: &[u8; HEADER_LEN]) -> io::Result<Self> {
+    if (&bytes[..MAGIC.len()] != MAGIC) {
+      return Err(corrupt("Not an i6-pack recovery record"));
+    }
+
+    let archive_len = u64::from_le_bytes(bytes[8..16].try_into().unwrap());
+    let shard_size = u32::from_le_bytes(bytes[16..20].try_into().unwrap());
+    let percent = bytes[20];
+
+    if (shard_size == 0 || !(1..=100).contains(&percent)) {
+      return Err(corrupt("Corrupt recovery record header"));
+    }
+
+    Ok(Self { archive_len, shard_size: shard_size as u64, percent })
+  }
+}
+
+/// The hash table of a recovery record, the parity shards follow it.
+struct Record {
+  layout: Layout,
+  data_hashes: Vec<[u8; HASH_LEN]>,
+  parity_hashes: Vec<[u8; HASH_LEN]>,
+}
+
+impl Record {
+  fn table_len(layout: &Layout) -> io::Result<u64> {
+    layout
+      .data_shards()
+      .checked_add(layout.parity_shards())
+      .and_then(|shards| shards.checked_mul(HASH_LEN as u64))
+      .ok_or_else(|| corrupt("Corrupt recovery record header"))
+  }
+
+  /// Offset of the first parity shard in the record file, after the header,
+  /// the hash table and the hash that covers both.
+  fn parity_offset(layout: &Layout) -> io::Result<u64> {
+    Self::table_len(layout)?
+      .checked_add((HEADER_LEN + HASH_LEN) as u64)
+      .ok_or_else(|| corrupt("Corrupt recovery record header"))
+  }
+
+  fn write_header(&self, file: &mut File) -> io::Result<()> {
+    let mut header = self.layout.to_bytes();
+    for hash in self.data_hashes.iter().chain(&self.parity_hashes) {
+      header.extend_from_slice(hash);
+    }
+    let checksum = hash(&header);
+    header.extend_from_slice(&checksum);
+
+    file.seek(SeekFrom::Start(0))?;
+    file.write_all(&header)
+  }
+
+  /// Reads the layout, checking that its hash table fits in the file before
+  /// anything is allocated for it.
+  fn read_layout(file: &mut File) -> io::Result<Layout> {
+    let mut header = [0u8; HEADER_LEN];
+    file.seek(SeekFrom::Start(0))?;
+    file.read_exact(&mut header)?;
+    let layout = Layout::from_bytes(&header)?;
+
+    if (Self::parity_offset(&layout)? > file.metadata()?.len()) {
+      return Err(corrupt("Corrupt recovery record header"));
+    }
+
+    Ok(layout)
+  }
+
+  /// Reads the hash table that follows the header of `layout`, see
+  /// `read_layout`.
+  fn read_table(file: &mut File, layout: Layout) -> io::Result<Self> {
+    let header = layout.to_bytes();
+    file.seek(SeekFrom::Start(HEADER_LEN as u64))?;
+
+    let mut table = vec![0u8; Self::table_len(&layout)? as usize];
+    let mut checksum = [0u8; HASH_LEN];
+    file.read_exact(&mut table)?;
+    file.read_exact(&mut checksum)?;
+
+    let mut hasher = Sha256::new();
+    hasher.update(&header);
+    hasher.update(&table);
+    if (<[u8; HASH_LEN]>::from(hasher.finalize()) != checksum) {
+      return Err(corrupt("The recovery record itself is damaged"));
+    }
+
+    let mut hashes: Vec<[u8; HASH_LEN]> = table
+      .chunks_exact(HASH_LEN)
+      .map(|chunk| chunk.try_into().unwrap())
+      .collect();
+    let parity_hashes = hashes.split_off(layout.data_shards() as usize);
+
+    Ok(Self { layout, data_hashes: hashes, parity_hashes })
+  }
+}
+
+/// Reads data shard `index`, padding the last one with zeros.
+fn read_shard(
+  file: &mut File,
+  layout: &Layout,
+  index: u64,
+) -> io::Result<Vec<u8>> {
+  let offset = index * layout.shard_size;
+  let len = layout.archive_len.saturating_sub(offset).min(layout.shard_size);
+
+  let mut shard = vec![0u8; layout.shard_size as usize];
+  file.seek(SeekFrom::Start(offset))?;
+  file.read_exact(&mut shard[..len as usize])?;
+  Ok(shard)
+}
+
+fn write_shard(
+  file: &mut File,
+  layout: &Layout,
+  index: u64,
+  shard: &[u8],
+) -> io::Result<()> {
+  let offset = index * layout.shard_size;
+  let len = layout.archive_len.saturating_sub(offset).min(layout.shard_size);
+
+  file.seek(SeekFrom::Start(offset))?;
+  file.write_all(&shard[..len as usize])
+}
+
+fn codec(data: usize, parity: usize) -> io::Result<ReedSolomon> {
+  ReedSolomon::new(data, parity)
+    .map_err(|e| io::Error::other(format!("{:?}", e)))
+}
+
+/// Writes a recovery record for `archive` with `percent` parity, returning
+/// its path.
+pub fn create_recovery_record(
+  archive: &str,
+  percent: u8,
+) -> io::Result<String> {
+  let mut file = File::open(archive)?;
+  let layout = Layout::new(file.metadata()?.len(), percent);
+
+  let mut record =
+    Record { layout, data_hashes: Vec::new(), parity_hashes: Vec::new() };
+
+  // Parity shards are written as they are encoded, the hash table in front
+  // of them is filled in at the end.
+  let path = recovery_path(archive);
+  let mut out = File::create(&path)?;
+  out.seek(SeekFrom::Start(Record::parity_offset(&layout)?))?;
+
+  for (first, data, parity) in layout.groups() {
+    let mut shards = (first..first + data as u64)
+      .map(|i| read_shard(&mut file, &layout, i))
+      .collect::<io::Result<Vec<_>>>()?;
+    shards.resize(data + parity, vec![0u8; layout.shard_size as usize]);
+
+    codec(data, parity)?
+      .encode(&mut shards)
+      .map_err(|e| io::Error::other(format!("{:?}", e)))?;
+
+    record.data_hashes.extend(shards[..data].iter().map(|s| hash(s)));
+    for shard in &shards[data..] {
+      record.parity_hashes.push(hash(shard));
+      out.write_all(shard)?;
+    }
+  }
+
+  record.write_header(&mut out)?;
+  out.sync_all()?;
+
+  Ok(path)
+}
+
+/// Rebuilds the recovery record of `archive` with the same redundancy, if it
+/// has one. Needed after the archive is changed on purpose, for example by
+/// `rekey`, or a repair would revert the change.
+pub fn refresh_recovery_record(archive: &str) -> io::Result<()> {
+  let path = recovery_path(archive);
+  let percent = match File::open(&path) {
+    Ok(mut file) => Record::read_layout(&mut file)?.percent,
+    Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+    Err(e) => return Err(e),
+  };
+
+  create_recovery_record(archive, percent).map(|_| ())
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RepairReport {
+  /// Damaged data shards that were rebuilt in the archive.
+  pub repaired: usize,
+  /// Damaged parity shards that were rebuilt in the recovery record.
+  pub repaired_parity: usize,
+}
+
+/// Checks `archive` against its recovery record and rebuilds damaged shards in
+/// place. Fails without writing anything to a group that has more damaged
+/// shards than parity shards.
+pub fn repair_archive(archive: &str) -> io::Result<RepairReport> {
+  let path = recovery_path(archive);
+  let mut record_file =
+    OpenOptions::new().read(true).write(true).open(&path).map_err(|e| {
+      io::Error::new(e.kind(), format!("No recovery record {}: {}", path, e))
+    })?;
+  let layout = Record::read_layout(&mut record_file)?;
+
+  let mut file = OpenOptions::new().read(true).write(true).open(archive)?;
+  if (file.metadata()?.len() != layout.archive_len) {
+    return Err(corrupt("Archive length does not match its recovery record"));
+  }
+  let record = Record::read_table(&mut record_file, layout)?;
+  let parity_offset = Record::parity_offset(&layout)?;
+
+  let mut report = RepairReport::default();
+  let mut parity_index = 0;
+
+  for (first, data, parity) in layout.groups() {
+    let mut shards = Vec::with_capacity(data + parity);
+
+    for i in 0..data {
+      let shard = read_shard(&mut file, &layout, first + i as u64)?;
+      let intact = hash(&shard) == record.data_hashes[first as usize + i];
+      shards.push(intact.then_some(shard));
+    }
+
+    for i in 0..parity {
+      let offset =
+        parity_offset + (parity_index + i) as u64 * layout.shard_size;
+      let mut shard = vec![0u8; layout.shard_size as usize];
+      record_file.seek(SeekFrom::Start(offset))?;
+      let intact = record_file.read_exact(&mut shard).is_ok()
+        && hash(&shard) == record.parity_hashes[parity_index + i];
+      shards.push(intact.then_some(shard));
+    }
+
+    let damaged: Vec<usize> =
+      (0..data + parity).filter(|i| shards[*i].is_none()).collect();
+
+    if (!damaged.is_empty()) {
+      codec(data, parity)?.reconstruct(&mut shards).map_err(|_| {
+        corrupt(&format!(
+          "Too much damage to repair, {} shards are damaged in a group with \
+           {} parity shards",
+          damaged.len(),
+          parity
+        ))
+      })?;
+
+      for i in damaged {
+        let shard = shards[i].as_ref().unwrap();
+        if (i < data) {
+          write_shard(&mut file, &layout, first + i as u64, shard)?;
+          report.repaired += 1;
+        } else {
+          let offset = parity_offset
+            + (parity_index + i - data) as u64 * layout.shard_size;
+          record_file.seek(SeekFrom::Start(offset))?;
+          record_file.write_all(shard)?;
+          report.repaired_parity += 1;
+        }
+      }
+    }
+
+    parity_index += parity;
+  }
+
+  if (report.repaired > 0) {
+    file.sync_all()?;
+  }
+  if (report.repaired_parity > 0) {
+    record_file.sync_all()?;
+  }
+
+  Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn fixture(len: usize) -> (std::path::PathBuf, String, Vec<u8>) {
+    let dir = crate::test_utils::temp_dir();
+
+    let archive = dir.join("archive.i6p").to_str().unwrap().to_owned();
+    let content: Vec<u8> = (0..len).map(|i| (i * 31 % 251) as u8).collect();
+    std::fs::write(&archive, &content).unwrap();
+
+    (dir, archive, content)
+  }
+
+  fn flip(path: &str, offsets: &[usize]) {
+    let mut bytes = std::fs::read(path).unwrap();
+    for offset in offsets {
+      bytes[*offset] ^= 0xff;
+    }
+    std::fs::write(path, bytes).unwrap();
+  }
+
+  #[test]
+  fn test_parse_percent() {
+    assert_eq!(parse_percent("5%"), Ok(5));
+    assert_eq!(parse_percent("100"), Ok(100));
+    assert!(parse_percent("0%").is_err());
+    assert!(parse_percent("five").is_err());
+  }
+
+  #[test]
+  fn test_repair() {
+    let (dir, archive, content) = fixture(1_000_003);
+    create_recovery_record(&archive, 5).unwrap();
+
+    assert_eq!(repair_archive(&archive).unwrap(), RepairReport::default());
+
+    let shard_size = Layout::new(content.len() as u64, 5).shard_size as usize;
+    flip(&archive, &[0, 3 * shard_size + 17, content.len() - 1]);
+    flip(
+      &recovery_path(&archive),
+      &[Record::parity_offset(&Layout::new(content.len() as u64, 5)).unwrap()
+        as usize],
+    );
+
+    let report = repair_archive(&archive).unwrap();
+    assert_eq!(report, RepairReport { repaired: 3, repaired_parity: 1 });
+    assert_eq!(std::fs::read(&archive).unwrap(), content);
+    assert_eq!(repair_archive(&archive).unwrap(), RepairReport::default());
+
+    std::fs::remove_dir_all(dir).unwrap();
+  }
+
+  #[test]
+  fn test_repair_too_much_damage() {
+    let (dir, archive, content) = fixture(4096);
+    create_recovery_record(&archive, 1).unwrap();
+
+    let damaged: Vec<usize> = (0..content.len()).step_by(512).collect();
+    flip(&archive, &damaged);
+
+    assert!(repair_archive(&archive).is_err());
+
+    std::fs::remove_dir_all(dir).unwrap();
+  }
+
+  #[test]
+  fn test_corrupt_layout() {
+    let (dir, archive, _) = fixture(4096);
+    let path = create_recovery_record(&archive, 5).unwrap();
+
+    // Claims more shards than fit in memory, let alone in the record.
+    let layout = Layout { archive_len: u64::MAX, shard_size: 1, percent: 100 };
+    let mut record = std::fs::read(&path).unwrap();
+    record[..HEADER_LEN].copy_from_slice(&layout.to_bytes());
+    std::fs::write(&path, &record).unwrap();
+
+    let err = repair_archive(&archive).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    let err = refresh_recovery_record(&archive).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+    // A valid layout for an archive of another length.
+    let layout = Layout::new(2048, 5);
+    record[..HEADER_LEN].copy_from_slice(&layout.to_bytes());
+    std::fs::write(&path, &record).unwrap();
+
+    let err = repair_archive(&archive).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+    std::fs::remove_dir_all(dir).unwrap();
+  }
+}
@@ -0,0 +1,197 @@
+use std::fs::OpenOptions;
+use std::io;
+
+use crate::encryptions::cha_cha20_poly1305::ChaCha20Poly1305;
+use crate::encryptions::encryption::Encryption;
+use crate::encryptions::key_wrapped::{self, KeyWrapped};
+use crate::encryptions::keyslots::KeySlots;
+use crate::recovery;
+use crate::seekable;
+
+#[derive(Debug, Clone)]
+pub enum Rekey {
+  /// Replace the password of the slot that the current password opens.
+  ChangePassword(String),
+  /// Add a new password in a free slot.
+  AddPassword(String),
+  /// Remove a slot, the current password may be in any other slot.
+  RemoveSlot(usize),
+}
+
+/// Returns the position of the key slots in an encrypted archive, or `None`
+/// for archives in the legacy format, where the key is derived directly from
+/// the password.
+pub fn key_slots_offset(archive: &str) -> io::Result<Option<u64>> {
+  if seekable::is_encrypted(archive)? {
+    return Ok(Some(seekable::ENCRYPTED_MAGIC.len() as u64));
+  }
+
+  if key_wrapped::is_key_wrapped(archive)? {
+    return Ok(Some(key_wrapped::MAGIC.len() as u64));
+  }
+
+  Ok(None)
+}
+
+/// Converts a legacy encrypted archive to the key wrapped format, keeping the
+/// compressed payload as is.
+pub fn upgrade_legacy(archive: &str, password: &str) -> io::Result<()> {
+  let compressed_file =
+    &format!("{}-{}.tar.zst", archive, uuid::Uuid::new_v4());
+  let upgraded_file = &format!("{}-{}.i6pe", archive, uuid::Uuid::new_v4());
+
+  let result =
+    ChaCha20Poly1305::decrypt_file(archive, compressed_file, password)
+      .and_then(|_| {
+        KeyWrapped::encrypt_file(compressed_file, upgraded_file, password)
+      })
+      .and_then(|_| std::fs::rename(upgraded_file, archive));
+
+  for file in [compressed_file, upgraded_file] {
+    let _ = std::fs::remove_file(file);
+  }
+
+  result
+}
+
+/// Applies `rekey` to the key slots of an encrypted archive, rewriting only
+/// its header. Returns the slot that was changed, added or removed.
+pub fn rekey_archive(
+  archive: &str,
+  password: &str,
+  rekey: &Rekey,
+) -> io::Result<usize> {
+  let offset = match key_slots_offset(archive)? {
+    Some(offset) => offset,
+    None => {
+      upgrade_legacy(archive, password)?;
+      key_wrapped::MAGIC.len() as u64
+    }
+  };
+
+  let mut file = OpenOptions::new().read(true).write(true).open(archive)?;
+  let mut slots = KeySlots::read_from(&mut file, offset)?;
+  let (unlocked, data_key) = slots.unlock(password)?;
+
+  let slot = match rekey {
+    Rekey::ChangePassword(new_password) => {
+      slots.replace(unlocked, &data_key, new_password)?;
+      unlocked
+    }
+    Rekey::AddPassword(new_password) => slots.add(&data_key, new_password)?,
+    Rekey::RemoveSlot(slot) => {
+      slots.remove(*slot)?;
+      *slot
+    }
+  };
+
+  slots.write_to(&mut file, offset)?;
+  recovery::refresh_recovery_record(archive)?;
+
+  Ok(slot)
+}
+
+/// Returns the slots in use, which does not require a password.
+pub fn active_slots(archive: &str) -> io::Result<Vec<usize>> {
+  let offset = key_slots_offset(archive)?.ok_or_else(|| {
+    io::Error::new(
+      io::ErrorKind::InvalidInput,
+      "Archive uses the legacy encryption format without key slots",
+    )
+  })?;
+
+  let mut file = std::fs::File::open(archive)?;
+  Ok(KeySlots::read_from(&mut file, offset)?.active().collect())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::encryptions::keyslots;
+
+  const PAYLOAD_OFFSET: usize = key_wrapped::MAGIC.len() + keyslots::HEADER_LEN;
+
+  fn encrypted_fixture(legacy: bool) -> (std::path::PathBuf, String) {
+    let dir = crate::test_utils::temp_dir();
+
+    let input = dir.join("input.tar.zst");
+    let archive = dir.join("input.i6pe");
+    std::fs::write(&input, b"compressed payload").unwrap();
+
+    let input = input.to_str().unwrap();
+    let archive = archive.to_str().unwrap().to_owned();
+    if legacy {
+      ChaCha20Poly1305::encrypt_file(input, &archive, "old").unwrap();
+    } else {
+      KeyWrapped::encrypt_file(input, &archive, "old").unwrap();
+    }
+
+    (dir, archive)
+  }
+
+  fn decrypts_with(archive: &str, password: &str) -> bool {
+    let output = format!("{}.out", archive);
+    let ok = KeyWrapped::decrypt_file(archive, &output, password).is_ok();
+    if ok {
+      assert_eq!(std::fs::read(&output).unwrap(), b"compressed payload");
+    }
+    ok
+  }
+
+  #[test]
+  fn test_rekey_slots() {
+    let (dir, archive) = encrypted_fixture(false);
+    let payload = std::fs::read(&archive).unwrap()[PAYLOAD_OFFSET..].to_vec();
+
+    let change = Rekey::ChangePassword("new".to_owned());
+    assert_eq!(rekey_archive(&archive, "old", &change).unwrap(), 0);
+    assert!(!decrypts_with(&archive, "old"));
+    assert!(decrypts_with(&archive, "new"));
+
+    let add = Rekey::AddPassword("other".to_owned());
+    assert_eq!(rekey_archive(&archive, "new", &add).unwrap(), 1);
+    assert_eq!(active_slots(&archive).unwrap(), vec![0, 1]);
+    assert!(decrypts_with(&archive, "other"));
+
+    rekey_archive(&archive, "other", &Rekey::RemoveSlot(0)).unwrap();
+    assert!(!decrypts_with(&archive, "new"));
+    assert!(decrypts_with(&archive, "other"));
+    assert!(rekey_archive(&archive, "other", &Rekey::RemoveSlot(1)).is_err());
+
+    // Only the header is rewritten.
+    assert_eq!(std::fs::read(&archive).unwrap()[PAYLOAD_OFFSET..], payload[..]);
+
+    std::fs::remove_dir_all(dir).unwrap();
+  }
+
+  #[test]
+  fn test_rekey_upgrades_legacy() {
+    let (dir, archive) = encrypted_fixture(true);
+    assert_eq!(key_slots_offset(&archive).unwrap(), None);
+
+    let change = Rekey::ChangePassword("new".to_owned());
+    rekey_archive(&archive, "old", &change).unwrap();
+    assert!(key_wrapped::is_key_wrapped(&archive).unwrap());
+    assert!(decrypts_with(&archive, "new"));
+
+    std::fs::remove_dir_all(dir).unwrap();
+  }
+
+  #[test]
+  fn test_rekey_refreshes_recovery_record() {
+    let (dir, archive) = encrypted_fixture(false);
+    recovery::create_recovery_record(&archive, 10).unwrap();
+
+    let change = Rekey::ChangePassword("new".to_owned());
+    rekey_archive(&archive, "old", &change).unwrap();
+
+    // A stale record would restore the old key slots.
+    assert_eq!(
+      recovery::repair_archive(&archive).unwrap(),
+      recovery::RepairReport::default()
+    );
+    assert!(decrypts_with(&archive, "new"));
+
+    std::fs::remove_dir_all(dir).unwrap();
+  }
+}
@@ -0,0 +1,783 @@
+//! Seekable archive layout.
+//!
+//! The tar stream is compressed as a sequence of independent zstd frames whose
+//! boundaries line up with tar entries where possible, followed by an index
+//! mapping every entry to its position in the tar stream and every frame to
+//! its position in the file.
+//!
+//! Unencrypted archives store the index in a zstd skippable frame, so plain
+//! zstd decoders, including `compression::decompress_file`, still see a normal
+//! `.tar.zst`. Encrypted archives seal every frame and the index as
+//! independently authenticated chunks, see `encryptions::chunked`, under a
+//! data key stored in `encryptions::keyslots`.
+
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use crate::encryptions::chunked::{self, ChunkCipher};
+use crate::encryptions::keyslots::{self, KeySlots};
+use crate::sparse;
+use crate::utils;
+
+pub const FRAME_SIZE: u64 = 4 << 20;
+
+const COMPRESSION_LEVEL: i32 = 18;
+
+pub const ENCRYPTED_MAGIC: &[u8; 8] = b"i6pseek\x01";
+const ENCRYPTED_HEADER_LEN: u64 = (ENCRYPTED_MAGIC.len()
+  + keyslots::HEADER_LEN
+  + chunked::NONCE_PREFIX_LEN) as u64;
+
+const FOOTER_MAGIC: &[u8; 8] = b"i6pindex";
+const FOOTER_LEN: u64 = 16;
+
+const SKIPPABLE_FRAME_MAGIC: u32 = 0x184D2A5E;
+const SKIPPABLE_HEADER_LEN: u64 = 8;
+
+/// Version 2 adds the sparse flag of entries and version 3 their link name,
+/// older versions are still read.
+const INDEX_VERSION: u8 = 3;
+const INDEX_CHUNK: u64 = u64::MAX;
+
+const TAR_BLOCK: u64 = 512;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+  /// Position of the stored frame in the archive file.
+  pub offset: u64,
+  /// Stored size, including the authentication tag for encrypted archives.
+  pub size: u64,
+  /// Position of the decompressed frame in the tar stream.
+  pub tar_offset: u64,
+  pub tar_size: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexEntry {
+  pub path: String,
+  pub entry_type: u8,
+  pub mode: u32,
+  pub mtime: u64,
+  /// Size of the file, for sparse entries including the holes.
+  pub size: u64,
+  /// The entry data is a sparse map followed by the data segments, see
+  /// `sparse`.
+  pub sparse: bool,
+  /// First header block of the entry, including any extension headers.
+  pub start: u64,
+  pub data_offset: u64,
+  /// End of the entry data, padded to the tar block size.
+  pub end: u64,
+  /// Target of symlinks and hardlinks.
+  pub link: Option<String>,
+}
+
+impl IndexEntry {
+  pub fn is_dir(&self) -> bool {
+    tar::EntryType::new(self.entry_type).is_dir()
+  }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Index {
+  pub frames: Vec<Frame>,
+  pub entries: Vec<IndexEntry>,
+}
+
+impl Index {
+  fn to_bytes(&self) -> Vec<u8> {
+    let mut out = vec![INDEX_VERSION];
+
+    put_u64(&mut out, self.frames.len() as u64);
+    for frame in &self.frames {
+      put_u64(&mut out, frame.offset);
+      put_u64(&mut out, frame.size);
+      put_u64(&mut out, frame.tar_offset);
+      put_u64(&mut out, frame.tar_size);
+    }
+
+    put_u64(&mut out, self.entries.len() as u64);
+    for entry in &self.entries {
+      put_u64(&mut out, entry.path.len() as u64);
+      out.extend_from_slice(entry.path.as_bytes());
+      out.push(entry.entry_type);
+      put_u64(&mut out, entry.mode as u64);
+      put_u64(&mut out, entry.mtime);
+      put_u64(&mut out, entry.size);
+      put_u64(&mut out, entry.start);
+      put_u64(&mut out, entry.data_offset);
+      put_u64(&mut out, entry.end);
+      out.push(entry.sparse as u8);
+      let link = entry.link.as_deref().unwrap_or("");
+      put_u64(&mut out, link.len() as u64);
+      out.extend_from_slice(link.as_bytes());
+    }
+
+    out
+  }
+
+  fn from_bytes(mut bytes: &[u8]) -> io::Result<Self> {
+    let input = &mut bytes;
+
+    let version = get_u8(input)?;
+    if !(1..=INDEX_VERSION).contains(&version) {
+      return Err(invalid_data("Unsupported archive index version"));
+    }
+
+    let mut index = Index::default();
+
+    for _ in 0..get_u64(input)? {
+      index.frames.push(Frame {
+        offset: get_u64(input)?,
+        size: get_u64(input)?,
+        tar_offset: get_u64(input)?,
+        tar_size: get_u64(input)?,
+      });
+    }
+
+    for _ in 0..get_u64(input)? {
+      let path = get_string(input)?;
+
+      index.entries.push(IndexEntry {
+        path,
+        entry_type: get_u8(input)?,
+        mode: get_u64(input)? as u32,
+        mtime: get_u64(input)?,
+        size: get_u64(input)?,
+        start: get_u64(input)?,
+        data_offset: get_u64(input)?,
+        end: get_u64(input)?,
+        sparse: version >= 2 && get_u8(input)? != 0,
+        link: if version >= 3 {
+          Some(get_string(input)?).filter(|link| !link.is_empty())
+        } else {
+          None
+        },
+      });
+    }
+
+    Ok(index)
+  }
+
+  /// Checks that the frames are contiguous, fit between `data_start` and
+  /// `data_end` of the file and hold at most `FRAME_SIZE` bytes each, and
+  /// that the entries lie inside the tar stream. The index of unencrypted
+  /// archives is not authenticated, and its sizes are allocated as they are.
+  fn validate(&self, data_start: u64, data_end: u64) -> io::Result<()> {
+    let corrupt = || invalid_data("Corrupt archive index");
+
+    let (mut offset, mut tar_offset) = (data_start, 0u64);
+    for frame in &self.frames {
+      if frame.offset != offset
+        || frame.tar_offset != tar_offset
+        || frame.tar_size > FRAME_SIZE
+      {
+        return Err(corrupt());
+      }
+      offset = offset.checked_add(frame.size).ok_or_else(corrupt)?;
+      tar_offset += frame.tar_size;
+    }
+    if offset > data_end {
+      return Err(corrupt());
+    }
+
+    for entry in &self.entries {
+      if entry.start > entry.data_offset
+        || entry.data_offset > entry.end
+        || entry.end > tar_offset
+      {
+        return Err(corrupt());
+      }
+    }
+
+    Ok(())
+  }
+}
+
+fn put_u64(out: &mut Vec<u8>, value: u64) {
+  out.extend_from_slice(&value.to_le_bytes());
+}
+
+fn get_u8(input: &mut &[u8]) -> io::Result<u8> {
+  let mut buf = [0u8; 1];
+  input.read_exact(&mut buf)?;
+  Ok(buf[0])
+}
+
+fn get_u64(input: &mut &[u8]) -> io::Result<u64> {
+  let mut buf = [0u8; 8];
+  input.read_exact(&mut buf)?;
+  Ok(u64::from_le_bytes(buf))
+}
+
+fn get_string(input: &mut &[u8]) -> io::Result<String> {
+  let len = get_u64(input)?;
+  if len > input.len() as u64 {
+    return Err(invalid_data("Corrupt archive index"));
+  }
+  let (string, rest) = input.split_at(len as usize);
+  *input = rest;
+
+  String::from_utf8(string.to_vec())
+    .map_err(|_| invalid_data("Corrupt archive index"))
+}
+
+fn invalid_data(message: &str) -> io::Error {
+  io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Lists the entries of an uncompressed tar file together with their
+/// positions in the tar stream.
+pub fn index_tar_file(tar_file: &str) -> io::Result<Vec<IndexEntry>> {
+  let mut archive = tar::Archive::new(File::open(tar_file)?);
+  let mut entries = Vec::new();
+  let mut start = 0;
+
+  for entry in archive.entries()? {
+    let mut entry = entry?;
+    let data_offset = entry.raw_file_position();
+    let end = data_offset + entry.size().div_ceil(TAR_BLOCK) * TAR_BLOCK;
+
+    let (path, size, sparse) = match sparse::sparse_info(&mut entry)? {
+      Some(info) => (info.name, info.real_size, true),
+      None => {
+        (entry.path()?.to_string_lossy().into_owned(), entry.size(), false)
+      }
+    };
+
+    let link =
+      entry.link_name()?.map(|link| link.to_string_lossy().into_owned());
+    let header = entry.header();
+    entries.push(IndexEntry {
+      path,
+      entry_type: header.entry_type().as_byte(),
+      mode: header.mode().unwrap_or(0o644),
+      mtime: header.mtime().unwrap_or(0),
+      size,
+      sparse,
+      start,
+      data_offset,
+      end,
+      link,
+    });
+
+    start = end;
+  }
+
+  Ok(entries)
+}
+
+/// Splits the tar stream into frames of at most `FRAME_SIZE` bytes, cutting
+/// at entry boundaries whenever possible so small entries share a frame and
+/// every entry that fits in a frame starts at the beginning of one.
+fn frame_boundaries(entries: &[IndexEntry], tar_len: u64) -> Vec<u64> {
+  let mut cuts = vec![0];
+  let mut previous = 0;
+
+  for boundary in
+    entries.iter().map(|e| e.start).chain(std::iter::once(tar_len))
+  {
+    let last = *cuts.last().unwrap();
+    if boundary - last > FRAME_SIZE && previous > last {
+      cuts.push(previous);
+    }
+
+    while boundary - cuts.last().unwrap() > FRAME_SIZE {
+      cuts.push(cuts.last().unwrap() + FRAME_SIZE);
+    }
+
+    previous = boundary;
+  }
+
+  if *cuts.last().unwrap() < tar_len {
+    cuts.push(tar_len);
+  }
+
+  cuts
+}
+
+fn write_footer<W: Write>(output: &mut W, region_len: u64) -> io::Result<()> {
+  output.write_all(&region_len.to_le_bytes())?;
+  output.write_all(FOOTER_MAGIC)
+}
+
+/// Compresses a tar file into the seekable layout, encrypting it when a
+/// password is given.
+pub fn compress_tar_file_seekable(
+  tar_file: &str,
+  output_file: &str,
+  password: &str,
+) -> io::Result<()> {
+  let entries = index_tar_file(tar_file)?;
+
+  let mut tar = File::open(tar_file)?;
+  let tar_len = tar.metadata()?.len();
+  let boundaries = frame_boundaries(&entries, tar_len);
+  let windows: Vec<(u64, u64)> =
+    boundaries.windows(2).map(|w| (w[0], w[1])).collect();
+
+  let mut output = BufWriter::new(File::create(output_file)?);
+  let mut offset = 0;
+
+  let cipher = if password.is_empty() {
+    None
+  } else {
+    let data_key = KeySlots::generate_data_key();
+    let nonce_prefix = ChunkCipher::generate_nonce_prefix();
+
+    output.write_all(ENCRYPTED_MAGIC)?;
+    output.write_all(&KeySlots::new(&data_key, password)?.to_bytes())?;
+    output.write_all(&nonce_prefix)?;
+    offset = ENCRYPTED_HEADER_LEN;
+
+    Some(ChunkCipher::new(&data_key, nonce_prefix))
+  };
+
+  let mut frames = Vec::with_capacity(windows.len());
+
+  // Frames are independent, so compress one batch per core and write the
+  // results in order.
+  for batch in windows.chunks(num_cpus::get().max(1)) {
+    let inputs = batch
+      .iter()
+      .map(|(start, end)| {
+        let mut buffer = vec![0u8; (end - start) as usize];
+        tar.read_exact(&mut buffer)?;
+        Ok(buffer)
+      })
+      .collect::<io::Result<Vec<_>>>()?;
+
+    let outputs: Vec<io::Result<Vec<u8>>> = std::thread::scope(|s| {
+      let handles: Vec<_> = inputs
+        .iter()
+        .map(|input| {
+          s.spawn(move || zstd::bulk::compress(input, COMPRESSION_LEVEL))
+        })
+        .collect();
+
+      handles
+        .into_iter()
+        .map(|h| h.join().expect("Compression thread panicked"))
+        .collect()
+    });
+
+    for ((start, end), data) in batch.iter().zip(outputs) {
+      let mut data = data?;
+      if let Some(cipher) = &cipher {
+        data = cipher.seal(frames.len() as u64, &data)?;
+      }
+
+      output.write_all(&data)?;
+      frames.push(Frame {
+        offset,
+        size: data.len() as u64,
+        tar_offset: *start,
+        tar_size: end - start,
+      });
+      offset += data.len() as u64;
+    }
+  }
+
+  let index = Index { frames, entries }.to_bytes();
+
+  match &cipher {
+    Some(cipher) => {
+      let sealed = cipher.seal(INDEX_CHUNK, &index)?;
+      output.write_all(&sealed)?;
+      write_footer(&mut output, sealed.len() as u64 + FOOTER_LEN)?;
+    }
+    None => {
+      let payload_len = index.len() as u64 + FOOTER_LEN;
+      let frame_len = u32::try_from(payload_len)
+        .map_err(|_| io::Error::other("Archive index is too large"))?;
+
+      output.write_all(&SKIPPABLE_FRAME_MAGIC.to_le_bytes())?;
+      output.write_all(&frame_len.to_le_bytes())?;
+      output.write_all(&index)?;
+      write_footer(&mut output, SKIPPABLE_HEADER_LEN + payload_len)?;
+    }
+  }
+
+  output.flush()
+}
+
+/// Returns true if the file starts with the encrypted seekable header.
+pub fn is_encrypted<P: AsRef<Path>>(archive: P) -> io::Result<bool> {
+  let mut magic = [0u8; 8];
+  match File::open(archive)?.read_exact(&mut magic) {
+    Ok(()) => Ok(&magic == ENCRYPTED_MAGIC),
+    Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+    Err(e) => Err(e),
+  }
+}
+
+/// Returns true if the file ends with a seekable index.
+pub fn is_seekable<P: AsRef<Path>>(archive: P) -> bool {
+  let read_footer = || -> io::Result<bool> {
+    let mut file = File::open(archive)?;
+    if file.seek(SeekFrom::End(0))? < FOOTER_LEN {
+      return Ok(false);
+    }
+
+    let mut footer = [0u8; FOOTER_LEN as usize];
+    file.seek(SeekFrom::End(-(FOOTER_LEN as i64)))?;
+    file.read_exact(&mut footer)?;
+    Ok(&footer[8..] == FOOTER_MAGIC)
+  };
+
+  read_footer().unwrap_or(false)
+}
+
+pub struct SeekableArchive {
+  file: File,
+  index: Index,
+  cipher: Option<ChunkCipher>,
+  cache: Option<(usize, Vec<u8>)>,
+}
+
+impl SeekableArchive {
+  pub fn open(archive: &str, password: &str) -> io::Result<Self> {
+    let cipher = if is_encrypted(archive)? {
+      if password.is_empty() {
+        return Err(io::Error::new(
+          io::ErrorKind::InvalidInput,
+          "Archive is encrypted, a password is required",
+        ));
+      }
+
+      let mut header = [0u8; ENCRYPTED_HEADER_LEN as usize];
+      File::open(archive)?.read_exact(&mut header)?;
+      let (slots, nonce_prefix) =
+        header[ENCRYPTED_MAGIC.len()..].split_at(keyslots::HEADER_LEN);
+      let (_, data_key) = KeySlots::from_bytes(slots)?.unlock(password)?;
+
+      Some(ChunkCipher::new(&data_key, nonce_prefix.try_into().unwrap()))
+    } else {
+      None
+    };
+
+    let mut file = File::open(archive)?;
+    let len = file.seek(SeekFrom::End(0))?;
+    if len < FOOTER_LEN {
+      return Err(invalid_data("Archive has no seekable index"));
+    }
+
+    let mut footer = [0u8; FOOTER_LEN as usize];
+    file.seek(SeekFrom::End(-(FOOTER_LEN as i64)))?;
+    file.read_exact(&mut footer)?;
+
+    let region_len = u64::from_le_bytes(footer[..8].try_into().unwrap());
+    if &footer[8..] != FOOTER_MAGIC
+      || region_len < FOOTER_LEN
+      || region_len > len
+    {
+      return Err(invalid_data("Archive has no seekable index"));
+    }
+
+    let mut region = vec![0u8; (region_len - FOOTER_LEN) as usize];
+    file.seek(SeekFrom::Start(len - region_len))?;
+    file.read_exact(&mut region)?;
+
+    let index = match &cipher {
+      Some(cipher) => Index::from_bytes(&cipher.open(INDEX_CHUNK, &region)?)?,
+      None => {
+        let skippable = region
+          .get(..4)
+          .map(|magic| magic == SKIPPABLE_FRAME_MAGIC.to_le_bytes())
+          .unwrap_or(false);
+        if !skippable {
+          return Err(invalid_data("Archive has no seekable index"));
+        }
+
+        Index::from_bytes(&region[SKIPPABLE_HEADER_LEN as usize..])?
+      }
+    };
+
+    let data_start = if cipher.is_some() { ENCRYPTED_HEADER_LEN } else { 0 };
+    index.validate(data_start, len - region_len)?;
+
+    Ok(Self { file, index, cipher, cache: None })
+  }
+
+  pub fn index(&self) -> &Index {
+    &self.index
+  }
+
+  pub fn entries(&self) -> &[IndexEntry] {
+    &self.index.entries
+  }
+
+  fn read_frame(&mut self, i: usize) -> io::Result<Vec<u8>> {
+    let frame = &self.index.frames[i];
+
+    let mut data = vec![0u8; frame.size as usize];
+    self.file.seek(SeekFrom::Start(frame.offset))?;
+    self.file.read_exact(&mut data)?;
+
+    if let Some(cipher) = &self.cipher {
+      data = cipher.open(i as u64, &data)?;
+    }
+
+    let data = zstd::bulk::decompress(&data, frame.tar_size as usize)?;
+    if data.len() as u64 != frame.tar_size {
+      return Err(invalid_data("Frame size does not match the index"));
+    }
+
+    Ok(data)
+  }
+
+  /// Reads from the decompressed tar stream at `offset`, only decompressing
+  /// the frame that contains it.
+  pub fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+    let frames = &self.index.frames;
+    let i = frames.partition_point(|f| f.tar_offset + f.tar_size <= offset);
+    if i >= frames.len() {
+      return Ok(0);
+    }
+    let start = offset
+      .checked_sub(frames[i].tar_offset)
+      .ok_or_else(|| invalid_data("Offset is not covered by the index"))?;
+
+    if self.cache.as_ref().map(|(cached, _)| *cached != i).unwrap_or(true) {
+      self.cache = Some((i, self.read_frame(i)?));
+    }
+    let data = &self.cache.as_ref().unwrap().1;
+
+    let start = start as usize;
+    if start > data.len() {
+      return Err(invalid_data("Frame size does not match the index"));
+    }
+    let n = buf.len().min(data.len() - start);
+    buf[..n].copy_from_slice(&data[start..start + n]);
+
+    Ok(n)
+  }
+
+  /// Returns a reader over the decompressed tar stream between `start` and
+  /// `end`.
+  pub fn range(&mut self, start: u64, end: u64) -> RangeReader<'_> {
+    RangeReader { archive: self, pos: start, end }
+  }
+
+  /// Extracts `path`, and everything below it if it is a directory, into
+  /// `output_dir`. Returns the number of extracted entries.
+  pub fn extract<P: AsRef<Path>>(
+    &mut self,
+    path: &str,
+    output_dir: P,
+  ) -> io::Result<usize> {
+    let ranges: Vec<(u64, u64)> = self
+      .index
+      .entries
+      .iter()
+      .filter(|e| utils::path_matches(&e.path, path))
+      .map(|e| (e.start, e.end))
+      .collect();
+
+    if ranges.is_empty() {
+      return Err(io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("{} not found in archive", path),
+      ));
+    }
+
+    std::fs::create_dir_all(&output_dir)?;
+
+    for (start, end) in &ranges {
+      // Terminate the single entry stream with the two zero blocks that mark
+      // the end of a tar archive.
+      let reader =
+        self.range(*start, *end).chain(io::repeat(0).take(2 * TAR_BLOCK));
+      let mut archive = tar::Archive::new(reader);
+
+      for entry in archive.entries()? {
+        sparse::unpack_in(&mut entry?, output_dir.as_ref())?;
+      }
+    }
+
+    Ok(ranges.len())
+  }
+
+  /// Writes the whole decompressed tar stream to `output_file`.
+  pub fn decompress_to(&mut self, output_file: &str) -> io::Result<()> {
+    let mut output = BufWriter::new(File::create(output_file)?);
+
+    for i in 0..self.index.frames.len() {
+      output.write_all(&self.read_frame(i)?)?;
+    }
+
+    output.flush()
+  }
+}
+
+pub struct RangeReader<'a> {
+  archive: &'a mut SeekableArchive,
+  pos: u64,
+  end: u64,
+}
+
+impl Read for RangeReader<'_> {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    if self.pos >= self.end {
+      return Ok(0);
+    }
+
+    let len = buf.len().min((self.end - self.pos) as usize);
+    let n = self.archive.read_at(self.pos, &mut buf[..len])?;
+    self.pos += n as u64;
+
+    Ok(n)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::compression;
+
+  fn fixture() -> std::path::PathBuf {
+    let dir = crate::test_utils::temp_dir();
+    std::fs::create_dir_all(dir.join("folder/nested")).unwrap();
+    std::fs::write(dir.join("folder/small.txt"), b"hello").unwrap();
+    std::fs::write(
+      dir.join("folder/nested/large.bin"),
+      (0..FRAME_SIZE * 2 + 7).map(|i| (i % 251) as u8).collect::<Vec<_>>(),
+    )
+    .unwrap();
+    dir
+  }
+
+  fn roundtrip(password: &str) {
+    let dir = fixture();
+    let tar_file = dir.join("folder.tar");
+    let archive = dir.join("folder.i6p");
+    let tar_file = tar_file.to_str().unwrap();
+    let archive = archive.to_str().unwrap();
+
+    let mut builder = tar::Builder::new(File::create(tar_file).unwrap());
+    builder.append_dir_all("folder", dir.join("folder")).unwrap();
+    builder.finish().unwrap();
+    compress_tar_file_seekable(tar_file, archive, password).unwrap();
+
+    assert!(is_seekable(archive));
+    assert_eq!(is_encrypted(archive).unwrap(), !password.is_empty());
+
+    let mut seekable = SeekableArchive::open(archive, password).unwrap();
+    assert_eq!(seekable.entries(), index_tar_file(tar_file).unwrap());
+    assert!(seekable.index().frames.len() > 2);
+
+    let output = dir.join("out");
+    assert_eq!(seekable.extract("folder/nested", &output).unwrap(), 2);
+    assert_eq!(
+      std::fs::read(output.join("folder/nested/large.bin")).unwrap(),
+      std::fs::read(dir.join("folder/nested/large.bin")).unwrap()
+    );
+    assert!(!output.join("folder/small.txt").exists());
+
+    let decompressed = dir.join("decompressed.tar");
+    let decompressed = decompressed.to_str().unwrap();
+    if password.is_empty() {
+      compression::decompress_file(archive, decompressed).unwrap();
+    } else {
+      seekable.decompress_to(decompressed).unwrap();
+    }
+    assert_eq!(
+      std::fs::read(decompressed).unwrap(),
+      std::fs::read(tar_file).unwrap()
+    );
+
+    std::fs::remove_dir_all(dir).unwrap();
+  }
+
+  #[test]
+  fn test_seekable_roundtrip() {
+    roundtrip("");
+  }
+
+  #[test]
+  fn test_seekable_roundtrip_encrypted() {
+    roundtrip("password");
+  }
+
+  #[test]
+  fn test_tampered_index() {
+    let dir = fixture();
+    let tar_file = dir.join("folder.tar");
+    let archive = dir.join("folder.i6p");
+    let tar_file = tar_file.to_str().unwrap();
+    let archive = archive.to_str().unwrap();
+
+    let mut builder = tar::Builder::new(File::create(tar_file).unwrap());
+    builder.append_dir_all("folder", dir.join("folder")).unwrap();
+    builder.finish().unwrap();
+    compress_tar_file_seekable(tar_file, archive, "").unwrap();
+
+    let original = std::fs::read(archive).unwrap();
+    let u64_at = |position: usize| {
+      u64::from_le_bytes(original[position..position + 8].try_into().unwrap())
+    };
+
+    // Behind the skippable frame header and the version come the frames,
+    // then the entries.
+    let region_len = u64_at(original.len() - FOOTER_LEN as usize);
+    let frame_count = original.len() - region_len as usize + 8 + 1;
+    let frames = frame_count + 8;
+    let entries = frames + u64_at(frame_count) as usize * 32 + 8;
+    let first_end = entries + 8 + u64_at(entries) as usize + 1 + 8 * 5;
+
+    let index = SeekableArchive::open(archive, "").unwrap().index().clone();
+    assert_eq!(u64_at(frames + 24), index.frames[0].tar_size);
+    assert_eq!(u64_at(first_end), index.entries[0].end);
+
+    // The size of the first frame, its decompressed size, the offset of the
+    // second frame and the end of the first entry.
+    for position in [frames + 8, frames + 24, frames + 32, first_end] {
+      let mut tampered = original.clone();
+      tampered[position..position + 8]
+        .copy_from_slice(&(u64::MAX / 2).to_le_bytes());
+      std::fs::write(archive, &tampered).unwrap();
+
+      let err = SeekableArchive::open(archive, "").err().unwrap();
+      assert_eq!(err.kind(), io::ErrorKind::InvalidData, "{}", position);
+    }
+
+    std::fs::write(archive, &original).unwrap();
+    assert!(SeekableArchive::open(archive, "").is_ok());
+
+    std::fs::remove_dir_all(dir).unwrap();
+  }
+
+  #[test]
+  fn test_read_at_frame_size_mismatch() {
+    let dir = fixture();
+    let tar_file = dir.join("folder.tar");
+    let archive = dir.join("folder.i6p");
+    let tar_file = tar_file.to_str().unwrap();
+    let archive = archive.to_str().unwrap();
+
+    let mut builder = tar::Builder::new(File::create(tar_file).unwrap());
+    builder.append_dir_all("folder", dir.join("folder")).unwrap();
+    builder.finish().unwrap();
+    compress_tar_file_seekable(tar_file, archive, "").unwrap();
+
+    // An index that claims more data than the frame decompresses to.
+    let mut seekable = SeekableArchive::open(archive, "").unwrap();
+    let frame = &mut seekable.index.frames[0];
+    frame.tar_size += 10;
+    let offset = frame.tar_offset + frame.tar_size - 1;
+
+    let err = seekable.read_at(offset, &mut [0u8; 16]).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+    // And one with a gap in front of a frame.
+    let mut seekable = SeekableArchive::open(archive, "").unwrap();
+    let offset = seekable.index.frames[1].tar_offset;
+    seekable.index.frames[1].tar_offset += 10;
+
+    let err = seekable.read_at(offset, &mut [0u8; 16]).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+    std::fs::remove_dir_all(dir).unwrap();
+  }
+}
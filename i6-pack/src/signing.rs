@@ -0,0 +1,187 @@
+//! Ed25519 signatures for archives.
+//!
+//! A signature is stored next to the archive in `<archive>.sig` and covers the
+//! format magic, the archive length and a SHA-512 hash of the archive. The key
+//! slots of encrypted archives are left out of the hash, so changing a
+//! password with `rekey` keeps the signature valid.
+
+use ed25519_dalek::pkcs8::{
+  spki::der::pem::LineEnding, DecodePrivateKey, DecodePublicKey,
+  EncodePrivateKey, EncodePublicKey,
+};
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use sha2::{Digest, Sha512};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+use crate::encryptions::keyslots;
+use crate::rekey;
+
+const DOMAIN: &[u8] = b"i6-pack-signature-v1\0";
+
+pub fn signature_path(archive: &str) -> String {
+  format!("{}.sig", archive)
+}
+
+fn invalid_key(e: impl std::fmt::Display) -> io::Error {
+  io::Error::new(io::ErrorKind::InvalidData, format!("Invalid key: {}", e))
+}
+
+fn create_new(path: &str) -> io::Result<File> {
+  let mut options = OpenOptions::new();
+  options.write(true).create_new(true);
+
+  #[cfg(unix)]
+  {
+    use std::os::unix::fs::OpenOptionsExt;
+    options.mode(0o600);
+  }
+
+  options.open(path)
+}
+
+/// Writes a new key pair to `<name>.pem` and `<name>.pub.pem`, and returns
+/// their paths.
+pub fn generate_key_pair(name: &str) -> io::Result<(String, String)> {
+  let signing_key = SigningKey::generate(&mut rand::rngs::OsRng);
+
+  let secret_path = format!("{}.pem", name);
+  let public_path = format!("{}.pub.pem", name);
+
+  let secret_pem =
+    signing_key.to_pkcs8_pem(LineEnding::LF).map_err(invalid_key)?;
+  let public_pem = signing_key
+    .verifying_key()
+    .to_public_key_pem(LineEnding::LF)
+    .map_err(invalid_key)?;
+
+  create_new(&secret_path)?.write_all(secret_pem.as_bytes())?;
+  File::create(&public_path)?.write_all(public_pem.as_bytes())?;
+
+  Ok((secret_path, public_path))
+}
+
+pub fn read_signing_key(path: &str) -> io::Result<SigningKey> {
+  SigningKey::from_pkcs8_pem(&std::fs::read_to_string(path)?)
+    .map_err(invalid_key)
+}
+
+pub fn read_verifying_key(path: &str) -> io::Result<VerifyingKey> {
+  VerifyingKey::from_public_key_pem(&std::fs::read_to_string(path)?)
+    .map_err(invalid_key)
+}
+
+/// Builds the message that is signed for an archive.
+fn signed_message(archive: &str) -> io::Result<Vec<u8>> {
+  let mut file = File::open(archive)?;
+  let len = file.metadata()?.len();
+
+  let mut magic = [0u8; 8];
+  let magic_len = file.read(&mut magic)?;
+  file.seek(SeekFrom::Start(0))?;
+
+  let mut hasher = Sha512::new();
+  match rekey::key_slots_offset(archive)? {
+    Some(offset) => {
+      io::copy(&mut (&mut file).take(offset), &mut hasher)?;
+      file.seek(SeekFrom::Current(keyslots::HEADER_LEN as i64))?;
+      io::copy(&mut file, &mut hasher)?;
+    }
+    None => {
+      io::copy(&mut file, &mut hasher)?;
+    }
+  }
+
+  let mut message = DOMAIN.to_vec();
+  message.extend_from_slice(&magic[..magic_len]);
+  message.extend_from_slice(&len.to_le_bytes());
+  message.extend_from_slice(&hasher.finalize());
+  Ok(message)
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+  bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+  if !hex.len().is_multiple_of(2) {
+    return None;
+  }
+
+  (0..hex.len())
+    .step_by(2)
+    .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+    .collect()
+}
+
+/// Signs `archive` and writes the detached signature, returning its path.
+pub fn sign_archive(archive: &str, key_file: &str) -> io::Result<String> {
+  let signing_key = read_signing_key(key_file)?;
+  let signature = signing_key.sign(&signed_message(archive)?);
+
+  let path = signature_path(archive);
+  std::fs::write(&path, format!("{}\n", to_hex(&signature.to_bytes())))?;
+  Ok(path)
+}
+
+/// Fails unless `archive` has a detached signature made by the key in
+/// `public_key_file`.
+pub fn verify_archive(archive: &str, public_key_file: &str) -> io::Result<()> {
+  let verifying_key = read_verifying_key(public_key_file)?;
+
+  let path = signature_path(archive);
+  let signature = match std::fs::read_to_string(&path) {
+    Ok(signature) => signature,
+    Err(e) if e.kind() == io::ErrorKind::NotFound => {
+      return Err(io::Error::new(
+        io::ErrorKind::PermissionDenied,
+        format!("Archive is not signed, {} is missing", path),
+      ));
+    }
+    Err(e) => return Err(e),
+  };
+
+  let signature = from_hex(signature.trim())
+    .and_then(|bytes| Signature::from_slice(&bytes).ok())
+    .ok_or_else(|| {
+      io::Error::new(io::ErrorKind::InvalidData, "Malformed signature")
+    })?;
+
+  verifying_key.verify(&signed_message(archive)?, &signature).map_err(|_| {
+    io::Error::new(
+      io::ErrorKind::PermissionDenied,
+      "Signature verification failed",
+    )
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_sign_and_verify() {
+    let dir = crate::test_utils::temp_dir();
+
+    let archive = dir.join("archive.i6p");
+    let archive = archive.to_str().unwrap();
+    std::fs::write(archive, b"archive content").unwrap();
+
+    let (secret, public) =
+      generate_key_pair(dir.join("signer").to_str().unwrap()).unwrap();
+    let (_, other_public) =
+      generate_key_pair(dir.join("other").to_str().unwrap()).unwrap();
+    assert!(generate_key_pair(dir.join("signer").to_str().unwrap()).is_err());
+
+    assert!(verify_archive(archive, &public).is_err());
+
+    sign_archive(archive, &secret).unwrap();
+    verify_archive(archive, &public).unwrap();
+    assert!(verify_archive(archive, &other_public).is_err());
+
+    std::fs::write(archive, b"archive c0ntent").unwrap();
+    assert!(verify_archive(archive, &public).is_err());
+
+    std::fs::remove_dir_all(dir).unwrap();
+  }
+}
@@ -0,0 +1,536 @@
+//! Sparse files in tar archives, in the PAX 1.0 sparse format of GNU tar.
+//!
+//! A sparse file is stored as a regular entry whose data starts with a map of
+//! its data segments, padded to the tar block size, followed by the segments
+//! themselves. PAX records carry the real name and size. The entry is named
+//! `<dir>/GNUSparseFile.0/<name>`, so tools without sparse support extract the
+//! raw map and data next to, rather than over, the real file.
+
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Component, Path, PathBuf};
+use tar::{Builder, EntryType, Header};
+
+const TAR_BLOCK: usize = 512;
+
+/// Largest size the octal size field of a ustar header holds.
+const USTAR_MAX_SIZE: u64 = 0o77777777777;
+
+/// Upper bound on the number of segments in a map read from an archive.
+const MAX_SEGMENTS: u64 = 1 << 24;
+
+const PAX_MAJOR: &str = "GNU.sparse.major";
+const PAX_MINOR: &str = "GNU.sparse.minor";
+const PAX_NAME: &str = "GNU.sparse.name";
+const PAX_REALSIZE: &str = "GNU.sparse.realsize";
+const PAX_SIZE: &str = "size";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Segment {
+  pub offset: u64,
+  pub len: u64,
+}
+
+/// Returns the data segments of `file` if it has holes, using
+/// `SEEK_DATA`/`SEEK_HOLE`. Returns `None` for dense files and on platforms
+/// or filesystems that do not report holes. Leaves the file position at the
+/// start.
+#[cfg(target_os = "linux")]
+pub fn data_segments(
+  file: &File,
+  len: u64,
+) -> io::Result<Option<Vec<Segment>>> {
+  use std::os::unix::io::AsRawFd;
+
+  if len == 0 {
+    return Ok(None);
+  }
+
+  let fd = file.as_raw_fd();
+  let mut segments = Vec::new();
+  let mut pos = 0;
+
+  while pos < len {
+    let data = unsafe { libc::lseek(fd, pos as libc::off_t, libc::SEEK_DATA) };
+    if data < 0 {
+      match io::Error::last_os_error().raw_os_error() {
+        // No data after `pos`, the rest of the file is a hole.
+        Some(libc::ENXIO) => break,
+        _ => return Ok(None),
+      }
+    }
+
+    let hole = unsafe { libc::lseek(fd, data, libc::SEEK_HOLE) };
+    if hole < 0 {
+      return Ok(None);
+    }
+
+    let (data, hole) = (data as u64, (hole as u64).min(len));
+    if data >= hole {
+      break;
+    }
+    segments.push(Segment { offset: data, len: hole - data });
+    pos = hole;
+  }
+  (&*file).seek(SeekFrom::Start(0))?;
+
+  if segments.len() == 1 && segments[0] == (Segment { offset: 0, len }) {
+    return Ok(None);
+  }
+
+  Ok(Some(segments))
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn data_segments(
+  _file: &File,
+  _len: u64,
+) -> io::Result<Option<Vec<Segment>>> {
+  Ok(None)
+}
+
+fn pax_record(key: &str, value: &str) -> Vec<u8> {
+  // The length prefix counts itself, so grow it until it is stable.
+  let rest = format!(" {}={}\n", key, value);
+  let mut len = rest.len();
+  while (len.to_string().len() + rest.len() != len) {
+    len = len.to_string().len() + rest.len();
+  }
+
+  format!("{}{}", len, rest).into_bytes()
+}
+
+fn map_bytes(segments: &[Segment], real_size: u64) -> Vec<u8> {
+  let mut segments = segments.to_vec();
+  // Like GNU tar, end with an empty segment at the real size when the file
+  // ends in a hole.
+  if segments.last().map(|s| s.offset + s.len).unwrap_or(0) < real_size
+    || segments.is_empty()
+  {
+    segments.push(Segment { offset: real_size, len: 0 });
+  }
+
+  let mut map = format!("{}\n", segments.len());
+  for segment in &segments {
+    map.push_str(&format!("{}\n{}\n", segment.offset, segment.len));
+  }
+
+  let mut map = map.into_bytes();
+  map.resize(map.len().next_multiple_of(TAR_BLOCK), 0);
+  map
+}
+
+fn sparse_entry_path(path: &Path) -> PathBuf {
+  let name = path.file_name().unwrap_or(path.as_os_str());
+  match path.parent() {
+    Some(parent) => parent.join("GNUSparseFile.0").join(name),
+    None => Path::new("GNUSparseFile.0").join(name),
+  }
+}
+
+/// Reads the data segments of a file one after another.
+struct SegmentReader {
+  file: File,
+  segments: std::vec::IntoIter<Segment>,
+  remaining: u64,
+}
+
+impl Read for SegmentReader {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    while self.remaining == 0 {
+      match self.segments.next() {
+        Some(segment) => {
+          self.file.seek(SeekFrom::Start(segment.offset))?;
+          self.remaining = segment.len;
+        }
+        None => return Ok(0),
+      }
+    }
+
+    let max = (buf.len() as u64).min(self.remaining) as usize;
+    let n = self.file.read(&mut buf[..max])?;
+    if n == 0 {
+      return Err(io::Error::new(
+        io::ErrorKind::UnexpectedEof,
+        "Sparse file shrank while it was packed",
+      ));
+    }
+
+    self.remaining -= n as u64;
+    Ok(n)
+  }
+}
+
+/// Returns the PAX records of a sparse entry. Stored sizes beyond the ustar
+/// size field are recorded too, rather than relying on the base-256 encoding
+/// of GNU tar.
+fn sparse_records(name: String, real_size: u64, stored_size: u64) -> Vec<u8> {
+  let mut records = Vec::new();
+  for (key, value) in [
+    (PAX_MAJOR, "1".to_owned()),
+    (PAX_MINOR, "0".to_owned()),
+    (PAX_NAME, name),
+    (PAX_REALSIZE, real_size.to_string()),
+  ] {
+    records.extend(pax_record(key, &value));
+  }
+  if stored_size > USTAR_MAX_SIZE {
+    records.extend(pax_record(PAX_SIZE, &stored_size.to_string()));
+  }
+
+  records
+}
+
+fn append_pax<W: Write>(
+  archive: &mut Builder<W>,
+  mtime: u64,
+  records: &[u8],
+) -> io::Result<()> {
+  let mut pax = Header::new_ustar();
+  pax.set_path("PaxHeaders.0/sparse")?;
+  pax.set_entry_type(EntryType::XHeader);
+  pax.set_mode(0o644);
+  pax.set_mtime(mtime);
+  pax.set_size(records.len() as u64);
+  pax.set_cksum();
+  archive.append(&pax, records)
+}
+
+/// Appends `file` as a PAX sparse entry holding only `segments`. The ustar
+/// header carries the metadata of the file, its size is set here.
+pub fn append_sparse<W: Write>(
+  archive: &mut Builder<W>,
+  header: &mut Header,
+  path: &Path,
+  file: File,
+  real_size: u64,
+  segments: Vec<Segment>,
+) -> io::Result<()> {
+  let map = map_bytes(&segments, real_size);
+  let data_len: u64 = segments.iter().map(|s| s.len).sum();
+  let stored_size = map.len() as u64 + data_len;
+
+  let name = path.to_string_lossy().replace('\\', "/");
+  let records = sparse_records(name, real_size, stored_size);
+  append_pax(archive, header.mtime().unwrap_or(0), &records)?;
+
+  header.set_entry_type(EntryType::Regular);
+  header.set_size(stored_size);
+
+  let data =
+    SegmentReader { file, segments: segments.into_iter(), remaining: 0 };
+  archive.append_data(
+    header,
+    sparse_entry_path(path),
+    map.as_slice().chain(data),
+  )
+}
+
+/// Name and size of a file stored as a sparse entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SparseInfo {
+  pub name: String,
+  pub real_size: u64,
+}
+
+/// Returns the real name and size if `entry` is a PAX 1.0 sparse entry.
+pub fn sparse_info<R: Read>(
+  entry: &mut tar::Entry<R>,
+) -> io::Result<Option<SparseInfo>> {
+  let Some(extensions) = entry.pax_extensions()? else {
+    return Ok(None);
+  };
+
+  let (mut major, mut name, mut real_size) = (None, None, None);
+  for extension in extensions {
+    let extension = extension?;
+    let value = extension.value().ok().map(str::to_owned);
+    match extension.key() {
+      Ok(PAX_MAJOR) => major = value,
+      Ok(PAX_NAME) => name = value,
+      Ok(PAX_REALSIZE) => real_size = value.and_then(|v| v.parse().ok()),
+      _ => {}
+    }
+  }
+
+  match (major.as_deref(), name, real_size) {
+    (Some("1"), Some(name), Some(real_size)) => {
+      Ok(Some(SparseInfo { name, real_size }))
+    }
+    _ => Ok(None),
+  }
+}
+
+/// Returns the path of `entry`, using the real name of sparse entries.
+pub fn entry_path<R: Read>(entry: &mut tar::Entry<R>) -> io::Result<String> {
+  match sparse_info(entry)? {
+    Some(info) => Ok(info.name),
+    None => Ok(entry.path()?.to_string_lossy().into_owned()),
+  }
+}
+
+fn invalid_map() -> io::Error {
+  io::Error::new(io::ErrorKind::InvalidData, "Corrupt sparse map")
+}
+
+/// Reads the sparse map at the start of the entry data. Returns the segments
+/// and the length of the map, after which the segment data follows.
+pub fn read_map<R: Read>(reader: &mut R) -> io::Result<(Vec<Segment>, u64)> {
+  let mut buf = Vec::new();
+  let mut pos = 0;
+
+  let mut next_number = |buf: &mut Vec<u8>| -> io::Result<u64> {
+    loop {
+      if let Some(newline) = buf[pos..].iter().position(|b| *b == b'\n') {
+        let number = std::str::from_utf8(&buf[pos..pos + newline])
+          .ok()
+          .and_then(|s| s.parse().ok())
+          .ok_or_else(invalid_map)?;
+        pos += newline + 1;
+        return Ok(number);
+      }
+
+      let len = buf.len();
+      buf.resize(len + TAR_BLOCK, 0);
+      reader.read_exact(&mut buf[len..])?;
+    }
+  };
+
+  let count = next_number(&mut buf)?;
+  if count > MAX_SEGMENTS {
+    return Err(invalid_map());
+  }
+
+  // Grown as the map is read, as `count` comes from the archive.
+  let mut segments = Vec::new();
+  for _ in 0..count {
+    let offset = next_number(&mut buf)?;
+    let len = next_number(&mut buf)?;
+    segments.push(Segment { offset, len });
+  }
+
+  Ok((segments, buf.len() as u64))
+}
+
+/// Joins an archive path onto `dst` like `tar::Entry::unpack_in`, skipping
+/// root and `.` components and refusing `..`.
+fn output_path(dst: &Path, name: &str) -> io::Result<PathBuf> {
+  let mut path = dst.to_path_buf();
+  for component in Path::new(name).components() {
+    match component {
+      Component::Normal(part) => path.push(part),
+      Component::CurDir | Component::RootDir | Component::Prefix(_) => {}
+      Component::ParentDir => {
+        return Err(io::Error::new(
+          io::ErrorKind::InvalidData,
+          format!("Invalid path in archive: {}", name),
+        ));
+      }
+    }
+  }
+
+  Ok(path)
+}
+
+fn unpack_sparse<R: Read>(
+  entry: &mut tar::Entry<R>,
+  info: &SparseInfo,
+  dst: &Path,
+) -> io::Result<()> {
+  let path = output_path(dst, &info.name)?;
+  let parent = path.parent().unwrap_or(dst);
+  let leaves = || {
+    io::Error::new(
+      io::ErrorKind::InvalidData,
+      format!("Path in archive leaves the output directory: {}", info.name),
+    )
+  };
+
+  // Like `unpack_in`, do not follow symlinks out of the output directory.
+  // The deepest existing directory is checked before creating anything, so
+  // a symlink from an earlier entry cannot make us create directories
+  // outside of it.
+  let dst = dst.canonicalize().unwrap_or(dst.to_path_buf());
+  let mut existing = parent;
+  while !existing.try_exists()? {
+    existing = existing.parent().ok_or_else(leaves)?;
+  }
+  if !existing.canonicalize()?.starts_with(&dst) {
+    return Err(leaves());
+  }
+
+  fs::create_dir_all(parent)?;
+  if !parent.canonicalize()?.starts_with(&dst) {
+    return Err(leaves());
+  }
+
+  let (segments, _) = read_map(entry)?;
+
+  let _ = fs::remove_file(&path);
+  let mut file = File::create(&path)?;
+  for segment in segments {
+    if segment.offset.saturating_add(segment.len) > info.real_size {
+      return Err(invalid_map());
+    }
+
+    file.seek(SeekFrom::Start(segment.offset))?;
+    if io::copy(&mut entry.take(segment.len), &mut file)? != segment.len {
+      return Err(io::Error::new(
+        io::ErrorKind::UnexpectedEof,
+        "Truncated sparse entry",
+      ));
+    }
+  }
+  file.set_len(info.real_size)?;
+
+  let header = entry.header();
+  if let Ok(mtime) = header.mtime() {
+    file.set_modified(
+      std::time::UNIX_EPOCH + std::time::Duration::from_secs(mtime),
+    )?;
+  }
+
+  #[cfg(unix)]
+  if let Ok(mode) = header.mode() {
+    use std::os::unix::fs::PermissionsExt;
+    file.set_permissions(fs::Permissions::from_mode(mode & 0o777))?;
+  }
+
+  Ok(())
+}
+
+/// Unpacks `entry` into `dst`, recreating the holes of sparse entries.
+pub fn unpack_in<R: Read>(
+  entry: &mut tar::Entry<R>,
+  dst: &Path,
+) -> io::Result<()> {
+  match sparse_info(entry)? {
+    Some(info) => unpack_sparse(entry, &info, dst),
+    None => entry.unpack_in(dst).map(|_| ()),
+  }
+}
+
+/// Like `tar::Archive::unpack`, with support for sparse entries.
+pub fn unpack<R: Read>(
+  archive: &mut tar::Archive<R>,
+  dst: &Path,
+) -> io::Result<()> {
+  fs::create_dir_all(dst)?;
+  let dst = &dst.canonicalize().unwrap_or(dst.to_path_buf());
+
+  // Directories are applied last, deepest first, so their permissions do not
+  // get in the way of extracting their contents.
+  let mut directories = Vec::new();
+  for entry in archive.entries()? {
+    let mut entry = entry?;
+    if (entry.header().entry_type() == EntryType::Directory) {
+      directories.push(entry);
+    } else {
+      unpack_in(&mut entry, dst)?;
+    }
+  }
+
+  directories.sort_by(|a, b| b.path_bytes().cmp(&a.path_bytes()));
+  for mut dir in directories {
+    dir.unpack_in(dst)?;
+  }
+
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_pax_record() {
+    assert_eq!(pax_record("GNU.sparse.major", "1"), b"22 GNU.sparse.major=1\n");
+    let record = pax_record("k", &"v".repeat(95));
+    assert_eq!(record.len(), 102);
+    assert!(record.starts_with(b"102 "));
+  }
+
+  #[test]
+  fn test_map_roundtrip() {
+    let segments = vec![
+      Segment { offset: 0, len: 4096 },
+      Segment { offset: 1 << 20, len: 8192 },
+    ];
+    let map = map_bytes(&segments, 10 << 30);
+    assert_eq!(map.len() % TAR_BLOCK, 0);
+
+    let (read, len) = read_map(&mut map.as_slice()).unwrap();
+    assert_eq!(len, map.len() as u64);
+    assert_eq!(read[..2], segments[..]);
+    assert_eq!(read[2], Segment { offset: 10 << 30, len: 0 });
+  }
+
+  #[test]
+  fn test_large_sparse_size() {
+    let size = |real_size, stored_size| {
+      let mut archive = Builder::new(Vec::new());
+      let records = sparse_records("big".into(), real_size, stored_size);
+      append_pax(&mut archive, 0, &records).unwrap();
+      let mut header = Header::new_ustar();
+      header.set_path("GNUSparseFile.0/big").unwrap();
+      header.set_size(stored_size);
+      header.set_cksum();
+      archive.get_mut().extend_from_slice(header.as_bytes());
+      let bytes = archive.into_inner().unwrap();
+
+      let mut archive = tar::Archive::new(bytes.as_slice());
+      let mut entry = archive.entries().unwrap().next().unwrap().unwrap();
+      assert_eq!(entry.size(), stored_size);
+      assert_eq!(
+        sparse_info(&mut entry).unwrap().unwrap().real_size,
+        real_size
+      );
+
+      let extensions = entry.pax_extensions().unwrap().unwrap();
+      extensions
+        .map(|e| e.unwrap())
+        .find(|e| e.key() == Ok(PAX_SIZE))
+        .map(|e| e.value().unwrap().parse::<u64>().unwrap())
+    };
+
+    assert_eq!(size(1 << 30, 4096), None);
+    assert_eq!(size(1 << 40, USTAR_MAX_SIZE), None);
+    assert_eq!(size(1 << 40, 9 << 30), Some(9 << 30));
+  }
+
+  #[cfg(unix)]
+  #[test]
+  fn test_unpack_symlink_escape() {
+    let dir = crate::test_utils::temp_dir();
+    let outside = dir.join("outside");
+    let dst = dir.join("dst");
+    fs::create_dir_all(&outside).unwrap();
+    fs::write(dir.join("data"), b"data").unwrap();
+
+    let mut archive = Builder::new(Vec::new());
+    let mut link = Header::new_ustar();
+    link.set_entry_type(EntryType::Symlink);
+    link.set_size(0);
+    link.set_mode(0o777);
+    archive.append_link(&mut link, "link", &outside).unwrap();
+
+    let mut header = Header::new_ustar();
+    header.set_mode(0o644);
+    append_sparse(
+      &mut archive,
+      &mut header,
+      Path::new("link/sub/file"),
+      File::open(dir.join("data")).unwrap(),
+      4,
+      vec![Segment { offset: 0, len: 4 }],
+    )
+    .unwrap();
+    let bytes = archive.into_inner().unwrap();
+
+    let result = unpack(&mut tar::Archive::new(bytes.as_slice()), &dst);
+    assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidData);
+    assert_eq!(fs::read_dir(&outside).unwrap().count(), 0);
+
+    fs::remove_dir_all(dir).unwrap();
+  }
+}
@@ -0,0 +1,20 @@
+//! Fixtures shared by the unit tests.
+
+use std::path::PathBuf;
+
+/// Creates an empty directory for one test in the system temp directory.
+pub fn temp_dir() -> PathBuf {
+  let dir =
+    std::env::temp_dir().join(format!("i6-pack-{}", uuid::Uuid::new_v4()));
+  std::fs::create_dir_all(&dir).unwrap();
+  dir
+}
+
+/// Creates an empty directory for one test below the package, for trees that
+/// get packed. Archives only hold relative paths, so the returned path is
+/// relative to the package root that `cargo test` runs in.
+pub fn relative_dir() -> String {
+  let dir = format!("target/i6-pack-test-{}", uuid::Uuid::new_v4());
+  std::fs::create_dir_all(&dir).unwrap();
+  dir
+}
@@ -26,3 +26,32 @@ pub fn sanitize_output_path(output_path: &str) -> io::Result<PathBuf> {
     Err(io::Error::new(io::ErrorKind::InvalidInput, "Invalid output path"))
   }
 }
+
+pub fn unique_output_dir(output_dir: &str) -> String {
+  if Path::new(output_dir).exists() {
+    format!("{}-{}", output_dir, uuid::Uuid::new_v4())
+  } else {
+    output_dir.to_owned()
+  }
+}
+
+/// Returns true if `entry` is `path` or lies below it.
+pub fn path_matches(entry: &str, path: &str) -> bool {
+  let entry = entry.trim_end_matches('/');
+  let path = path.trim_end_matches('/');
+
+  entry == path
+    || entry.strip_prefix(path).is_some_and(|rest| rest.starts_with('/'))
+}
+
+/// Reads `SOURCE_DATE_EPOCH`, see https://reproducible-builds.org/specs/source-date-epoch/
+pub fn source_date_epoch() -> Option<u64> {
+  let value = std::env::var("SOURCE_DATE_EPOCH").ok()?;
+  match value.trim().parse() {
+    Ok(epoch) => Some(epoch),
+    Err(_) => {
+      eprintln!("Warning: ignoring invalid SOURCE_DATE_EPOCH {:?}", value);
+      None
+    }
+  }
+}
@@ -330,11 +330,10 @@ impl Command for cp {
             let dest_child_path = match entry_path.file_name() {
               Some(name) => dest_path.join(name),
               None => {
-                return Err(Box::new(std::io::Error::new(
-                  std::io::ErrorKind::Other,
-                  "Invalid file name",
-                ))
-                  as Box<dyn std::error::Error + Send>)
+                return Err(
+                  Box::new(std::io::Error::other("Invalid file name"))
+                    as Box<dyn std::error::Error + Send>,
+                )
               }
             };
             match std::fs::copy(&entry_path, &dest_child_path) {
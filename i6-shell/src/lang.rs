@@ -124,7 +124,7 @@ impl Parser for DefaultParser {
             args.push(ASTNode::Command { name: value, args: command_args });
             if iter
               .peek()
-              .map_or(true, |t| matches!(t.token_type, TokenType::Operator))
+              .is_none_or(|t| matches!(t.token_type, TokenType::Operator))
             {
               break;
             }
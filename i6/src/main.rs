@@ -9,26 +9,56 @@ fn main() -> Result<(), Box<dyn Error>> {
   let unpack_id = "unpack";
   let timer_id = "timer";
   let sh_id = "sh";
+  let cert_id = "cert";
 
   let matches = Command::new("i6")
     .version(env!("CARGO_PKG_VERSION"))
     .author(env!("CARGO_PKG_AUTHORS"))
     .about(env!("CARGO_PKG_DESCRIPTION"))
     .subcommand(
-      Command::new(http_id).about("Start a static http server").arg(
-        Arg::new("port")
-          .index(1)
-          .default_value("3030")
-          .value_parser(value_parser!(u16)),
-      ),
+      Command::new(http_id)
+        .about("Start a static http server")
+        .args(i6_http::cli::args()),
     )
     .subcommand(
-      Command::new(https_id).about("Start a static https server").arg(
-        Arg::new("port")
-          .index(1)
-          .default_value("3030")
-          .value_parser(value_parser!(u16)),
-      ),
+      Command::new(https_id)
+        .about("Start a static https server")
+        .args(i6_http::cli::args())
+        .args(i6_http::cli::tls_args()),
+    )
+    .subcommand(
+      Command::new(cert_id)
+        .about("Manage a local certificate authority for https servers")
+        .subcommand_required(true)
+        .subcommand(
+          Command::new("ca").subcommand_required(true).subcommand(
+            Command::new("init")
+              .about("Create the root certificate authority")
+              .arg(
+                Arg::new("force")
+                  .help("Replace an existing CA")
+                  .long("force")
+                  .action(clap::ArgAction::SetTrue),
+              ),
+          ),
+        )
+        .subcommand(
+          Command::new("issue")
+            .about("Sign a server certificate with the local CA")
+            .arg(
+              Arg::new("san")
+                .help("Hostname or IP address to include, repeatable")
+                .long("san")
+                .action(clap::ArgAction::Append),
+            )
+            .arg(
+              Arg::new("out")
+                .help("Directory to write cert.pem and key.pem to")
+                .short('o')
+                .long("out")
+                .default_value("."),
+            ),
+        ),
     )
     .subcommand(Command::new(sh_id).about("Start an interactive shell"))
     .subcommand(
@@ -89,6 +119,8 @@ fn main() -> Result<(), Box<dyn Error>> {
     .subcommand(
       Command::new(pack_id)
         .about("Compress and encrypt")
+        .args_conflicts_with_subcommands(true)
+        .subcommand_negates_reqs(true)
         .arg(
           Arg::new("target")
             .help("Folder to compress and encrypt, or to extract to")
@@ -102,6 +134,165 @@ fn main() -> Result<(), Box<dyn Error>> {
             .long("encrypt")
             .action(clap::ArgAction::SetTrue)
             .default_value("false"),
+        )
+        .arg(
+          Arg::new("seekable")
+            .help(
+              "Compress into independent frames with an index for fast access",
+            )
+            .short('s')
+            .long("seekable")
+            .action(clap::ArgAction::SetTrue)
+            .default_value("false"),
+        )
+        .arg(
+          Arg::new("sign")
+            .help("Sign the archive with this Ed25519 secret key")
+            .long("sign"),
+        )
+        .arg(
+          Arg::new("recovery")
+            .help(
+              "Write a Reed-Solomon recovery record with this much parity, \
+               e.g. 5%",
+            )
+            .long("recovery")
+            .value_parser(i6_pack::recovery::parse_percent),
+        )
+        .arg(
+          Arg::new("min-strength")
+            .help("Refuse passwords with a lower strength score (0-4)")
+            .long("min-strength")
+            .value_parser(value_parser!(u8).range(0..=4)),
+        )
+        .arg(
+          Arg::new("generate-passphrase")
+            .help("Encrypt with a generated passphrase, printed once")
+            .long("generate-passphrase")
+            .action(clap::ArgAction::SetTrue)
+            .default_value("false"),
+        )
+        .arg(
+          Arg::new("reproducible")
+            .help(
+              "Normalise ordering, ownership and timestamps for identical \
+               output, honouring SOURCE_DATE_EPOCH",
+            )
+            .long("reproducible")
+            .action(clap::ArgAction::SetTrue)
+            .default_value("false"),
+        )
+        .subcommand(
+          Command::new("list")
+            .about("List the contents of an archive")
+            .alias("ls")
+            .arg(
+              Arg::new("target")
+                .help("Archive to list")
+                .required(true)
+                .index(1),
+            )
+            .arg(
+              Arg::new("encrypt")
+                .help("Flag to indicate encryption/decryption")
+                .short('e')
+                .long("encrypt")
+                .action(clap::ArgAction::SetTrue)
+                .default_value("false"),
+            ),
+        )
+        .subcommand(
+          Command::new("verify")
+            .about("Verify the signature of an archive")
+            .arg(
+              Arg::new("target")
+                .help("Archive to verify")
+                .required(true)
+                .index(1),
+            )
+            .arg(
+              Arg::new("verify-with")
+                .help("Public key that the archive must be signed with")
+                .long("verify-with")
+                .required(true),
+            ),
+        )
+        .subcommand(
+          Command::new("repair")
+            .about(
+              "Rebuild damaged parts of an archive from its recovery record",
+            )
+            .arg(
+              Arg::new("target")
+                .help("Archive to repair")
+                .required(true)
+                .index(1),
+            ),
+        )
+        .subcommand(
+          Command::new("keygen")
+            .about("Generate an Ed25519 key pair for signing archives")
+            .arg(
+              Arg::new("name")
+                .help("Writes <name>.pem and <name>.pub.pem")
+                .required(true)
+                .index(1),
+            ),
+        )
+        .subcommand(
+          Command::new("mount")
+            .about("Mount a seekable archive as a read-only filesystem")
+            .arg(
+              Arg::new("target")
+                .help("Archive to mount")
+                .required(true)
+                .index(1),
+            )
+            .arg(
+              Arg::new("mountpoint")
+                .help("Directory to mount the archive at")
+                .required(true)
+                .index(2),
+            )
+            .arg(
+              Arg::new("encrypt")
+                .help("Flag to indicate encryption/decryption")
+                .short('e')
+                .long("encrypt")
+                .action(clap::ArgAction::SetTrue)
+                .default_value("false"),
+            ),
+        )
+        .subcommand(
+          Command::new("rekey")
+            .about("Change, add or remove passwords of an encrypted archive")
+            .arg(
+              Arg::new("target")
+                .help("Encrypted archive to rekey")
+                .required(true)
+                .index(1),
+            )
+            .arg(
+              Arg::new("add")
+                .help("Add a password in a free key slot")
+                .short('a')
+                .long("add")
+                .action(clap::ArgAction::SetTrue),
+            )
+            .arg(
+              Arg::new("remove")
+                .help("Remove the password in the given key slot")
+                .short('r')
+                .long("remove")
+                .value_parser(value_parser!(usize)),
+            )
+            .arg(
+              Arg::new("list")
+                .help("List the key slots in use")
+                .short('l')
+                .long("list")
+                .action(clap::ArgAction::SetTrue),
+            ),
         ),
     )
     .subcommand(
@@ -120,20 +311,55 @@ fn main() -> Result<(), Box<dyn Error>> {
             .long("encrypt")
             .action(clap::ArgAction::SetTrue)
             .default_value("false"),
+        )
+        .arg(
+          Arg::new("path")
+            .help("Only unpack this path from the archive")
+            .short('p')
+            .long("path"),
+        )
+        .arg(
+          Arg::new("verify-with")
+            .help("Refuse archives that are not signed by this public key")
+            .long("verify-with"),
         ),
     )
     .get_matches();
 
   if let Some(matches) = matches.subcommand_matches(http_id) {
-    println!("http, {:?}", matches);
-    let port = *matches.get_one::<u16>("port").unwrap_or(&3030);
-    i6_http::create_server_http(port)?;
+    let config = i6_http::cli::config_from_matches(matches)?;
+    i6_http::create_server_http_with_config(config)?;
   }
 
   if let Some(matches) = matches.subcommand_matches(https_id) {
-    println!("https, {:?}", matches);
-    let port = *matches.get_one::<u16>("port").unwrap_or(&3030);
-    i6_http::create_server_https(port)?;
+    let config = i6_http::cli::config_from_matches(matches)?;
+    i6_http::create_server_https_with_config(config)?;
+  }
+
+  if let Some(matches) = matches.subcommand_matches(cert_id) {
+    if let Some(matches) = matches.subcommand_matches("ca") {
+      if let Some(matches) = matches.subcommand_matches("init") {
+        let path = i6_http::cert::init_ca(
+          &i6_http::cert::ca_dir(),
+          matches.get_flag("force"),
+        )?;
+        println!("Created CA {}", path.display());
+        println!("Install it as a trusted root on clients of your servers");
+      }
+    } else if let Some(matches) = matches.subcommand_matches("issue") {
+      let sans: Vec<String> = matches
+        .get_many::<String>("san")
+        .unwrap_or_default()
+        .cloned()
+        .collect();
+      let out = matches.get_one::<String>("out").unwrap();
+      let (cert, key) = i6_http::cert::issue(
+        &i6_http::cert::ca_dir(),
+        &i6_http::cert::sans_with_defaults(&sans),
+        std::path::Path::new(out),
+      )?;
+      println!("Issued {} with key {}", cert.display(), key.display());
+    }
   }
 
   if let Some(_matches) = matches.subcommand_matches(sh_id) {
@@ -176,17 +402,73 @@ fn main() -> Result<(), Box<dyn Error>> {
   }
 
   if let Some(matches) = matches.subcommand_matches(pack_id) {
-    let target = matches.get_one::<String>("target").unwrap();
-    let encrypt = matches.get_flag("encrypt");
+    if let Some(matches) = matches.subcommand_matches("list") {
+      let target = matches.get_one::<String>("target").unwrap();
+      let encrypt = matches.get_flag("encrypt");
+
+      i6_pack::cli::run("list", target, encrypt)?;
+    } else if let Some(matches) = matches.subcommand_matches("verify") {
+      let target = matches.get_one::<String>("target").unwrap();
+      let options = i6_pack::cli::Options {
+        verify_with: matches.get_one::<String>("verify-with").cloned(),
+        ..Default::default()
+      };
+
+      i6_pack::cli::run_with_options("verify", target, false, &options)?;
+    } else if let Some(matches) = matches.subcommand_matches("repair") {
+      let target = matches.get_one::<String>("target").unwrap();
+
+      i6_pack::cli::run_repair(target)?;
+    } else if let Some(matches) = matches.subcommand_matches("keygen") {
+      let name = matches.get_one::<String>("name").unwrap();
 
-    i6_pack::cli::run("pack", target, encrypt)?;
+      i6_pack::cli::run_keygen(name)?;
+    } else if let Some(matches) = matches.subcommand_matches("mount") {
+      let target = matches.get_one::<String>("target").unwrap();
+      let mountpoint = matches.get_one::<String>("mountpoint").unwrap();
+      let encrypt = matches.get_flag("encrypt");
+
+      i6_pack::cli::run_mount(target, mountpoint, encrypt)?;
+    } else if let Some(matches) = matches.subcommand_matches("rekey") {
+      let target = matches.get_one::<String>("target").unwrap();
+      let action = if matches.get_flag("list") {
+        i6_pack::cli::RekeyAction::List
+      } else if matches.get_flag("add") {
+        i6_pack::cli::RekeyAction::Add
+      } else if let Some(slot) = matches.get_one::<usize>("remove") {
+        i6_pack::cli::RekeyAction::Remove(*slot)
+      } else {
+        i6_pack::cli::RekeyAction::Change
+      };
+
+      i6_pack::cli::run_rekey(target, action)?;
+    } else {
+      let target = matches.get_one::<String>("target").unwrap();
+      let encrypt = matches.get_flag("encrypt");
+      let options = i6_pack::cli::Options {
+        seekable: matches.get_flag("seekable"),
+        sign: matches.get_one::<String>("sign").cloned(),
+        reproducible: matches.get_flag("reproducible"),
+        recovery: matches.get_one::<u8>("recovery").copied(),
+        min_strength: matches.get_one::<u8>("min-strength").copied(),
+        generate_passphrase: matches.get_flag("generate-passphrase"),
+        ..Default::default()
+      };
+
+      i6_pack::cli::run_with_options("pack", target, encrypt, &options)?;
+    }
   }
 
   if let Some(matches) = matches.subcommand_matches(unpack_id) {
     let target = matches.get_one::<String>("target").unwrap();
     let encrypt = matches.get_flag("encrypt");
+    let options = i6_pack::cli::Options {
+      path: matches.get_one::<String>("path").cloned(),
+      verify_with: matches.get_one::<String>("verify-with").cloned(),
+      ..Default::default()
+    };
 
-    i6_pack::cli::run("unpack", target, encrypt)?;
+    i6_pack::cli::run_with_options("unpack", target, encrypt, &options)?;
   }
 
   Ok(())